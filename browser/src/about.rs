@@ -0,0 +1,220 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use wbe_core::escape_html;
+use wbe_http::{cache::CacheEntry, cookie::Cookie, CACHE, COOKIES};
+
+/// the stages of the last page load, for about:memory and about:timing.
+static STAGES: Mutex<Vec<Stage>> = Mutex::new(Vec::new());
+
+/// one step of the pipeline, which may take several ticks when streaming.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    pub name: &'static str,
+    pub ticks: usize,
+    pub duration: Duration,
+    /// memory used by the document at the end of the stage.
+    pub memory_usage: usize,
+}
+
+/// the page for an about: url, or None if `location` is not one.
+pub fn about_page(location: &str) -> Option<String> {
+    let name = about_name(location)?;
+
+    Some(match &*name.to_ascii_lowercase() {
+        "blank" => String::new(),
+        "memory" => memory_page(&STAGES.lock().unwrap()),
+        "timing" => timing_page(&STAGES.lock().unwrap()),
+        "cache" => cache_page(&CACHE.entries(), SystemTime::now()),
+        "cookies" => cookies_page(&COOKIES.cookies(), SystemTime::now()),
+        _ => format!(
+            "<h1>[unknown about page]</h1><p>{}<p>try about:blank, about:memory, about:timing, about:cache, or about:cookies",
+            escape_html(location)
+        ),
+    })
+}
+
+/// the name of the page in an about: url, like `blank` in `about:blank#top`.
+fn about_name(location: &str) -> Option<&str> {
+    let scheme = location.get(..6)?;
+    if !scheme.eq_ignore_ascii_case("about:") {
+        return None;
+    }
+
+    location[6..].split(['?', '#']).next()
+}
+
+/// records a tick of the pipeline that returned a document with the given
+/// status and location. `first` is true for the first tick of a page load.
+pub(crate) fn record_tick(
+    status: &'static str,
+    location: Option<&str>,
+    first: bool,
+    duration: Duration,
+    memory_usage: usize,
+) {
+    // about pages would otherwise replace the page load they’re about
+    if location.and_then(about_name).is_some() {
+        return;
+    }
+
+    let name = match status {
+        "Streaming" => "load+parse",
+        "Loaded" => "load",
+        "Parsed" => "parse",
+        "Styled" => "style",
+        "LaidOut" => "layout",
+        other => other,
+    };
+    let mut stages = STAGES.lock().unwrap();
+    if first {
+        stages.clear();
+    }
+    match stages.last_mut() {
+        Some(stage) if stage.name == name => {
+            stage.ticks += 1;
+            stage.duration += duration;
+            stage.memory_usage = memory_usage;
+        }
+        _ => stages.push(Stage {
+            name,
+            ticks: 1,
+            duration,
+            memory_usage,
+        }),
+    }
+}
+
+fn memory_page(stages: &[Stage]) -> String {
+    let mut result = "<h1>memory</h1>".to_owned();
+    if stages.is_empty() {
+        result += "<p>no page loaded yet";
+    }
+    for stage in stages {
+        result += &format!(
+            "<p><b>{}</b>: {} KiB",
+            stage.name,
+            stage.memory_usage / 1024
+        );
+    }
+
+    result
+}
+
+fn timing_page(stages: &[Stage]) -> String {
+    let mut result = "<h1>timing</h1>".to_owned();
+    if stages.is_empty() {
+        result += "<p>no page loaded yet";
+    }
+    for stage in stages {
+        result += &format!(
+            "<p><b>{}</b>: {:.1?} in {} ticks",
+            stage.name, stage.duration, stage.ticks
+        );
+    }
+    let total = stages.iter().map(|x| x.duration).sum::<Duration>();
+    result += &format!("<p><b>total</b>: {:.1?}", total);
+
+    result
+}
+
+fn cache_page(entries: &BTreeMap<String, CacheEntry>, now: SystemTime) -> String {
+    let mut result = format!("<h1>cache</h1><p>{} entries", entries.len());
+    for (url, entry) in entries {
+        let freshness = if entry.is_fresh(now) {
+            "fresh"
+        } else {
+            "stale"
+        };
+        result += &format!(
+            "<p><b>{}</b><br>http {}, {} bytes, {} s old, {}",
+            escape_html(url),
+            entry.status,
+            entry.body.len(),
+            entry.current_age(now).as_secs(),
+            freshness,
+        );
+    }
+
+    result
+}
+
+fn cookies_page(cookies: &[Cookie], now: SystemTime) -> String {
+    let mut result = format!("<h1>cookies</h1><p>{} cookies", cookies.len());
+    for cookie in cookies {
+        let expiry = match cookie.expiry {
+            Some(expiry) => format!(
+                "expires in {} s",
+                expiry.duration_since(now).unwrap_or_default().as_secs()
+            ),
+            None => "session".to_owned(),
+        };
+        let flags = [
+            (cookie.host_only, "host-only"),
+            (cookie.secure, "secure"),
+            (cookie.http_only, "httponly"),
+        ];
+        result += &format!(
+            "<p><b>{}</b>={}<br>{}{}, {}, samesite={:?}",
+            escape_html(&cookie.name),
+            escape_html(&cookie.value),
+            escape_html(&cookie.domain),
+            escape_html(&cookie.path),
+            expiry,
+            cookie.same_site,
+        );
+        for (_, flag) in flags.iter().filter(|x| x.0) {
+            result += &format!(", {}", flag);
+        }
+    }
+
+    result
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_about_pages() {
+    use wbe_http::{cookie::SameSite, Headers};
+
+    assert_eq!(about_page("about:blank").as_deref(), Some(""));
+    assert_eq!(about_page("ABOUT:Blank#top").as_deref(), Some(""));
+    assert_eq!(about_page("http://example.org/"), None);
+    assert!(about_page("about:nothing").unwrap().starts_with("<h1>[unknown about page]</h1>"));
+
+    let stages = [
+        Stage { name: "load+parse", ticks: 3, duration: Duration::from_millis(30), memory_usage: 4096 },
+        Stage { name: "style", ticks: 1, duration: Duration::from_millis(5), memory_usage: 8192 },
+    ];
+    assert_eq!(memory_page(&stages), "<h1>memory</h1><p><b>load+parse</b>: 4 KiB<p><b>style</b>: 8 KiB");
+    assert_eq!(timing_page(&stages), "<h1>timing</h1><p><b>load+parse</b>: 30.0ms in 3 ticks<p><b>style</b>: 5.0ms in 1 ticks<p><b>total</b>: 35.0ms");
+    assert_eq!(timing_page(&[]), "<h1>timing</h1><p>no page loaded yet<p><b>total</b>: 0.0ns");
+
+    let now = SystemTime::now();
+    let entry = CacheEntry {
+        status: 200,
+        headers: Headers::from([("cache-control", "max-age=60")]),
+        body: b"hello".to_vec(),
+        request_time: now - Duration::from_secs(10),
+        response_time: now - Duration::from_secs(10),
+    };
+    let entries = BTreeMap::from([("http://example.org/?a&b".to_owned(), entry)]);
+    assert_eq!(cache_page(&entries, now), "<h1>cache</h1><p>1 entries<p><b>http://example.org/?a&amp;b</b><br>http 200, 5 bytes, 10 s old, fresh");
+
+    let cookie = Cookie {
+        name: "id".to_owned(),
+        value: "<1>".to_owned(),
+        domain: "example.org".to_owned(),
+        path: "/".to_owned(),
+        expiry: Some(now + Duration::from_secs(60)),
+        host_only: true,
+        secure: false,
+        http_only: true,
+        same_site: SameSite::Lax,
+        creation_time: now,
+        last_access_time: now,
+    };
+    assert_eq!(cookies_page(&[cookie], now), "<h1>cookies</h1><p>1 cookies<p><b>id</b>=&lt;1&gt;<br>example.org/, expires in 60 s, samesite=Lax, host-only, httponly");
+}
@@ -1,28 +1,92 @@
+use std::collections::BTreeMap;
+use std::io::Read;
 use std::mem::{size_of, size_of_val};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
-use std::{fmt::Debug, mem::swap, str};
+use std::{
+    fmt::Debug,
+    mem::{swap, take},
+};
 
 use backtrace::Backtrace;
-use egui::{Align2, Color32, Ui, Vec2};
+use egui::{
+    Align2, Button, Checkbox, Color32, ComboBox, Key, RadioButton, Rect, TextEdit, Ui, Vec2,
+};
+use encoding_rs::{Encoding, UTF_8};
 use eyre::bail;
 use owning_ref::{RwLockReadGuardRef, RwLockWriteGuardRefMut};
 use tracing::{debug, error, info, instrument, warn};
 
-use wbe_core::dump_backtrace;
-use wbe_dom::{Node, NodeData, OwnedNode};
-use wbe_html_parser::parse_html;
-use wbe_http::request;
+use crate::about::{about_page, record_tick};
+use crate::form::submission;
+use crate::view_source::{view_source, view_source_url};
+use wbe_core::{dump_backtrace, escape_html, CancellationToken, Cancelled};
+use wbe_dom::{
+    form::{self, ControlType},
+    Node, NodeData, OwnedNode,
+};
+use wbe_html_parser::{parse_html, HtmlDecoder, HtmlParser};
+use wbe_http::{
+    Body, Destination, Fetcher, Headers, HttpError, MimeType, Request, Response, StreamingResponse,
+    Url,
+};
 use wbe_layout::Paint;
 use wbe_layout::{viewport::ViewportInfo, Layout, OwnedLayout};
-use wbe_style::{parse_css_file, resolve_styles};
+use wbe_style::{decode_css_file, parse_css_file, resolve_styles};
+
+/// how much of the response body we read in each step while streaming.
+const CHUNK_SIZE: usize = 16384;
 
 #[derive(Default, Clone)]
 pub struct Document(Arc<RwLock<OwnedDocument>>);
 
+/// a response body being parsed as it arrives. clones share the same
+/// stream, since the body can only be read once.
+#[derive(Clone)]
+pub struct Stream(Arc<Mutex<OwnedStream>>);
+
+struct OwnedStream {
+    body: Body,
+    decoder: HtmlDecoder,
+    parser: HtmlParser,
+    received: usize,
+    /// text of the external stylesheets fetched for previews, by href.
+    stylesheets: BTreeMap<String, String>,
+}
+
 pub type DocumentRead<'n, T> = RwLockReadGuardRef<'n, OwnedDocument, T>;
 pub type DocumentWrite<'n, T> = RwLockWriteGuardRefMut<'n, OwnedDocument, T>;
 
+impl Stream {
+    fn new(body: Body, transport_charset: Option<&str>) -> Self {
+        Self(Arc::new(Mutex::new(OwnedStream {
+            body,
+            decoder: HtmlDecoder::new(transport_charset),
+            parser: HtmlParser::new(),
+            received: 0,
+            stylesheets: BTreeMap::new(),
+        })))
+    }
+
+    /// how many bytes of the body have arrived so far.
+    pub fn received(&self) -> usize {
+        self.0.lock().unwrap().received
+    }
+
+    /// the document so far.
+    pub fn dom(&self) -> Node {
+        self.0.lock().unwrap().parser.document()
+    }
+}
+
+impl Debug for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stream")
+            .field("received", &self.received())
+            .finish_non_exhaustive()
+    }
+}
+
 impl Document {
     pub fn wrap(inner: OwnedDocument) -> Self {
         Self(Arc::new(RwLock::new(inner)))
@@ -50,23 +114,39 @@ pub enum OwnedDocument {
     Navigated {
         location: String,
     },
+    /// navigated by submitting a form, which needs more than a url.
+    Submitted {
+        location: String,
+        request: Request,
+    },
+    /// the response head has arrived, and we’re parsing the body as the
+    /// rest of it arrives. the response body so far is in `response_body`.
+    Streaming {
+        location: String,
+        response_body: String,
+        stream: Stream,
+    },
     Loaded {
         location: String,
         response_body: String,
+        encoding: &'static Encoding,
     },
     Parsed {
         location: String,
         response_body: String,
+        encoding: &'static Encoding,
         dom: Node,
     },
     Styled {
         location: String,
         response_body: String,
+        encoding: &'static Encoding,
         dom: Node,
     },
     LaidOut {
         location: String,
         response_body: String,
+        encoding: &'static Encoding,
         dom: Node,
         layout: Layout,
         viewport: ViewportInfo,
@@ -86,11 +166,13 @@ impl OwnedDocument {
             OwnedDocument::LaidOut {
                 location,
                 response_body,
+                encoding,
                 dom,
                 ..
             } => OwnedDocument::Styled {
                 location,
                 response_body,
+                encoding,
                 dom,
             },
             other => other,
@@ -101,6 +183,8 @@ impl OwnedDocument {
         match self {
             OwnedDocument::None => "None",
             OwnedDocument::Navigated { .. } => "Navigated",
+            OwnedDocument::Submitted { .. } => "Submitted",
+            OwnedDocument::Streaming { .. } => "Streaming",
             OwnedDocument::Loaded { .. } => "Loaded",
             OwnedDocument::Parsed { .. } => "Parsed",
             OwnedDocument::Styled { .. } => "Styled",
@@ -128,35 +212,239 @@ impl OwnedDocument {
         result.max(Vec2::ZERO)
     }
 
-    #[instrument]
-    fn load(location: String) -> eyre::Result<OwnedDocument> {
-        let body = match wbe_http::request(&location, None) {
-            Ok((200 | 204, _headers, body)) => body,
-            Ok((status, _headers, _body)) => format!("<h1>[http {}]</h1>", status).into_bytes(),
-            Err(error) => format!("<h1>[network error]</h1>{}", error).into_bytes(),
+    pub fn location(&self) -> Option<&str> {
+        match self {
+            OwnedDocument::None => None,
+            OwnedDocument::Navigated { location }
+            | OwnedDocument::Submitted { location, .. }
+            | OwnedDocument::Streaming { location, .. }
+            | OwnedDocument::Loaded { location, .. }
+            | OwnedDocument::Parsed { location, .. }
+            | OwnedDocument::Styled { location, .. }
+            | OwnedDocument::LaidOut { location, .. } => Some(location),
+        }
+    }
+
+    #[instrument(skip(request, fetcher, cancellation))]
+    fn load(
+        location: String,
+        request: Result<Request, HttpError>,
+        fetcher: &dyn Fetcher,
+        cancellation: &CancellationToken,
+    ) -> eyre::Result<OwnedDocument> {
+        // location becomes the final url, so that relative urls are resolved
+        // against the document we actually got after any redirects
+        let response =
+            request.and_then(|x| fetcher.fetch_streaming(&x.cancellation(cancellation.clone())));
+        let (location, response_body) = match response {
+            Ok(StreamingResponse {
+                url,
+                status: 200 | 204,
+                headers,
+                body,
+            }) => {
+                return Ok(OwnedDocument::Streaming {
+                    location: url.to_string(),
+                    response_body: String::new(),
+                    stream: Stream::new(body, charset(&headers).as_deref()),
+                });
+            }
+            Ok(StreamingResponse { url, status, .. }) => {
+                (url.to_string(), format!("<h1>[http {}]</h1>", status))
+            }
+            // nobody wants this document anymore, not even an error page
+            Err(HttpError::Cancelled) => return Err(Cancelled.into()),
+            Err(error) => {
+                warn!(%error, "request failed");
+                (location, error_page(&error))
+            }
+        };
+
+        Ok(OwnedDocument::Loaded {
+            location,
+            response_body,
+            encoding: UTF_8,
+        })
+    }
+
+    /// loads the resource at `url` and shows its source as a document,
+    /// without styling or running it.
+    #[instrument(skip(fetcher, cancellation))]
+    fn load_source(
+        location: String,
+        url: &str,
+        fetcher: &dyn Fetcher,
+        cancellation: &CancellationToken,
+    ) -> eyre::Result<OwnedDocument> {
+        let request = Url::parse(url, None)
+            .map(|x| Request::get(x).cancellation(cancellation.clone()))
+            .map_err(HttpError::from);
+
+        // show the source of error responses too, since that’s often the point
+        let (location, response_body) = match request.and_then(|x| fetcher.fetch(&x)) {
+            Ok(Response {
+                url, headers, body, ..
+            }) => {
+                let mut decoder = HtmlDecoder::new(charset(&headers).as_deref());
+                let response_body = decoder.push(&body, true);
+                let dom = view_source(&response_body);
+
+                return Ok(OwnedDocument::Parsed {
+                    location: format!("view-source:{}", url),
+                    response_body,
+                    encoding: decoder.encoding().unwrap_or(UTF_8),
+                    dom,
+                });
+            }
+            Err(HttpError::Cancelled) => return Err(Cancelled.into()),
+            Err(error) => {
+                warn!(%error, "request failed");
+                (location, error_page(&error))
+            }
         };
 
         Ok(OwnedDocument::Loaded {
             location,
-            // TODO: hard-coding utf-8 is not correct in practice
-            response_body: str::from_utf8(&body)?.to_owned(),
+            response_body,
+            encoding: UTF_8,
         })
     }
 
-    #[instrument(skip(response_body))]
-    fn parse(location: String, response_body: String) -> eyre::Result<OwnedDocument> {
-        let dom = parse_html(&response_body)?;
+    /// reads and parses the next piece of the response body, returning the
+    /// document so far, or the whole document once the body has ended.
+    #[instrument(skip(response_body, stream, cancellation))]
+    fn receive(
+        location: String,
+        mut response_body: String,
+        stream: Stream,
+        cancellation: &CancellationToken,
+    ) -> eyre::Result<OwnedDocument> {
+        let mut guard = stream.0.lock().unwrap();
+        let OwnedStream {
+            body,
+            decoder,
+            parser,
+            received,
+            ..
+        } = &mut *guard;
+        let mut buf = vec![0; CHUNK_SIZE];
+        let len = match body.read(&mut buf).map_err(HttpError::from) {
+            Ok(len) => len,
+            Err(HttpError::Cancelled) => return Err(Cancelled.into()),
+            // show as much of the document as we got
+            Err(error) => {
+                warn!(%error, "response body failed");
+                0
+            }
+        };
+        *received += len;
+        let text = decoder.push(&buf[..len], len == 0);
+        parser.push(&text, cancellation)?;
+        response_body += &text;
+        if len > 0 {
+            drop(guard);
+            return Ok(OwnedDocument::Streaming {
+                location,
+                response_body,
+                stream,
+            });
+        }
+
+        let encoding = decoder.encoding().unwrap_or(UTF_8);
+        let parser = take(parser);
+        let dom = parser.finish(cancellation)?;
         debug!(%dom);
 
         Ok(OwnedDocument::Parsed {
             location,
             response_body,
+            encoding,
             dom,
         })
     }
 
-    #[instrument(skip(location, response_body, dom))]
-    fn style(location: String, response_body: String, dom: Node) -> eyre::Result<OwnedDocument> {
+    /// styles and lays out the document so far, so it can be painted while
+    /// the rest of the response body arrives.
+    #[instrument(skip(self, viewport, fetcher, cancellation))]
+    pub fn preview(
+        &self,
+        viewport: ViewportInfo,
+        fetcher: &dyn Fetcher,
+        cancellation: &CancellationToken,
+    ) -> eyre::Result<OwnedDocument> {
+        let OwnedDocument::Streaming {
+            location,
+            response_body,
+            stream,
+        } = self
+        else {
+            bail!("can only preview a document that’s streaming")
+        };
+        let (dom, encoding, mut stylesheets) = {
+            let mut stream = stream.0.lock().unwrap();
+            let encoding = stream.decoder.encoding().unwrap_or(UTF_8);
+            let stylesheets = take(&mut stream.stylesheets);
+            (stream.parser.document(), encoding, stylesheets)
+        };
+        let result = Self::style(
+            location.clone(),
+            response_body.clone(),
+            encoding,
+            dom,
+            fetcher,
+            &mut stylesheets,
+            cancellation,
+        );
+        stream.0.lock().unwrap().stylesheets = stylesheets;
+
+        match result? {
+            OwnedDocument::Styled {
+                location,
+                response_body,
+                encoding,
+                dom,
+            } => Self::layout(
+                viewport,
+                location,
+                response_body,
+                encoding,
+                dom,
+                cancellation,
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[instrument(skip(response_body, cancellation))]
+    fn parse(
+        location: String,
+        response_body: String,
+        encoding: &'static Encoding,
+        cancellation: &CancellationToken,
+    ) -> eyre::Result<OwnedDocument> {
+        let dom = parse_html(&response_body, cancellation)?;
+        debug!(%dom);
+
+        Ok(OwnedDocument::Parsed {
+            location,
+            response_body,
+            encoding,
+            dom,
+        })
+    }
+
+    /// styles the document, reusing any external stylesheets whose text is
+    /// already in `stylesheets`, and adding any others.
+    #[instrument(skip(location, response_body, dom, fetcher, stylesheets, cancellation))]
+    fn style(
+        location: String,
+        response_body: String,
+        encoding: &'static Encoding,
+        dom: Node,
+        fetcher: &dyn Fetcher,
+        stylesheets: &mut BTreeMap<String, String>,
+        cancellation: &CancellationToken,
+    ) -> eyre::Result<OwnedDocument> {
         // start with ua styles
         let mut css_rules = parse_css_file(include_str!("html.css"))?;
 
@@ -173,21 +461,49 @@ impl OwnedDocument {
                     .is_some()
         }) {
             if let Some(href) = node.attr("href") {
-                fn request_link(href: &str, base: &str) -> eyre::Result<String> {
-                    let body = match request(href, Some(base)) {
-                        Ok((200, _headers, body)) => body,
-                        Ok((status, _headers, _body)) => bail!("http {}: {}", status, href),
-                        Err(error) => return Err(error),
+                fn request_link(
+                    href: &str,
+                    base: &str,
+                    encoding: &'static Encoding,
+                    fetcher: &dyn Fetcher,
+                    cancellation: &CancellationToken,
+                ) -> eyre::Result<String> {
+                    let base = Url::parse(base, None)?;
+                    let request = Request::get(Url::parse(href, Some(&base))?)
+                        .destination(Destination::Style)
+                        .referrer(&base)
+                        .cancellation(cancellation.clone());
+                    let (headers, body) = match fetcher.fetch(&request) {
+                        Ok(Response {
+                            status: 200,
+                            headers,
+                            body,
+                            ..
+                        }) => (headers, body),
+                        Ok(Response { status, .. }) => bail!("http {}: {}", status, href),
+                        Err(error) => return Err(error.into()),
                     };
 
-                    // TODO: hard-coding utf-8 is not correct in practice
-                    Ok(str::from_utf8(&body)?.to_owned())
+                    Ok(decode_css_file(
+                        &body,
+                        charset(&headers).as_deref(),
+                        encoding,
+                    ))
                 }
 
-                match request_link(&href, &location) {
-                    Ok(text) => css_rules.append(&mut parse_css_file(&text)?),
-                    Err(error) => error!("stylesheet request failed: {}: {}", *href, error),
+                if !stylesheets.contains_key(&*href) {
+                    let text = match request_link(&href, &location, encoding, fetcher, cancellation)
+                    {
+                        Ok(text) => text,
+                        Err(error) => {
+                            cancellation.check()?;
+                            error!("stylesheet request failed: {}: {}", *href, error);
+                            String::new()
+                        }
+                    };
+                    stylesheets.insert((*href).clone(), text);
                 }
+                css_rules.append(&mut parse_css_file(&stylesheets[&*href])?);
             }
         }
 
@@ -202,33 +518,47 @@ impl OwnedDocument {
         Ok(OwnedDocument::Styled {
             location,
             response_body,
+            encoding,
             dom,
         })
     }
 
-    #[instrument(skip(viewport, location, response_body, dom))]
+    #[instrument(skip(viewport, location, response_body, dom, cancellation))]
     fn layout(
         viewport: ViewportInfo,
         location: String,
         response_body: String,
+        encoding: &'static Encoding,
         dom: Node,
+        cancellation: &CancellationToken,
     ) -> eyre::Result<OwnedDocument> {
         let layout = Layout::with_node(dom.clone(), viewport.rect.width());
-        layout.layout(&viewport)?;
+        layout.layout(&viewport, cancellation)?;
 
         Ok(OwnedDocument::LaidOut {
             location,
             response_body,
+            encoding,
             dom,
             layout,
             viewport,
         })
     }
 
+    /// paints the layout, returning the request for a form submission if
+    /// the user submitted one.
     #[instrument(skip(ui, layout))]
-    pub fn paint(ui: &Ui, layout: &Layout, viewport: &ViewportInfo, scroll: Vec2) {
-        let painter = ui.painter();
-        for paint in &*layout.display_list() {
+    pub fn paint(
+        ui: &mut Ui,
+        location: &str,
+        encoding: &'static Encoding,
+        layout: &Layout,
+        viewport: &ViewportInfo,
+        scroll: Vec2,
+    ) -> Option<Request> {
+        let mut result = None;
+        let painter = ui.painter().clone();
+        for (i, paint) in layout.display_list().iter().enumerate() {
             let rect = paint.rect().translate(-scroll);
             if rect.intersects(viewport.rect) {
                 match paint {
@@ -242,36 +572,187 @@ impl OwnedDocument {
                     Paint::Fill(_, color) => {
                         painter.rect(rect, 0.0, *color, (0.0, Color32::TRANSPARENT));
                     }
+                    Paint::Control(_, node) => {
+                        // ids from the display list stay the same between frames
+                        let submitter = ui.push_id(i, |ui| Self::paint_control(ui, rect, node));
+                        if let Some((form, submitter)) = submitter.inner {
+                            match submission(&form, submitter.as_ref(), location, encoding) {
+                                Ok(request) => result = Some(request),
+                                Err(error) => error!(%error, "form submission failed"),
+                            }
+                        }
+                    }
                 }
             }
         }
+
+        result
     }
 
-    #[instrument(skip(self, viewport))]
-    pub fn tick(self, viewport: ViewportInfo) -> eyre::Result<OwnedDocument> {
+    /// draws a form control as an egui widget, which updates the dom as the
+    /// user interacts with it. returns the form and submitter (if any) when
+    /// the user submits a form.
+    fn paint_control(ui: &mut Ui, rect: Rect, node: &Node) -> Option<(Node, Option<Node>)> {
+        let r#type = ControlType::of(node)?;
+        ui.set_enabled(!form::is_disabled(node));
+        match r#type {
+            ControlType::Hidden => {}
+            ControlType::Text | ControlType::Password | ControlType::TextArea => {
+                let mut value = form::value(node);
+                let widget = match r#type {
+                    ControlType::TextArea => TextEdit::multiline(&mut value),
+                    _ => TextEdit::singleline(&mut value).password(r#type == ControlType::Password),
+                };
+                let response = ui.put(rect, widget);
+                if response.changed() {
+                    form::set_value(node, value);
+                }
+
+                // implicit submission, as if by the form’s default button
+                // https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#implicit-submission
+                if r#type != ControlType::TextArea
+                    && response.lost_focus()
+                    && ui.input(|i| i.key_pressed(Key::Enter))
+                {
+                    let form = form::form_owner(node)?;
+                    let submitter = form::default_button(&form);
+                    return Some((form, submitter));
+                }
+            }
+            ControlType::Checkbox => {
+                let mut checked = form::checked(node);
+                if ui.put(rect, Checkbox::new(&mut checked, "")).changed() {
+                    form::set_checked(node, checked);
+                }
+            }
+            ControlType::Radio => {
+                if ui
+                    .put(rect, RadioButton::new(form::checked(node), ""))
+                    .clicked()
+                {
+                    form::set_checked(node, true);
+                }
+            }
+            ControlType::Select => {
+                ui.allocate_ui_at_rect(rect, |ui| {
+                    ComboBox::from_id_source("select")
+                        .width(rect.width())
+                        .selected_text(form::label(node))
+                        .show_ui(ui, |ui| {
+                            let selected = form::selected_options(node);
+                            for option in form::options(node) {
+                                let label = form::option_label(&option);
+                                if ui
+                                    .selectable_label(selected.contains(&option), label)
+                                    .clicked()
+                                {
+                                    form::select_option(node, &option);
+                                }
+                            }
+                        });
+                });
+            }
+            // reset would need the default values, which we don’t keep
+            ControlType::Submit | ControlType::Image | ControlType::Reset | ControlType::Button => {
+                let clicked = ui.put(rect, Button::new(form::label(node))).clicked();
+                if clicked && r#type.is_submit_button() {
+                    return Some((form::form_owner(node)?, Some(node.clone())));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// advances the document one step through the pipeline. if the token
+    /// is cancelled, this fails with [`Cancelled`] as soon as it can.
+    #[instrument(skip(self, viewport, fetcher, cancellation))]
+    pub fn tick(
+        self,
+        viewport: ViewportInfo,
+        fetcher: &dyn Fetcher,
+        cancellation: &CancellationToken,
+    ) -> eyre::Result<OwnedDocument> {
+        cancellation.check()?;
         let start = Instant::now();
+        let first = matches!(
+            self,
+            OwnedDocument::Navigated { .. } | OwnedDocument::Submitted { .. }
+        );
         let result = match self {
             OwnedDocument::None => return Ok(self),
-            OwnedDocument::Navigated { location } => Self::load(location)?,
+            OwnedDocument::Navigated { location } => {
+                if let Some(url) = view_source_url(&location) {
+                    let url = url.to_owned();
+                    Self::load_source(location, &url, fetcher, cancellation)?
+                } else if let Some(response_body) = about_page(&location) {
+                    OwnedDocument::Loaded {
+                        location,
+                        response_body,
+                        encoding: UTF_8,
+                    }
+                } else {
+                    let request = Url::parse(&location, None)
+                        .map(|x| Request::get(x).destination(Destination::Document))
+                        .map_err(HttpError::from);
+                    Self::load(location, request, fetcher, cancellation)?
+                }
+            }
+            OwnedDocument::Submitted { location, request } => {
+                Self::load(location, Ok(request), fetcher, cancellation)?
+            }
+            OwnedDocument::Streaming {
+                location,
+                response_body,
+                stream,
+            } => Self::receive(location, response_body, stream, cancellation)?,
             OwnedDocument::Loaded {
                 location,
                 response_body,
-            } => Self::parse(location, response_body)?,
+                encoding,
+            } => Self::parse(location, response_body, encoding, cancellation)?,
             OwnedDocument::Parsed {
                 location,
                 response_body,
+                encoding,
+                dom,
+            } => Self::style(
+                location,
+                response_body,
+                encoding,
                 dom,
-            } => Self::style(location, response_body, dom)?,
+                fetcher,
+                // previews may have fetched these already, but the http
+                // cache will usually answer for them this time
+                &mut BTreeMap::new(),
+                cancellation,
+            )?,
             OwnedDocument::Styled {
                 location,
                 response_body,
+                encoding,
                 dom,
-            } => Self::layout(viewport, location, response_body, dom)?,
+            } => Self::layout(
+                viewport,
+                location,
+                response_body,
+                encoding,
+                dom,
+                cancellation,
+            )?,
             document @ OwnedDocument::LaidOut { .. } => document,
         };
 
-        let now = Instant::now();
-        info!(status = result.status(), duration = ?now.duration_since(start), memory_usage = result.memory_usage());
+        let duration = Instant::now().duration_since(start);
+        let memory_usage = result.memory_usage();
+        info!(status = result.status(), ?duration, memory_usage);
+        record_tick(
+            result.status(),
+            result.location(),
+            first,
+            duration,
+            memory_usage,
+        );
 
         Ok(result)
     }
@@ -331,22 +812,37 @@ impl OwnedDocument {
 
         match self {
             Self::None => size_of_val(self),
-            Self::Navigated { location } => size_of_val(&Self::None) + size_of_string(location),
+            Self::Navigated { location } | Self::Submitted { location, .. } => {
+                size_of_val(&Self::None) + size_of_string(location)
+            }
             Self::Loaded {
                 location,
                 response_body,
+                ..
             } => {
                 size_of_val(&Self::None) + size_of_string(location) + size_of_string(response_body)
             }
+            Self::Streaming {
+                location,
+                response_body,
+                stream,
+            } => {
+                size_of_val(&Self::None)
+                    + size_of_string(location)
+                    + size_of_string(response_body)
+                    + size_of_dom_tree(&stream.dom())
+            }
             Self::Parsed {
                 location,
                 response_body,
                 dom,
+                ..
             }
             | Self::Styled {
                 location,
                 response_body,
                 dom,
+                ..
             } => {
                 size_of_val(&Self::None)
                     + size_of_string(location)
@@ -358,7 +854,7 @@ impl OwnedDocument {
                 response_body,
                 dom,
                 layout,
-                viewport: _,
+                ..
             } => {
                 debug!(
                     dom_tree_size = size_of_dom_tree(dom),
@@ -373,3 +869,102 @@ impl OwnedDocument {
         }
     }
 }
+
+fn error_page(error: &HttpError) -> String {
+    let title = match error {
+        HttpError::InvalidUrl(_) => "invalid url",
+        HttpError::UnsupportedScheme(_) => "unsupported scheme",
+        HttpError::Dns(..) => "server not found",
+        HttpError::Connect(..) => "connection failed",
+        HttpError::Tls(_) => "secure connection failed",
+        HttpError::Proxy(_) => "proxy error",
+        HttpError::Timeout => "connection timed out",
+        HttpError::Cancelled => "cancelled",
+        HttpError::Io(_) => "network error",
+        HttpError::MalformedResponse(_) | HttpError::ContentDecoding(_) => "invalid response",
+        HttpError::TooManyRedirects(_) | HttpError::RedirectLoop(_) => "redirect loop",
+        HttpError::InvalidDataUrl(_) => "invalid data url",
+        HttpError::InvalidFileUrl(_) => "invalid file url",
+        HttpError::File(..) => "failed to read file",
+    };
+
+    format!("<h1>[{}]</h1><p>{}", title, escape_html(&error.to_string()))
+}
+
+/// the charset parameter of the content-type, if any.
+fn charset(headers: &Headers) -> Option<String> {
+    let content_type = MimeType::parse(headers.get("content-type")?)?;
+
+    content_type.parameter("charset").map(|x| x.to_owned())
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_memory_fetcher() -> eyre::Result<()> {
+    use egui::{pos2, vec2};
+    use wbe_http::MemoryFetcher;
+
+    let fetcher = MemoryFetcher::new()
+        .with_ok("http://example.org/index.html", "text/html; charset=windows-1252", b"<link rel=stylesheet href=style.css><link rel=stylesheet href=missing.css><p>caf\xE9</p>".to_vec())
+        .with_ok("http://example.org/style.css", "text/css", "p { color: lime; }")
+        .with("http://example.org/gone.html", 410, &[], "");
+    let viewport = ViewportInfo { rect: Rect::from_min_size(pos2(0.0, 0.0), vec2(800.0, 600.0)), scale: 1.0 };
+    let run = |location: &str| -> eyre::Result<OwnedDocument> {
+        let mut document = OwnedDocument::Navigated { location: location.to_owned() };
+        while !matches!(document, OwnedDocument::LaidOut { .. }) {
+            document = document.tick(viewport.clone(), &fetcher, &CancellationToken::new())?;
+        }
+        Ok(document)
+    };
+
+    let OwnedDocument::LaidOut { response_body, encoding, dom, .. } = run("http://example.org/index.html#top")? else { unreachable!() };
+    assert!(response_body.ends_with("<p>café</p>"));
+    assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+    let p = dom.descendants().find(|x| &*x.name() == "p").unwrap();
+    assert_eq!(p.data().style().color(), Color32::from_rgb(0, 255, 0));
+    let requests = fetcher.requests();
+    assert_eq!(requests[0].destination, Destination::Document);
+    assert_eq!((requests[1].url.href(), requests[1].destination), ("http://example.org/style.css".to_owned(), Destination::Style));
+    assert_eq!(requests[1].referrer.as_ref().map(|x| x.href()).as_deref(), Some("http://example.org/index.html#top"));
+
+    let OwnedDocument::LaidOut { response_body, .. } = run("http://example.org/gone.html")? else { unreachable!() };
+    assert_eq!(response_body, "<h1>[http 410]</h1>");
+    let OwnedDocument::LaidOut { response_body, .. } = run("gopher://example.org/")? else { unreachable!() };
+    assert!(response_body.starts_with("<h1>[unsupported scheme]</h1>"));
+    let OwnedDocument::LaidOut { dom, .. } = run("about:timing")? else { unreachable!() };
+    assert!(dom.text_content().starts_with("timingload: "));
+    let OwnedDocument::LaidOut { response_body, .. } = run("about:blank")? else { unreachable!() };
+    assert_eq!(response_body, "");
+
+    let OwnedDocument::LaidOut { location, response_body, .. } = run("view-source:http://example.org/gone.html")? else { unreachable!() };
+    assert_eq!((&*location, &*response_body), ("view-source:http://example.org/gone.html", ""));
+    let OwnedDocument::LaidOut { location, dom, .. } = run("VIEW-SOURCE:http://example.org/index.html")? else { unreachable!() };
+    assert_eq!(location, "view-source:http://example.org/index.html");
+    assert!(dom.text_content().contains("<p>café</p>"));
+    let tag = dom.descendants().find(|x| x.attr("class").filter(|x| &**x == "tag").is_some()).unwrap();
+    assert_eq!(tag.children()[0].data().style().color(), Color32::from_rgb(0x88, 0x12, 0x80));
+
+    let cancelled = CancellationToken::new();
+    cancelled.cancel();
+    let document = OwnedDocument::Navigated { location: "http://example.org/index.html".to_owned() };
+    assert!(document.tick(viewport.clone(), &fetcher, &cancelled).unwrap_err().is::<Cancelled>());
+    assert_eq!(fetcher.requests().len(), 7);
+
+    // documents longer than a chunk can be previewed before they’ve arrived
+    let fetcher = MemoryFetcher::new()
+        .with_ok("http://example.org/long.html", "text/html", format!("<p>one</p>{}<p>two</p>", " ".repeat(CHUNK_SIZE)));
+    let cancellation = CancellationToken::new();
+    let document = OwnedDocument::Navigated { location: "http://example.org/long.html".to_owned() };
+    let document = document.tick(viewport.clone(), &fetcher, &cancellation)?.tick(viewport.clone(), &fetcher, &cancellation)?;
+    assert_eq!(document.status(), "Streaming");
+    let OwnedDocument::LaidOut { dom, .. } = document.preview(viewport.clone(), &fetcher, &cancellation)? else { unreachable!() };
+    assert_eq!(dom.text_content(), "one");
+    let mut document = document;
+    while let OwnedDocument::Streaming { .. } = document {
+        document = document.tick(viewport.clone(), &fetcher, &cancellation)?;
+    }
+    let OwnedDocument::Parsed { dom, .. } = document else { unreachable!() };
+    assert_eq!(dom.descendants().filter(|x| &*x.name() == "p").count(), 2);
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use encoding_rs::Encoding;
+
+use wbe_dom::{form::entry_list, Node};
+use wbe_http::{
+    encode_multipart, encode_text_plain, encode_urlencoded, Destination, HttpError, Request, Url,
+};
+
+/// the request that submits the given form, as if by the given button, from
+/// a document at the given location, in the given encoding.
+/// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#form-submission-algorithm>
+pub fn submission(
+    form: &Node,
+    submitter: Option<&Node>,
+    location: &str,
+    encoding: &'static Encoding,
+) -> Result<Request, HttpError> {
+    // formaction and friends on the button win over action and friends on the form
+    let attr = |name: &str| {
+        submitter
+            .and_then(|x| x.attr(&format!("form{}", name)).map(|x| x.to_owned()))
+            .or_else(|| form.attr(name).map(|x| x.to_owned()))
+            .unwrap_or_default()
+    };
+    let base = Url::parse(location, None)?;
+    let mut action = match &*attr("action") {
+        "" => base.clone(),
+        action => Url::parse(action, Some(&base))?,
+    };
+    // utf-16 documents submit forms in utf-8
+    let encoding = encoding.output_encoding();
+    let entries = entry_list(form, submitter, encoding.name());
+
+    // unknown methods and enctypes fall back to the defaults
+    let request = match &*attr("method").to_ascii_lowercase() {
+        "post" => match &*attr("enctype").to_ascii_lowercase() {
+            "multipart/form-data" => {
+                let (content_type, body) = encode_multipart(&entries, encoding);
+                Request::post(action).body(&content_type, body)
+            }
+            "text/plain" => {
+                Request::post(action).body("text/plain", encode_text_plain(&entries, encoding))
+            }
+            _ => Request::post(action).body(
+                "application/x-www-form-urlencoded",
+                encode_urlencoded(&entries, encoding),
+            ),
+        },
+        _ => {
+            action.set_query(Some(encode_urlencoded(&entries, encoding)));
+            Request::get(action)
+        }
+    };
+
+    Ok(request.destination(Destination::Document).referrer(&base))
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_submission() -> eyre::Result<()> {
+    use egui::{pos2, vec2, Rect};
+    use wbe_core::CancellationToken;
+    use wbe_http::MemoryFetcher;
+    use wbe_layout::{Paint, ViewportInfo};
+
+    use crate::OwnedDocument;
+
+    let fetcher = MemoryFetcher::new()
+        .with_ok("http://example.org/form.html", "text/html; charset=windows-1252", r#"
+            <form action=search><input name=q value="a b"><select name=s><option>x<option selected>y</select><button>go</button></form>
+            <form action=post.html method=post enctype=multipart/form-data><textarea name=t>hi</textarea><input type=submit name=b value=post formenctype=text/plain></form>
+        "#)
+        .with_ok("http://example.org/search?q=a+b&s=y", "text/html", "found")
+        .with_ok("http://example.org/post.html", "text/html", "posted");
+    let viewport = ViewportInfo { rect: Rect::from_min_size(pos2(0.0, 0.0), vec2(800.0, 600.0)), scale: 1.0 };
+    let run = |mut document: OwnedDocument| -> eyre::Result<OwnedDocument> {
+        while !matches!(document, OwnedDocument::LaidOut { .. }) {
+            document = document.tick(viewport.clone(), &fetcher, &CancellationToken::new())?;
+        }
+        Ok(document)
+    };
+
+    let location = "http://example.org/form.html";
+    let OwnedDocument::LaidOut { layout, dom, encoding, .. } = run(OwnedDocument::Navigated { location: location.to_owned() })? else { unreachable!() };
+    assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+    let controls = layout.display_list().iter().filter_map(|x| match x { Paint::Control(_, node) => Some(node.clone()), _ => None }).collect::<Vec<_>>();
+    assert_eq!(controls.iter().map(|x| x.name().to_owned()).collect::<Vec<_>>(), ["input", "select", "button", "textarea", "input"]);
+    let forms = dom.descendants().filter(|x| &*x.name() == "form").collect::<Vec<_>>();
+
+    let request = submission(&forms[0], Some(&controls[2]), location, encoding)?;
+    assert_eq!((&*request.method, request.url.href()), ("GET", "http://example.org/search?q=a+b&s=y".to_owned()));
+    let OwnedDocument::LaidOut { response_body, location: found, .. } = run(OwnedDocument::Submitted { location: request.url.href(), request })? else { unreachable!() };
+    assert_eq!((&*response_body, &*found), ("found", "http://example.org/search?q=a+b&s=y"));
+
+    // forms are encoded like the document they’re in
+    wbe_dom::form::set_value(&controls[0], "café".to_owned());
+    let request = submission(&forms[0], Some(&controls[2]), location, encoding)?;
+    assert_eq!(request.url.href(), "http://example.org/search?q=caf%E9&s=y");
+
+    let request = submission(&forms[1], None, location, encoding)?;
+    let content_type = request.headers.get("content-type").unwrap().to_owned();
+    let boundary = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+    assert_eq!(String::from_utf8(request.body.clone())?, format!("--{0}\r\nContent-Disposition: form-data; name=\"t\"\r\n\r\nhi\r\n--{0}--\r\n", boundary));
+    let request = submission(&forms[1], Some(&controls[4]), location, encoding)?;
+    assert_eq!((&*request.method, request.headers.get("content-type"), &*request.body), ("POST", Some("text/plain"), &b"t=hi\r\nb=post\r\n"[..]));
+    assert_eq!(request.site_for_cookies().map(|x| x.href()), Some(location.to_owned()));
+    let OwnedDocument::LaidOut { response_body, .. } = run(OwnedDocument::Submitted { location: request.url.href(), request })? else { unreachable!() };
+    assert_eq!(response_body, "posted");
+    let requests = fetcher.requests();
+    assert_eq!((&*requests[requests.len() - 1].method, requests[requests.len() - 1].referrer.as_ref().map(|x| x.href())), ("POST", Some(location.to_owned())));
+
+    Ok(())
+}
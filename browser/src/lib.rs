@@ -1,6 +1,9 @@
+pub mod about;
 pub mod document;
+pub mod form;
+pub mod view_source;
 
-pub use crate::document::{Document, OwnedDocument};
+pub use crate::document::{Document, OwnedDocument, Stream};
 
 use std::sync::{Arc, RwLock};
 
@@ -8,6 +11,7 @@ use backtrace::Backtrace;
 use egui::Vec2;
 use owning_ref::{RwLockReadGuardRef, RwLockWriteGuardRefMut};
 use wbe_core::dump_backtrace;
+use wbe_http::{Fetcher, SocketFetcher};
 use wbe_layout::ViewportInfo;
 
 #[derive(Clone)]
@@ -19,6 +23,9 @@ pub type BrowserWrite<'n, T> = RwLockWriteGuardRefMut<'n, OwnedBrowser, T>;
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum RenderStatus {
     Load,
+    /// loading and parsing at the same time, with how many bytes of the
+    /// response body have arrived so far.
+    Stream(usize),
     Parse,
     Style,
     Layout,
@@ -65,6 +72,7 @@ pub struct OwnedBrowser {
     pub scroll: Vec2,
     pub status: RenderStatus,
     pub first_update: bool,
+    pub fetcher: Arc<dyn Fetcher>,
 }
 
 impl Default for OwnedBrowser {
@@ -77,6 +85,7 @@ impl Default for OwnedBrowser {
             scroll: Vec2::ZERO,
             status: RenderStatus::Done,
             first_update: true,
+            fetcher: Arc::new(SocketFetcher::default()),
         }
     }
 }
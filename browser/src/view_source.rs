@@ -0,0 +1,176 @@
+use wbe_dom::Node;
+use wbe_html_lexer::{html_attr, html_ident, html_space, html_token, is_html_space, HtmlToken};
+
+/// the kinds of html source that get their own colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Text,
+    Tag,
+    AttrName,
+    AttrValue,
+    Comment,
+    Doctype,
+}
+
+impl SourceKind {
+    fn class(self) -> &'static str {
+        match self {
+            SourceKind::Text => "text",
+            SourceKind::Tag => "tag",
+            SourceKind::AttrName => "attr-name",
+            SourceKind::AttrValue => "attr-value",
+            SourceKind::Comment => "comment",
+            SourceKind::Doctype => "doctype",
+        }
+    }
+}
+
+/// the url inside a `view-source:` url, if `location` is one.
+pub fn view_source_url(location: &str) -> Option<&str> {
+    let scheme = location.get(..12)?;
+
+    scheme
+        .eq_ignore_ascii_case("view-source:")
+        .then(|| &location[12..])
+}
+
+/// splits html source into runs of each kind, using the same tokens as the
+/// html parser. the runs concatenate to the original source.
+pub fn highlight_html(mut input: &str) -> Vec<(SourceKind, &str)> {
+    let mut result = vec![];
+    while !input.is_empty() {
+        let Ok((rest, token)) = html_token(input)
+            else { push(&mut result, SourceKind::Text, input); break };
+        let source = &input[..input.len() - rest.len()];
+        match token {
+            HtmlToken::Comment(_) => push(&mut result, SourceKind::Comment, source),
+            HtmlToken::Doctype(_) => push(&mut result, SourceKind::Doctype, source),
+            HtmlToken::Text(_) => push(&mut result, SourceKind::Text, source),
+            HtmlToken::Tag(..) => highlight_tag(source, &mut result),
+            HtmlToken::Script(_, text) => {
+                highlight_raw_text(source, text, "</script>", &mut result)
+            }
+            HtmlToken::Style(_, text) => highlight_raw_text(source, text, "</style>", &mut result),
+        }
+        input = rest;
+    }
+
+    result
+}
+
+/// a document that shows html source with line numbers and syntax highlighting.
+pub fn view_source(source: &str) -> Node {
+    let mut lines = vec![vec![]];
+    for (kind, text) in highlight_html(source) {
+        for (i, text) in text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(vec![]);
+            }
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            if !text.is_empty() {
+                lines.last_mut().unwrap().push(span(kind.class(), text));
+            }
+        }
+    }
+
+    let width = lines.len().to_string().len();
+    let body = Node::element("body".to_owned(), vec![]);
+    for (i, spans) in lines.into_iter().enumerate() {
+        let number = format!("{:>width$}  ", i + 1, width = width);
+        let line = Node::element("div".to_owned(), vec![]);
+        line.append(&[span("line-number", &number)]);
+        line.append(&spans);
+        body.append(&[line]);
+    }
+
+    let style = Node::element("style".to_owned(), vec![]);
+    style.append(&[Node::text(include_str!("view-source.css").to_owned())]);
+    let head = Node::element("head".to_owned(), vec![]).append(&[style]);
+    let html = Node::element("html".to_owned(), vec![]).append(&[head, body]);
+
+    Node::document().append(&[html])
+}
+
+fn push<'i>(result: &mut Vec<(SourceKind, &'i str)>, kind: SourceKind, text: &'i str) {
+    if !text.is_empty() {
+        result.push((kind, text));
+    }
+}
+
+/// splits a start or end tag into the tag itself and its attributes.
+fn highlight_tag<'i>(source: &'i str, result: &mut Vec<(SourceKind, &'i str)>) {
+    let start = if source.starts_with("</") { 2 } else { 1 };
+    let name = html_ident(&source[start..]).map_or(0, |(_, x)| x.len());
+    let (open, mut input) = source.split_at(start + name);
+    push(result, SourceKind::Tag, open);
+
+    while let Ok((rest, _)) = html_attr(input) {
+        let attr = &input[..input.len() - rest.len()];
+        let (attr, space) = html_space(attr).expect("attr starts with space");
+        let (value, name) = html_ident(attr).expect("attr has name");
+        push(result, SourceKind::Text, space);
+        push(result, SourceKind::AttrName, name);
+
+        // the equals sign and the spaces around it are not part of the value
+        let after = value.trim_start_matches(is_html_space);
+        let after = after.strip_prefix('=').unwrap_or(after);
+        let after = after.trim_start_matches(is_html_space);
+        let (equals, value) = value.split_at(value.len() - after.len());
+        push(result, SourceKind::Text, equals);
+        push(result, SourceKind::AttrValue, value);
+        input = rest;
+    }
+
+    push(result, SourceKind::Tag, input);
+}
+
+/// splits a script or style element into its start tag, text, and end tag.
+fn highlight_raw_text<'i>(
+    source: &'i str,
+    text: &'i str,
+    end_tag: &str,
+    result: &mut Vec<(SourceKind, &'i str)>,
+) {
+    let (source, end_tag) = source.split_at(source.len() - end_tag.len());
+    let (start_tag, _) = source.split_at(source.len() - text.len());
+    highlight_tag(start_tag, result);
+    push(result, SourceKind::Text, text);
+    highlight_tag(end_tag, result);
+}
+
+/// keeps all of the whitespace in a line of source, which would otherwise
+/// collapse, since we can’t do `white-space: pre` yet.
+fn span(class: &str, text: &str) -> Node {
+    let text = text.replace('\t', "    ").replace(' ', "\u{A0}");
+
+    Node::element(
+        "span".to_owned(),
+        vec![("class".to_owned(), class.to_owned())],
+    )
+    .append(&[Node::text(text)])
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_view_source() {
+    use SourceKind::*;
+
+    let source = "<!doctype html>\n<p class=\"a\" id = b hidden>x &amp; y</p><!-- c -->\r\n<script type=module>if (a<b) {}</script>";
+    let runs = highlight_html(source);
+    assert_eq!(runs.iter().map(|x| x.1).collect::<String>(), source);
+    assert_eq!(runs, [
+        (Doctype, "<!doctype html>"), (Text, "\n"),
+        (Tag, "<p"), (Text, " "), (AttrName, "class"), (Text, "="), (AttrValue, "\"a\""),
+        (Text, " "), (AttrName, "id"), (Text, " = "), (AttrValue, "b"),
+        (Text, " "), (AttrName, "hidden"), (Tag, ">"),
+        (Text, "x "), (Text, "&amp;"), (Text, " y"), (Tag, "</p"), (Tag, ">"), (Comment, "<!-- c -->"), (Text, "\r\n"),
+        (Tag, "<script"), (Text, " "), (AttrName, "type"), (Text, "="), (AttrValue, "module"), (Tag, ">"),
+        (Text, "if (a<b) {}"), (Tag, "</script"), (Tag, ">"),
+    ]);
+
+    let dom = view_source(source);
+    let lines = dom.descendants().filter(|x| &*x.name() == "div").collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[1].text_content(), "2\u{A0}\u{A0}<p\u{A0}class=\"a\"\u{A0}id\u{A0}=\u{A0}b\u{A0}hidden>x\u{A0}&amp;\u{A0}y</p><!--\u{A0}c\u{A0}-->");
+    assert_eq!(&*lines[2].children()[1].attr("class").unwrap(), "tag");
+}
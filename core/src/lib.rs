@@ -1,3 +1,11 @@
+pub mod cancel;
+pub mod span;
+
+pub use crate::{
+    cancel::{CancellationToken, Cancelled},
+    span::{SourcePosition, SourceSpan},
+};
+
 use std::{
     io::{Read, Write},
     net::TcpStream,
@@ -98,6 +106,22 @@ pub fn dump(bytes: &[u8]) -> String {
         .collect::<String>()
 }
 
+pub fn escape_html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result += "&amp;",
+            '<' => result += "&lt;",
+            '>' => result += "&gt;",
+            '"' => result += "&quot;",
+            '\'' => result += "&#39;",
+            other => result.push(other),
+        }
+    }
+
+    result
+}
+
 pub fn parse<'i>(input: &'i str, pattern: &str) -> Option<Captures<'i>> {
     // +s (dot matches newlines), but no -u by default. -u affects ascii
     // character classes (\w\d\s), but it also makes dot (.) unusable.
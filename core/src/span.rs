@@ -0,0 +1,84 @@
+use std::fmt::Display;
+
+/// a place in some source text, as a byte offset and a line and column that
+/// both count from 1. columns count characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourcePosition {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// the part of some source text that something came from, from `start` up
+/// to but not including `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    pub start: SourcePosition,
+    pub end: SourcePosition,
+}
+
+impl SourcePosition {
+    /// the position before any text.
+    pub fn start() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Default for SourcePosition {
+    fn default() -> Self {
+        Self::start()
+    }
+}
+
+impl SourceSpan {
+    pub fn new(start: SourcePosition, end: SourcePosition) -> Self {
+        Self { start, end }
+    }
+
+    /// the empty span at the given position.
+    pub fn at(position: SourcePosition) -> Self {
+        Self::new(position, position)
+    }
+
+    /// the smallest span that covers both spans.
+    pub fn to(self, other: SourceSpan) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    pub fn len(&self) -> usize {
+        self.end.offset - self.start.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Display for SourcePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl Display for SourceSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_source_span() {
+    let position = |offset, line, column| SourcePosition { offset, line, column };
+    let a = SourceSpan::new(position(3, 1, 4), position(5, 2, 1));
+    let b = SourceSpan::new(position(9, 2, 5), position(12, 2, 8));
+    assert_eq!(a.to(b), SourceSpan::new(position(3, 1, 4), position(12, 2, 8)));
+    assert_eq!(b.to(a), a.to(b));
+    assert_eq!(a.len(), 2);
+    assert!(SourceSpan::at(position(5, 2, 1)).is_empty());
+    assert_eq!(a.to_string(), "1:4-2:1");
+}
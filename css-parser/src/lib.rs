@@ -189,6 +189,11 @@ pub fn stag(x: &'static str) -> impl FnMut(&str) -> IResult<&str, &str> {
     move |input| css_big_token(move |i| tag(x)(i))(input)
 }
 
+/// we decode before parsing, so all that’s left to do with @charset is skip it
+pub fn css_charset_rule(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((tag("@charset"), take_until(";"), tag(";"))))(input)
+}
+
 fn rule_with_bad_selector(input: &str) -> IResult<&str, &str> {
     recognize(tuple((take_until("}"), tag("}"))))(input)
 }
@@ -199,6 +204,10 @@ pub fn css_file(input: &str) -> IResult<&str, RuleList> {
     let mut input = input;
     let mut result = vec![];
 
+    if let Ok((rest, _)) = css_big_token(css_charset_rule)(input) {
+        input = rest;
+    }
+
     while !input.is_empty() {
         if let Ok((rest, rule)) = css_big_token(css_rule)(input) {
             result.push(rule);
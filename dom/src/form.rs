@@ -0,0 +1,358 @@
+use crate::{Node, NodeType};
+
+/// the kinds of form control we can draw and submit. file inputs are
+/// missing, since we have no way to pick files yet.
+/// <https://html.spec.whatwg.org/multipage/forms.html#categories>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlType {
+    /// text-like inputs, including ones like email and number that we
+    /// don’t treat specially.
+    Text,
+    Password,
+    Hidden,
+    Checkbox,
+    Radio,
+    Submit,
+    /// submit button with an image, which we can’t load, so we draw its alt.
+    Image,
+    Reset,
+    Button,
+    TextArea,
+    Select,
+}
+
+impl ControlType {
+    pub fn of(node: &Node) -> Option<Self> {
+        if node.r#type() != NodeType::Element {
+            return None;
+        }
+        let r#type = node.attr("type").map(|x| x.to_ascii_lowercase());
+        match &*node.name() {
+            "input" => match r#type.as_deref() {
+                Some("password") => Some(Self::Password),
+                Some("hidden") => Some(Self::Hidden),
+                Some("checkbox") => Some(Self::Checkbox),
+                Some("radio") => Some(Self::Radio),
+                Some("submit") => Some(Self::Submit),
+                Some("image") => Some(Self::Image),
+                Some("reset") => Some(Self::Reset),
+                Some("button") => Some(Self::Button),
+                Some("file") => None,
+                _ => Some(Self::Text),
+            },
+            "button" => match r#type.as_deref() {
+                Some("reset") => Some(Self::Reset),
+                Some("button") => Some(Self::Button),
+                _ => Some(Self::Submit),
+            },
+            "textarea" => Some(Self::TextArea),
+            "select" => Some(Self::Select),
+            _ => None,
+        }
+    }
+
+    pub fn is_button(self) -> bool {
+        matches!(
+            self,
+            Self::Submit | Self::Image | Self::Reset | Self::Button
+        )
+    }
+
+    pub fn is_submit_button(self) -> bool {
+        matches!(self, Self::Submit | Self::Image)
+    }
+}
+
+// we keep the current state of each control in the dom itself, in the value
+// and checked attributes, the selected attributes of options, and the text of
+// textareas. that means no separate dirty flags, but also no way to reset.
+
+/// the current value of the control.
+/// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#concept-fe-value>
+pub fn value(node: &Node) -> String {
+    match ControlType::of(node) {
+        Some(ControlType::Checkbox | ControlType::Radio) => {
+            node.attr("value").map_or("on".to_owned(), |x| x.to_owned())
+        }
+        Some(ControlType::TextArea) => node.text_content(),
+        Some(ControlType::Select) => selected_options(node)
+            .first()
+            .map_or(String::new(), option_value),
+        _ => node.attr("value").map_or(String::new(), |x| x.to_owned()),
+    }
+}
+
+pub fn set_value(node: &Node, value: String) {
+    match ControlType::of(node) {
+        Some(ControlType::TextArea) => {
+            node.write().children.clear();
+            node.append(&[Node::text(value)]);
+        }
+        _ => node.set_attr("value", value),
+    }
+}
+
+pub fn checked(node: &Node) -> bool {
+    node.attr("checked").is_some()
+}
+
+/// checks or unchecks the control, unchecking the rest of its radio group.
+/// <https://html.spec.whatwg.org/multipage/input.html#radio-button-group>
+pub fn set_checked(node: &Node, checked: bool) {
+    if !checked {
+        node.remove_attr("checked");
+        return;
+    }
+    if ControlType::of(node) == Some(ControlType::Radio) {
+        let name = node.attr("name").map(|x| x.to_owned());
+        let owner = form_owner(node);
+        for other in root(node).descendants() {
+            if other != *node
+                && ControlType::of(&other) == Some(ControlType::Radio)
+                && name.is_some()
+                && other.attr("name").map(|x| x.to_owned()) == name
+                && form_owner(&other) == owner
+            {
+                other.remove_attr("checked");
+            }
+        }
+    }
+    node.set_attr("checked", "");
+}
+
+/// what to write on a button or select.
+pub fn label(node: &Node) -> String {
+    let value = node.attr("value").map(|x| x.to_owned());
+    match ControlType::of(node) {
+        _ if &*node.name() == "button" => collapse_whitespace(&node.text_content()),
+        Some(ControlType::Submit) => value.unwrap_or("Submit".to_owned()),
+        Some(ControlType::Image) => node
+            .attr("alt")
+            .map_or("Submit".to_owned(), |x| x.to_owned()),
+        Some(ControlType::Reset) => value.unwrap_or("Reset".to_owned()),
+        Some(ControlType::Select) => selected_options(node)
+            .first()
+            .map_or(String::new(), option_label),
+        _ => value.unwrap_or_default(),
+    }
+}
+
+pub fn options(select: &Node) -> Vec<Node> {
+    select
+        .descendants()
+        .filter(|x| x.r#type() == NodeType::Element && &*x.name() == "option")
+        .collect()
+}
+
+/// the selected options, or for a select that can only show one, the
+/// first enabled option if none are selected.
+/// <https://html.spec.whatwg.org/multipage/form-elements.html#selectedness-setting-algorithm>
+pub fn selected_options(select: &Node) -> Vec<Node> {
+    let options = options(select);
+    let selected = options
+        .iter()
+        .filter(|x| x.attr("selected").is_some())
+        .cloned()
+        .collect::<Vec<_>>();
+    if !selected.is_empty() || select.attr("multiple").is_some() {
+        return selected;
+    }
+
+    options
+        .into_iter()
+        .filter(|x| x.attr("disabled").is_none())
+        .take(1)
+        .collect()
+}
+
+/// selects the given option, which deselects the others, unless the select
+/// allows more than one, in which case it toggles the option.
+pub fn select_option(select: &Node, option: &Node) {
+    if select.attr("multiple").is_some() {
+        match option.attr("selected").is_some() {
+            true => option.remove_attr("selected"),
+            false => option.set_attr("selected", ""),
+        }
+        return;
+    }
+    for other in options(select) {
+        other.remove_attr("selected");
+    }
+    option.set_attr("selected", "");
+}
+
+/// <https://html.spec.whatwg.org/multipage/form-elements.html#concept-option-value>
+pub fn option_value(option: &Node) -> String {
+    match option.attr("value") {
+        Some(value) => value.to_owned(),
+        None => collapse_whitespace(&option.text_content()),
+    }
+}
+
+/// <https://html.spec.whatwg.org/multipage/form-elements.html#concept-option-label>
+pub fn option_label(option: &Node) -> String {
+    match option.attr("label") {
+        Some(label) if !label.is_empty() => label.to_owned(),
+        _ => collapse_whitespace(&option.text_content()),
+    }
+}
+
+/// whether the control is disabled, by itself or by a fieldset.
+/// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#concept-fe-disabled>
+pub fn is_disabled(node: &Node) -> bool {
+    if node.attr("disabled").is_some() {
+        return true;
+    }
+
+    // a disabled fieldset disables everything in it, except its first legend
+    let mut child = node.clone();
+    for ancestor in node.walk_up() {
+        if &*ancestor.name() == "fieldset" && ancestor.attr("disabled").is_some() {
+            let first_legend = ancestor
+                .children()
+                .iter()
+                .find(|x| &*x.name() == "legend")
+                .cloned();
+            if first_legend.as_ref() != Some(&child) {
+                return true;
+            }
+        }
+        child = ancestor;
+    }
+
+    false
+}
+
+/// the form the control belongs to, which is the form named by its form
+/// attribute if any, otherwise its nearest form ancestor.
+/// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#form-owner>
+pub fn form_owner(node: &Node) -> Option<Node> {
+    if let Some(id) = node.attr("form").map(|x| x.to_owned()) {
+        return root(node)
+            .descendants()
+            .find(|x| &*x.name() == "form" && x.attr("id").filter(|x| **x == id).is_some());
+    }
+
+    node.walk_up().find(|x| &*x.name() == "form")
+}
+
+/// the first submit button of the form, which enter in a text field presses.
+/// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#default-button>
+pub fn default_button(form: &Node) -> Option<Node> {
+    controls(form).find(|x| {
+        ControlType::of(x)
+            .filter(|x| x.is_submit_button())
+            .is_some()
+    })
+}
+
+/// the names and values the form submits, in tree order. only the given
+/// submitter (if any) counts among the buttons, and hidden `_charset_`
+/// controls submit the name of the encoding the form will be encoded in.
+/// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#constructing-the-form-data-set>
+pub fn entry_list(form: &Node, submitter: Option<&Node>, charset: &str) -> Vec<(String, String)> {
+    let mut result = vec![];
+    for control in controls(form) {
+        let Some(r#type) = ControlType::of(&control) else { continue };
+        if is_disabled(&control)
+            || control.walk_up().any(|x| &*x.name() == "datalist")
+            || r#type.is_button() && submitter != Some(&control)
+            || matches!(r#type, ControlType::Checkbox | ControlType::Radio) && !checked(&control)
+        {
+            continue;
+        }
+
+        // image buttons submit where they were clicked, which we don’t know
+        let name = control.attr("name").map_or(String::new(), |x| x.to_owned());
+        if r#type == ControlType::Image {
+            let prefix = match name.is_empty() {
+                true => String::new(),
+                false => format!("{}.", name),
+            };
+            result.push((format!("{}x", prefix), "0".to_owned()));
+            result.push((format!("{}y", prefix), "0".to_owned()));
+            continue;
+        }
+        if name.is_empty() {
+            continue;
+        }
+
+        match r#type {
+            ControlType::Select => {
+                for option in selected_options(&control) {
+                    if !is_disabled(&option) {
+                        result.push((name.clone(), option_value(&option)));
+                    }
+                }
+            }
+            ControlType::Hidden if name.eq_ignore_ascii_case("_charset_") => {
+                result.push((name, charset.to_owned()));
+            }
+            _ => result.push((name, value(&control))),
+        }
+    }
+
+    result
+}
+
+/// every control whose form owner is the given form, in tree order.
+fn controls(form: &Node) -> impl Iterator<Item = Node> + '_ {
+    root(form)
+        .descendants()
+        .filter(|x| ControlType::of(x).is_some())
+        .filter(move |x| form_owner(x).as_ref() == Some(form))
+}
+
+fn root(node: &Node) -> Node {
+    node.walk_up().last().unwrap_or(node.clone())
+}
+
+/// <https://infra.spec.whatwg.org/#strip-and-collapse-ascii-whitespace>
+fn collapse_whitespace(input: &str) -> String {
+    input.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_entry_list() {
+    let element = |name: &str, attrs: &[(&str, &str)]| Node::element(name.to_owned(), attrs.iter().map(|&(n, v)| (n.to_owned(), v.to_owned())).collect());
+    let submit = element("button", &[("name", "go"), ("value", "1")]);
+    let other = element("input", &[("type", "submit"), ("name", "other")]);
+    let radio1 = element("input", &[("type", "radio"), ("name", "r"), ("value", "a"), ("checked", "")]);
+    let radio2 = element("input", &[("type", "radio"), ("name", "r"), ("value", "b")]);
+    let select = element("select", &[("name", "s")]).append(&[
+        element("option", &[("disabled", "")]).append(&[Node::text("zero".to_owned())]),
+        element("option", &[]).append(&[Node::text("  one\n two ".to_owned())]),
+        element("option", &[("value", "3")]).append(&[Node::text("three".to_owned())]),
+    ]);
+    let form = element("form", &[("id", "f")]).append(&[
+        element("input", &[("name", "q"), ("value", "x y")]),
+        element("input", &[("name", "pw"), ("type", "password"), ("disabled", "")]),
+        element("input", &[("type", "checkbox"), ("name", "c")]),
+        element("input", &[("type", "checkbox"), ("name", "c"), ("checked", "")]),
+        radio1.clone(),
+        radio2.clone(),
+        element("fieldset", &[("disabled", "")]).append(&[element("input", &[("name", "hidden-by-fieldset")])]),
+        element("textarea", &[("name", "t")]).append(&[Node::text("line\nbreak".to_owned())]),
+        element("input", &[("type", "hidden"), ("name", "_charset_")]),
+        element("input", &[("value", "no name")]),
+        select.clone(),
+        submit.clone(),
+        other,
+    ]);
+    let outside = element("input", &[("name", "outside"), ("form", "f")]);
+    let _document = Node::document().append(&[element("body", &[]).append(&[form.clone(), outside.clone()])]);
+
+    let entries = |submitter: Option<&Node>| entry_list(&form, submitter, "UTF-8").into_iter().map(|(n, v)| format!("{}={}", n, v)).collect::<Vec<_>>();
+    assert_eq!(entries(Some(&submit)), ["q=x y", "c=on", "r=a", "t=line\nbreak", "_charset_=UTF-8", "s=one two", "go=1", "outside="]);
+    assert_eq!(default_button(&form), Some(submit.clone()));
+    assert_eq!(form_owner(&outside), Some(form.clone()));
+
+    set_checked(&radio2, true);
+    assert!(!checked(&radio1));
+    select_option(&select, &options(&select)[2]);
+    set_value(&outside, "o".to_owned());
+    assert_eq!(label(&select), "three");
+    assert_eq!(&entries(None)[2..], ["r=b", "t=line\nbreak", "_charset_=UTF-8", "s=3", "outside=o"]);
+    assert!(entry_list(&form, None, "Shift_JIS").contains(&("_charset_".to_owned(), "Shift_JIS".to_owned())));
+}
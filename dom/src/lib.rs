@@ -1,6 +1,7 @@
+pub mod form;
 pub mod style;
 
-pub use crate::style::Style;
+pub use crate::{form::ControlType, style::Style};
 
 use std::{
     fmt::{Debug, Display},
@@ -9,6 +10,7 @@ use std::{
 
 use owning_ref::{RwLockReadGuardRef, RwLockWriteGuardRefMut};
 use tracing::{instrument, trace, warn};
+use wbe_core::SourceSpan;
 
 pub type NodeRead<'n, T> = RwLockReadGuardRef<'n, OwnedNode, T>;
 pub type NodeWrite<'n, T> = RwLockWriteGuardRefMut<'n, OwnedNode, T>;
@@ -18,6 +20,8 @@ pub struct OwnedNode {
     pub parent: Weak<RwLock<OwnedNode>>,
     pub children: Vec<Node>,
     pub inner: NodeData,
+    /// the markup this node came from, if it came from any.
+    pub source_span: Option<SourceSpan>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -94,6 +98,7 @@ impl Node {
             parent: Weak::new(),
             children: vec![],
             inner,
+            source_span: None,
         })))
     }
 
@@ -125,6 +130,33 @@ impl Node {
         self.clone()
     }
 
+    /// inserts the given children before `reference`, or at the end if None
+    /// or not a child of this node, moving any that already have a parent.
+    #[instrument(skip(self, children, reference))]
+    pub fn insert_before(&self, children: &[Node], reference: Option<&Node>) -> Self {
+        for child in children {
+            child.remove();
+            let index = reference.and_then(|r| self.children().iter().position(|x| x == r));
+            child.write().parent = Arc::downgrade(&self.0);
+            let mut this = self.write();
+            match index {
+                Some(index) => this.children.insert(index, child.clone()),
+                None => this.children.push(child.clone()),
+            }
+        }
+
+        self.clone()
+    }
+
+    /// removes this node from its parent, if it has one.
+    #[instrument(skip(self))]
+    pub fn remove(&self) {
+        if let Some(parent) = self.parent() {
+            parent.write().children.retain(|x| x != self);
+        }
+        self.write().parent = Weak::new();
+    }
+
     #[instrument(skip(self))]
     pub fn parent(&self) -> Option<Self> {
         self.read().parent.upgrade().map(Self)
@@ -208,6 +240,22 @@ impl Node {
             .ok()
     }
 
+    /// sets the given attribute, replacing any existing value.
+    pub fn set_attr(&self, name: &str, value: impl Into<String>) {
+        if let NodeData::Element(_, attrs, _) = &mut *self.data_mut() {
+            match attrs.iter_mut().find(|(n, _)| n == name) {
+                Some((_, old)) => *old = value.into(),
+                None => attrs.push((name.to_owned(), value.into())),
+            }
+        }
+    }
+
+    pub fn remove_attr(&self, name: &str) {
+        if let NodeData::Element(_, attrs, _) = &mut *self.data_mut() {
+            attrs.retain(|(n, _)| n != name);
+        }
+    }
+
     pub fn text_content(&self) -> String {
         let mut result = String::new();
 
@@ -218,6 +266,16 @@ impl Node {
         result
     }
 
+    /// where in the html source this node came from, if anywhere. for
+    /// elements, this is their start tag.
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.read().source_span
+    }
+
+    pub fn set_source_span(&self, span: Option<SourceSpan>) {
+        self.write().source_span = span;
+    }
+
     pub fn children(&self) -> NodeRead<[Node]> {
         self.read().map(|x| &*x.children)
     }
@@ -1,68 +1,84 @@
-use std::{collections::BTreeMap, env, error::Error, fs::File, io::Write};
+use std::{collections::BTreeMap, env, error::Error, fs, fs::File, io::Write};
 
 #[derive(Debug, serde::Deserialize)]
 struct Entity {
-    #[allow(dead_code)]
-    codepoints: Vec<u32>,
     characters: String,
 }
 
+/// a node in the trie of character reference names.
+#[derive(Debug, Default)]
+struct TrieNode {
+    /// the index of the entity whose name ends here, if any.
+    entity: Option<usize>,
+    children: BTreeMap<u8, usize>,
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut with = Vec::default();
-    let mut without = Vec::default();
-    for (name, value) in reqwest::blocking::get("https://html.spec.whatwg.org/entities.json")?
-        .json::<BTreeMap<String, Entity>>()?
-    {
-        if name.ends_with(";") {
-            with.push((name, value));
-        } else {
-            without.push((name, value));
+    // a copy of <https://html.spec.whatwg.org/entities.json>
+    let json = fs::read_to_string("entities.json")?;
+    let entities = serde_json::from_str::<BTreeMap<String, Entity>>(&json)?
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let mut nodes = vec![TrieNode::default()];
+    for (i, (name, _)) in entities.iter().enumerate() {
+        let mut node = 0;
+        for byte in name.bytes() {
+            node = match nodes[node].children.get(&byte) {
+                Some(&child) => child,
+                None => {
+                    nodes.push(TrieNode::default());
+                    let child = nodes.len() - 1;
+                    nodes[node].children.insert(byte, child);
+                    child
+                }
+            };
         }
+        nodes[node].entity = Some(i);
     }
+    assert!(nodes.len() < u16::MAX.into() && entities.len() < u16::MAX.into());
 
-    // longest character reference names first
-    with.sort_by(|p, q| p.0.len().cmp(&q.0.len()).reverse());
-    without.sort_by(|p, q| p.0.len().cmp(&q.0.len()).reverse());
+    let mut out = File::create(format!("{}/entities.rs", env::var("OUT_DIR")?))?;
 
-    let mut entities = File::create(dbg!(format!("{}/entities.rs", env::var("OUT_DIR")?)))?;
-
-    writeln!(
-        entities,
-        "pub const ENTITIES_WITH_SEMICOLON: &[(&str, &str)] = &["
-    )?;
-    for (name, value) in &with {
-        writeln!(entities, "    ({:?}, {:?}),", name, value.characters)?;
+    writeln!(out, "/// every named character reference, sorted by name.")?;
+    writeln!(out, "pub const ENTITIES: &[(&str, &str)] = &[")?;
+    for (name, entity) in &entities {
+        writeln!(out, "    ({:?}, {:?}),", name, entity.characters)?;
     }
-    writeln!(entities, "];")?;
-    writeln!(
-        entities,
-        "pub const ENTITIES_WITHOUT_SEMICOLON: &[(&str, &str)] = &["
-    )?;
-    for (name, value) in &without {
-        writeln!(entities, "    ({:?}, {:?}),", name, value.characters)?;
-    }
-    writeln!(entities, "];")?;
+    writeln!(out, "];")?;
 
-    writeln!(entities, "lazy_static::lazy_static! {{")?;
+    // each node has its edges in a contiguous run, sorted by byte
     writeln!(
-        entities,
-        "    pub static ref ENTITIES_WITH_SEMICOLON_REGEX: regex::RegexSet = regex::RegexSet::new(&["
+        out,
+        "/// the trie of names in [`ENTITIES`], as (first edge, number of edges,"
     )?;
-    for (name, _) in &with {
-        writeln!(entities, "        {:?},", format!("^{}", name))?;
-    }
-    writeln!(entities, "    ]).unwrap();")?;
     writeln!(
-        entities,
-        "    pub static ref ENTITIES_WITHOUT_SEMICOLON_REGEX: regex::RegexSet = regex::RegexSet::new(&["
+        out,
+        "/// entity or u16::MAX) for each node, starting with the root."
     )?;
-    for (name, _) in &without {
-        writeln!(entities, "        {:?},", format!("^{}", name))?;
+    writeln!(out, "const ENTITY_NODES: &[(u16, u8, u16)] = &[")?;
+    let mut edges = vec![];
+    for node in &nodes {
+        let entity = node.entity.map_or(u16::MAX, |x| x as u16);
+        writeln!(
+            out,
+            "    ({}, {}, {}),",
+            edges.len(),
+            node.children.len(),
+            entity
+        )?;
+        edges.extend(node.children.iter().map(|(&byte, &child)| (byte, child)));
+    }
+    writeln!(out, "];")?;
+    writeln!(out, "/// the edges of the trie, as (byte, node).")?;
+    writeln!(out, "const ENTITY_EDGES: &[(u8, u16)] = &[")?;
+    for (byte, child) in edges {
+        writeln!(out, "    (b'{}', {}),", byte as char, child)?;
     }
-    writeln!(entities, "    ]).unwrap();")?;
-    writeln!(entities, "}}")?;
+    writeln!(out, "];")?;
 
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=entities.json");
 
     Ok(())
 }
@@ -1,3 +1,9 @@
+pub mod tokenizer;
+
+pub use crate::tokenizer::{Doctype, State, Tag, Token, Tokenizer};
+
+use std::borrow::Cow;
+
 use nom::{
     branch::alt,
     bytes::complete::{tag, tag_no_case, take_while, take_while1},
@@ -7,6 +13,7 @@ use nom::{
     sequence::{delimited, preceded, tuple},
     IResult, Needed,
 };
+use tracing::debug;
 
 include!(concat!(env!("OUT_DIR"), "/entities.rs"));
 
@@ -31,7 +38,7 @@ fn quoted_attr_value(mut input: &str) -> IResult<&str, String> {
 
     while !input.is_empty() {
         let (rest, text) = html_text(true)(input).expect("parser is infallible");
-        result += text;
+        result += &text;
         input = rest;
     }
 
@@ -129,37 +136,125 @@ pub fn html_doctype(input: &str) -> IResult<&str, &str> {
     preceded(tag("<!"), shortest_until_tag_no_case(">"))(input)
 }
 
-pub fn html_entity(in_attr: bool) -> impl FnMut(&str) -> IResult<&str, &str> {
+/// the longest named character reference at the start of the input, like
+/// `&amp;` or `&amp`, and the characters it stands for.
+/// <https://html.spec.whatwg.org/multipage/parsing.html#named-character-reference-state>
+pub fn named_character_reference(input: &str) -> Option<(&'static str, &'static str)> {
+    // walk the trie as far as the input goes, remembering the last name
+    let mut node = 0;
+    let mut result = None;
+    for byte in input.bytes() {
+        let (first, len, _) = ENTITY_NODES[node];
+        let edges = &ENTITY_EDGES[usize::from(first)..][..usize::from(len)];
+        let Ok(i) = edges.binary_search_by_key(&byte, |x| x.0) else { break };
+        node = usize::from(edges[i].1);
+        let (_, _, entity) = ENTITY_NODES[node];
+        if entity != u16::MAX {
+            result = Some(ENTITIES[usize::from(entity)]);
+        }
+    }
+
+    result
+}
+
+/// replacements for numeric character references to c1 controls, which
+/// were usually meant as windows-1252.
+#[rustfmt::skip]
+const C1_REPLACEMENTS: &[(u32, char)] = &[
+    (0x80, '\u{20AC}'), (0x82, '\u{201A}'), (0x83, '\u{0192}'), (0x84, '\u{201E}'),
+    (0x85, '\u{2026}'), (0x86, '\u{2020}'), (0x87, '\u{2021}'), (0x88, '\u{02C6}'),
+    (0x89, '\u{2030}'), (0x8A, '\u{0160}'), (0x8B, '\u{2039}'), (0x8C, '\u{0152}'),
+    (0x8E, '\u{017D}'), (0x91, '\u{2018}'), (0x92, '\u{2019}'), (0x93, '\u{201C}'),
+    (0x94, '\u{201D}'), (0x95, '\u{2022}'), (0x96, '\u{2013}'), (0x97, '\u{2014}'),
+    (0x98, '\u{02DC}'), (0x99, '\u{2122}'), (0x9A, '\u{0161}'), (0x9B, '\u{203A}'),
+    (0x9C, '\u{0153}'), (0x9E, '\u{017E}'), (0x9F, '\u{0178}'),
+];
+
+/// the character for a numeric character reference, like 169 for `&#169;`,
+/// and the parse error it causes, if any.
+/// <https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state>
+pub fn numeric_character_reference(code: u32) -> (char, Option<&'static str>) {
+    let error = match code {
+        0 => return ('\u{FFFD}', Some("null-character-reference")),
+        0x110000.. => {
+            return (
+                '\u{FFFD}',
+                Some("character-reference-outside-unicode-range"),
+            );
+        }
+        0xD800..=0xDFFF => return ('\u{FFFD}', Some("surrogate-character-reference")),
+        0xFDD0..=0xFDEF => Some("noncharacter-character-reference"),
+        x if x & 0xFFFE == 0xFFFE => Some("noncharacter-character-reference"),
+        0x0D | 0x01..=0x08 | 0x0B | 0x0E..=0x1F | 0x7F..=0x9F => {
+            Some("control-character-reference")
+        }
+        _ => None,
+    };
+    let c = match C1_REPLACEMENTS.iter().find(|x| x.0 == code) {
+        Some(&(_, c)) => c,
+        None => char::from_u32(code).expect("not a surrogate or out of range"),
+    };
+
+    (c, error)
+}
+
+fn parse_error(code: &'static str) {
+    debug!(code, "html parse error");
+}
+
+pub fn html_entity(in_attr: bool) -> impl FnMut(&str) -> IResult<&str, Cow<str>> {
     move |input: &str| {
-        for i in ENTITIES_WITH_SEMICOLON_REGEX.matches(input) {
-            let (name, value) = ENTITIES_WITH_SEMICOLON[i];
-            return Ok((input.strip_prefix(name).unwrap(), value));
+        let Some(after_ampersand) = input.strip_prefix('&') else { return fail(input) };
+        if let Some(rest) = after_ampersand.strip_prefix('#') {
+            let (radix, digits) = match rest.strip_prefix(['x', 'X']) {
+                Some(digits) => (16, digits),
+                None => (10, rest),
+            };
+            let len = digits
+                .find(|c: char| !c.is_digit(radix))
+                .unwrap_or(digits.len());
+            if len > 0 {
+                // saturate, so huge references stay outside unicode
+                let code = digits[..len].chars().fold(0u32, |code, c| {
+                    code.saturating_mul(radix)
+                        .saturating_add(c.to_digit(radix).unwrap())
+                });
+                let rest = &digits[len..];
+                let rest = rest.strip_prefix(';').unwrap_or_else(|| {
+                    parse_error("missing-semicolon-after-character-reference");
+                    rest
+                });
+                let (c, error) = numeric_character_reference(code);
+                if let Some(error) = error {
+                    parse_error(error);
+                }
+                return Ok((rest, Cow::Owned(c.to_string())));
+            }
+            parse_error("absence-of-digits-in-numeric-character-reference");
         }
-        for i in ENTITIES_WITHOUT_SEMICOLON_REGEX.matches(input) {
-            let (name, value) = ENTITIES_WITHOUT_SEMICOLON[i];
+
+        if let Some((name, value)) = named_character_reference(input) {
             let rest = input.strip_prefix(name).unwrap();
-            if in_attr && rest.starts_with(|c: char| c == '=' || c.is_ascii_alphanumeric()) {
-                return Ok((rest, name));
+            if in_attr
+                && !name.ends_with(';')
+                && rest.starts_with(|c: char| c == '=' || c.is_ascii_alphanumeric())
+            {
+                return Ok((rest, Cow::Borrowed(name)));
             } else {
-                return Ok((rest, value));
+                return Ok((rest, Cow::Borrowed(value)));
             }
         }
 
-        if input.is_empty() {
-            fail(input)
-        } else {
-            let (ampersand, rest) = input.split_at(1);
-            Ok((rest, ampersand))
-        }
+        Ok((after_ampersand, Cow::Borrowed("&")))
     }
 }
 
-pub fn html_text(in_attr: bool) -> impl FnMut(&str) -> IResult<&str, &str> {
+pub fn html_text(in_attr: bool) -> impl FnMut(&str) -> IResult<&str, Cow<str>> {
     move |input: &str| {
         alt((
-            take_while1(|c| c != '<' && c != '&'),
+            map(take_while1(|c| c != '<' && c != '&'), Cow::Borrowed),
             html_entity(in_attr),
-            tag("<"),
+            map(tag("<"), Cow::Borrowed),
         ))(input)
     }
 }
@@ -170,7 +265,7 @@ pub enum HtmlToken<'i> {
     Script(Vec<(&'i str, String)>, &'i str),
     Style(Vec<(&'i str, String)>, &'i str),
     Tag(bool, &'i str, Vec<(&'i str, String)>),
-    Text(&'i str),
+    Text(Cow<'i, str>),
     Doctype(&'i str),
 }
 
@@ -211,3 +306,43 @@ pub fn html_word(input: &str) -> IResult<&str, HtmlWord> {
 
     Ok((rest, HtmlWord::Other(text)))
 }
+
+#[test]
+#[rustfmt::skip]
+fn test_named_character_reference() {
+    assert_eq!(named_character_reference("&amp;x"), Some(("&amp;", "&")));
+    assert_eq!(named_character_reference("&ampx"), Some(("&amp", "&")));
+    assert_eq!(named_character_reference("&notin;"), Some(("&notin;", "∉")));
+    assert_eq!(named_character_reference("&notit;"), Some(("&not", "¬")));
+    assert_eq!(named_character_reference("&NotEqualTilde;"), Some(("&NotEqualTilde;", "≂\u{338}")));
+    assert_eq!(named_character_reference("&nosuch;"), None);
+    assert_eq!(named_character_reference("&"), None);
+    assert_eq!(named_character_reference("amp;"), None);
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_numeric_character_reference() {
+    let text = |x| html_text(false)(x).unwrap();
+    assert_eq!(text("&#169;x"), ("x", Cow::Borrowed("©")));
+    assert_eq!(text("&#x1F600;x"), ("x", Cow::Borrowed("😀")));
+    assert_eq!(text("&#X41x"), ("x", Cow::Borrowed("A")));
+    assert_eq!(text("&#65x"), ("x", Cow::Borrowed("A")));
+    assert_eq!(text("&#128;"), ("", Cow::Borrowed("€")));
+    assert_eq!(text("&#0;"), ("", Cow::Borrowed("\u{FFFD}")));
+    assert_eq!(text("&#xD800;"), ("", Cow::Borrowed("\u{FFFD}")));
+    assert_eq!(text("&#x110000;"), ("", Cow::Borrowed("\u{FFFD}")));
+    assert_eq!(text("&#99999999999999;"), ("", Cow::Borrowed("\u{FFFD}")));
+    assert_eq!(text("&#;"), ("#;", Cow::Borrowed("&")));
+    assert_eq!(text("&#xg;"), ("#xg;", Cow::Borrowed("&")));
+    assert_eq!(text("<"), ("", Cow::Borrowed("<")));
+    assert_eq!(html_attr_value("'&#169;&#x41&copy'"), Ok(("", "©A©".to_owned())));
+
+    assert_eq!(numeric_character_reference(0x41), ('A', None));
+    assert_eq!(numeric_character_reference(0x81), ('\u{81}', Some("control-character-reference")));
+    assert_eq!(numeric_character_reference(0x9F), ('Ÿ', Some("control-character-reference")));
+    assert_eq!(numeric_character_reference(0x0D), ('\r', Some("control-character-reference")));
+    assert_eq!(numeric_character_reference(0x0A), ('\n', None));
+    assert_eq!(numeric_character_reference(0xFFFE), ('\u{FFFE}', Some("noncharacter-character-reference")));
+    assert_eq!(numeric_character_reference(0x10FFFF), ('\u{10FFFF}', Some("noncharacter-character-reference")));
+}
@@ -0,0 +1,1682 @@
+use std::{collections::VecDeque, mem::take};
+
+use wbe_core::{SourcePosition, SourceSpan};
+
+use crate::{named_character_reference, numeric_character_reference};
+
+/// a token from the html tokenizer, with runs of characters combined.
+/// <https://html.spec.whatwg.org/multipage/parsing.html#tokenization>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Doctype(Doctype),
+    StartTag(Tag),
+    EndTag(Tag),
+    Comment(String),
+    Text(String),
+    /// a parse error, named by its code in the spec. the tokenizer always
+    /// recovers from these, so they’re only interesting for debugging.
+    Error(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Doctype {
+    pub name: Option<String>,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    pub force_quirks: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tag {
+    /// in lowercase, like the names of the attributes.
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub self_closing: bool,
+}
+
+/// <https://html.spec.whatwg.org/multipage/parsing.html#tokenization>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Data,
+    /// for the text of `<title>` and `<textarea>`.
+    Rcdata,
+    /// for the text of `<style>` and friends.
+    Rawtext,
+    /// for the text of `<script>`.
+    ScriptData,
+    /// for everything after `<plaintext>`.
+    Plaintext,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    RcdataLessThanSign,
+    RcdataEndTagOpen,
+    RcdataEndTagName,
+    RawtextLessThanSign,
+    RawtextEndTagOpen,
+    RawtextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
+}
+
+/// turns html into tokens as it arrives in pieces, never failing on any
+/// input, but reporting parse errors as [`Token::Error`]. each token comes
+/// with the span of the source it came from, where errors are empty spans
+/// and text spans everything between the tokens around it.
+/// <https://html.spec.whatwg.org/multipage/parsing.html#tokenization>
+#[derive(Debug)]
+pub struct Tokenizer {
+    /// all of the input so far, so we can find the line and column of any
+    /// offset. input that hasn’t been consumed yet starts at `pos`.
+    input: String,
+    pos: usize,
+    /// the last position we found, and the start of its line.
+    cursor: SourcePosition,
+    line_start: SourcePosition,
+    /// where the current tag, comment, or doctype started, at its `<`.
+    markup_start: SourcePosition,
+    /// where the last tag, comment, or doctype ended, so any text after it
+    /// starts there.
+    last_end: SourcePosition,
+    /// whether there’s no more input after `input`.
+    eof: bool,
+    /// whether we’ve emitted [`Token::Eof`].
+    done: bool,
+    state: State,
+    return_state: State,
+    tokens: VecDeque<(Token, SourceSpan)>,
+    text: String,
+    tag: Tag,
+    is_end_tag: bool,
+    attr: Option<(String, String)>,
+    comment: String,
+    doctype: Doctype,
+    temp: String,
+    /// the code point of the numeric character reference so far.
+    char_ref_code: u32,
+    last_start_tag: Option<String>,
+    allow_cdata: bool,
+}
+
+/// the tokenizer can’t go on until more input arrives.
+struct Wait;
+
+impl Tokenizer {
+    pub fn new() -> Self {
+        Self {
+            input: String::new(),
+            pos: 0,
+            cursor: SourcePosition::start(),
+            line_start: SourcePosition::start(),
+            markup_start: SourcePosition::start(),
+            last_end: SourcePosition::start(),
+            eof: false,
+            done: false,
+            state: State::Data,
+            return_state: State::Data,
+            tokens: VecDeque::new(),
+            text: String::new(),
+            tag: Tag::default(),
+            is_end_tag: false,
+            attr: None,
+            comment: String::new(),
+            doctype: Doctype::default(),
+            temp: String::new(),
+            char_ref_code: 0,
+            last_start_tag: None,
+            allow_cdata: false,
+        }
+    }
+
+    /// adds the next piece of input, which can end anywhere, even in the
+    /// middle of a token.
+    pub fn push(&mut self, input: &str) {
+        assert!(!self.eof, "input after end of input");
+        self.input += input;
+    }
+
+    /// marks the end of the input, so any tokens waiting for more can finish.
+    pub fn end(&mut self) {
+        self.eof = true;
+    }
+
+    /// switches to another state, which the tree builder does after start
+    /// tags like `<title>`, `<style>`, and `<script>`.
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    /// whether `<![CDATA[` starts a cdata section rather than a bogus
+    /// comment, which is only true in svg and mathml.
+    pub fn set_allow_cdata(&mut self, allow_cdata: bool) {
+        self.allow_cdata = allow_cdata;
+    }
+
+    /// the next token and where it came from, or None if there’s no more
+    /// until more input arrives.
+    pub fn next_token(&mut self) -> Option<(Token, SourceSpan)> {
+        // text waits for the next token, since more input may continue it
+        while self.tokens.is_empty() && !self.done {
+            if let Err(Wait) = self.step() {
+                break;
+            }
+        }
+
+        self.tokens.pop_front()
+    }
+
+    fn consume(&mut self) -> Result<Option<char>, Wait> {
+        let rest = &self.input[self.pos..];
+        match rest.chars().next() {
+            // newlines are always line feeds, even if split across pieces
+            // https://html.spec.whatwg.org/multipage/parsing.html#preprocessing-the-input-stream
+            Some('\r') if rest.len() == 1 && !self.eof => Err(Wait),
+            Some('\r') => {
+                self.pos += if rest.starts_with("\r\n") { 2 } else { 1 };
+                Ok(Some('\n'))
+            }
+            Some(c) => {
+                self.pos += c.len_utf8();
+                Ok(Some(c))
+            }
+            None if self.eof => Ok(None),
+            None => Err(Wait),
+        }
+    }
+
+    fn reconsume(&mut self, c: Option<char>, state: State) {
+        if let Some(c) = c {
+            self.pos -= c.len_utf8();
+        }
+        self.state = state;
+    }
+
+    /// whether the input continues with the given ascii, consuming it if so.
+    fn consume_if(&mut self, pattern: &str, ignore_case: bool) -> Result<bool, Wait> {
+        let rest = &self.input.as_bytes()[self.pos..];
+        let len = rest.len().min(pattern.len());
+        let (rest, expected) = (&rest[..len], &pattern.as_bytes()[..len]);
+        let matches = match ignore_case {
+            true => rest.eq_ignore_ascii_case(expected),
+            false => rest == expected,
+        };
+        match (matches, len == pattern.len()) {
+            (false, _) => Ok(false),
+            (true, true) => {
+                self.pos += len;
+                Ok(true)
+            }
+            (true, false) if self.eof => Ok(false),
+            (true, false) => Err(Wait),
+        }
+    }
+
+    /// the line and column of an offset in the input, which is usually at
+    /// or after the last one we found.
+    fn position(&mut self, offset: usize) -> SourcePosition {
+        if offset < self.cursor.offset {
+            if offset < self.line_start.offset {
+                self.line_start = SourcePosition::start();
+            }
+            self.cursor = self.line_start;
+        }
+        while self.cursor.offset < offset {
+            let rest = &self.input[self.cursor.offset..];
+            let c = rest.chars().next().expect("offset in input");
+            self.cursor.offset += c.len_utf8();
+
+            // a carriage return and line feed together are one newline
+            if c == '\n' || (c == '\r' && !rest.starts_with("\r\n")) {
+                self.cursor.line += 1;
+                self.cursor.column = 1;
+                self.line_start = self.cursor;
+            } else {
+                self.cursor.column += 1;
+            }
+        }
+
+        self.cursor
+    }
+
+    /// remembers that a tag, comment, or doctype may start at the `<` we
+    /// just consumed, then switches to the given state.
+    fn start_markup(&mut self, state: State) {
+        self.markup_start = self.position(self.pos - 1);
+        self.state = state;
+    }
+
+    /// emits a tag, comment, or doctype that ends here, after any text.
+    fn emit(&mut self, token: Token) {
+        let span = SourceSpan::new(self.markup_start, self.position(self.pos));
+        self.flush_text(span.start);
+        self.tokens.push_back((token, span));
+        self.last_end = span.end;
+    }
+
+    fn flush_text(&mut self, end: SourcePosition) {
+        if !self.text.is_empty() {
+            let text = take(&mut self.text);
+            let span = SourceSpan::new(self.last_end, end);
+            self.tokens.push_back((Token::Text(text), span));
+        }
+    }
+
+    /// emits a parse error here, without ending any text, so the spans of
+    /// text stay whole.
+    fn error(&mut self, code: &'static str) {
+        let span = SourceSpan::at(self.position(self.pos));
+        self.tokens.push_back((Token::Error(code), span));
+    }
+
+    fn emit_eof(&mut self) {
+        let end = self.position(self.pos);
+        self.flush_text(end);
+        self.tokens.push_back((Token::Eof, SourceSpan::at(end)));
+        self.done = true;
+    }
+
+    fn new_tag(&mut self, is_end_tag: bool) {
+        self.tag = Tag::default();
+        self.is_end_tag = is_end_tag;
+        self.attr = None;
+    }
+
+    fn new_attr(&mut self, name: String) {
+        self.finish_attr();
+        self.attr = Some((name, String::new()));
+    }
+
+    /// adds the current attribute to the tag, unless it has one by that name.
+    fn finish_attr(&mut self) {
+        if let Some((name, value)) = self.attr.take() {
+            if self.tag.attrs.iter().any(|(x, _)| *x == name) {
+                self.error("duplicate-attribute");
+            } else {
+                self.tag.attrs.push((name, value));
+            }
+        }
+    }
+
+    fn attr_name(&mut self) -> &mut String {
+        &mut self.attr.get_or_insert_with(Default::default).0
+    }
+
+    fn attr_value(&mut self) -> &mut String {
+        &mut self.attr.get_or_insert_with(Default::default).1
+    }
+
+    fn emit_tag(&mut self) {
+        self.finish_attr();
+        let tag = take(&mut self.tag);
+        if self.is_end_tag {
+            if !tag.attrs.is_empty() {
+                self.error("end-tag-with-attributes");
+            }
+            if tag.self_closing {
+                self.error("end-tag-with-trailing-solidus");
+            }
+            self.emit(Token::EndTag(tag));
+        } else {
+            self.last_start_tag = Some(tag.name.clone());
+            self.emit(Token::StartTag(tag));
+        }
+    }
+
+    fn emit_comment(&mut self) {
+        let comment = take(&mut self.comment);
+        self.emit(Token::Comment(comment));
+    }
+
+    fn emit_doctype(&mut self) {
+        let doctype = take(&mut self.doctype);
+        self.emit(Token::Doctype(doctype));
+    }
+
+    /// emits the doctype with its force-quirks flag set, after `code`.
+    fn emit_quirks_doctype(&mut self, code: &'static str) {
+        self.error(code);
+        self.doctype.force_quirks = true;
+        self.emit_doctype();
+    }
+
+    fn is_appropriate_end_tag(&self) -> bool {
+        self.last_start_tag.as_deref() == Some(&*self.tag.name)
+    }
+
+    fn is_in_attr_value(&self) -> bool {
+        matches!(
+            self.return_state,
+            State::AttributeValueDoubleQuoted
+                | State::AttributeValueSingleQuoted
+                | State::AttributeValueUnquoted
+        )
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#flush-code-points-consumed-as-a-character-reference>
+    fn flush_character_reference(&mut self) {
+        let temp = take(&mut self.temp);
+        if self.is_in_attr_value() {
+            *self.attr_value() += &temp;
+        } else {
+            self.text += &temp;
+        }
+    }
+
+    fn less_than_sign(&mut self, end_tag_open: State, fallback: State) -> Result<(), Wait> {
+        match self.consume()? {
+            Some('/') => {
+                self.temp.clear();
+                self.state = end_tag_open;
+            }
+            c => {
+                self.text.push('<');
+                self.reconsume(c, fallback);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end_tag_open(&mut self, end_tag_name: State, fallback: State) -> Result<(), Wait> {
+        match self.consume()? {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.new_tag(true);
+                self.reconsume(Some(c), end_tag_name);
+            }
+            c => {
+                self.text += "</";
+                self.reconsume(c, fallback);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// the end tag name states for text that can only be ended by the end
+    /// tag of the element it’s in, like `</title>`.
+    fn end_tag_name(&mut self, fallback: State) -> Result<(), Wait> {
+        match self.consume()? {
+            Some(c) if is_space(c) && self.is_appropriate_end_tag() => {
+                self.state = State::BeforeAttributeName;
+            }
+            Some('/') if self.is_appropriate_end_tag() => {
+                self.state = State::SelfClosingStartTag;
+            }
+            Some('>') if self.is_appropriate_end_tag() => {
+                self.state = State::Data;
+                self.emit_tag();
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.tag.name.push(c.to_ascii_lowercase());
+                self.temp.push(c);
+            }
+            c => {
+                self.text += "</";
+                self.text += &self.temp;
+                self.reconsume(c, fallback);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<(), Wait> {
+        match self.state {
+            State::Data => {
+                // plain text is by far the most common case
+                let rest = &self.input[self.pos..];
+                let len = rest.find(['&', '<', '\0', '\r']).unwrap_or(rest.len());
+                if len > 0 {
+                    self.text += &rest[..len];
+                    self.pos += len;
+                    return Ok(());
+                }
+                match self.consume()? {
+                    Some('&') => {
+                        self.return_state = State::Data;
+                        self.state = State::CharacterReference;
+                    }
+                    Some('<') => self.start_markup(State::TagOpen),
+                    Some('\0') => {
+                        self.error("unexpected-null-character");
+                        self.text.push('\0');
+                    }
+                    Some(c) => self.text.push(c),
+                    None => self.emit_eof(),
+                }
+            }
+            State::Rcdata => match self.consume()? {
+                Some('&') => {
+                    self.return_state = State::Rcdata;
+                    self.state = State::CharacterReference;
+                }
+                Some('<') => self.start_markup(State::RcdataLessThanSign),
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => self.text.push(c),
+                None => self.emit_eof(),
+            },
+            State::Rawtext => match self.consume()? {
+                Some('<') => self.start_markup(State::RawtextLessThanSign),
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => self.text.push(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptData => match self.consume()? {
+                Some('<') => self.start_markup(State::ScriptDataLessThanSign),
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => self.text.push(c),
+                None => self.emit_eof(),
+            },
+            State::Plaintext => match self.consume()? {
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => self.text.push(c),
+                None => self.emit_eof(),
+            },
+            State::TagOpen => match self.consume()? {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(false);
+                    self.reconsume(Some(c), State::TagName);
+                }
+                Some('?') => {
+                    self.error("unexpected-question-mark-instead-of-tag-name");
+                    self.comment.clear();
+                    self.reconsume(Some('?'), State::BogusComment);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.text.push('<');
+                    self.emit_eof();
+                }
+                c => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.text.push('<');
+                    self.reconsume(c, State::Data);
+                }
+            },
+            State::EndTagOpen => match self.consume()? {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume(Some(c), State::TagName);
+                }
+                Some('>') => {
+                    self.error("missing-end-tag-name");
+                    self.state = State::Data;
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.text += "</";
+                    self.emit_eof();
+                }
+                c => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.comment.clear();
+                    self.reconsume(c, State::BogusComment);
+                }
+            },
+            State::TagName => match self.consume()? {
+                Some(c) if is_space(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.tag.name.push('\u{FFFD}');
+                }
+                Some(c) => self.tag.name.push(c.to_ascii_lowercase()),
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::RcdataLessThanSign => {
+                self.less_than_sign(State::RcdataEndTagOpen, State::Rcdata)?
+            }
+            State::RcdataEndTagOpen => self.end_tag_open(State::RcdataEndTagName, State::Rcdata)?,
+            State::RcdataEndTagName => self.end_tag_name(State::Rcdata)?,
+            State::RawtextLessThanSign => {
+                self.less_than_sign(State::RawtextEndTagOpen, State::Rawtext)?
+            }
+            State::RawtextEndTagOpen => {
+                self.end_tag_open(State::RawtextEndTagName, State::Rawtext)?
+            }
+            State::RawtextEndTagName => self.end_tag_name(State::Rawtext)?,
+            State::ScriptDataLessThanSign => match self.consume()? {
+                Some('/') => {
+                    self.temp.clear();
+                    self.state = State::ScriptDataEndTagOpen;
+                }
+                Some('!') => {
+                    self.state = State::ScriptDataEscapeStart;
+                    self.text += "<!";
+                }
+                c => {
+                    self.text.push('<');
+                    self.reconsume(c, State::ScriptData);
+                }
+            },
+            State::ScriptDataEndTagOpen => {
+                self.end_tag_open(State::ScriptDataEndTagName, State::ScriptData)?
+            }
+            State::ScriptDataEndTagName => self.end_tag_name(State::ScriptData)?,
+            State::ScriptDataEscapeStart => match self.consume()? {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapeStartDash;
+                    self.text.push('-');
+                }
+                c => self.reconsume(c, State::ScriptData),
+            },
+            State::ScriptDataEscapeStartDash => match self.consume()? {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.text.push('-');
+                }
+                c => self.reconsume(c, State::ScriptData),
+            },
+            State::ScriptDataEscaped => match self.consume()? {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDash;
+                    self.text.push('-');
+                }
+                Some('<') => self.start_markup(State::ScriptDataEscapedLessThanSign),
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => self.text.push(c),
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDash => match self.consume()? {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.text.push('-');
+                }
+                Some('<') => self.start_markup(State::ScriptDataEscapedLessThanSign),
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataEscaped;
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.text.push(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDashDash => match self.consume()? {
+                Some('-') => self.text.push('-'),
+                Some('<') => self.start_markup(State::ScriptDataEscapedLessThanSign),
+                Some('>') => {
+                    self.state = State::ScriptData;
+                    self.text.push('>');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataEscaped;
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.text.push(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedLessThanSign => match self.consume()? {
+                Some('/') => {
+                    self.temp.clear();
+                    self.state = State::ScriptDataEscapedEndTagOpen;
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp.clear();
+                    self.text.push('<');
+                    self.reconsume(Some(c), State::ScriptDataDoubleEscapeStart);
+                }
+                c => {
+                    self.text.push('<');
+                    self.reconsume(c, State::ScriptDataEscaped);
+                }
+            },
+            State::ScriptDataEscapedEndTagOpen => {
+                self.end_tag_open(State::ScriptDataEscapedEndTagName, State::ScriptDataEscaped)?
+            }
+            State::ScriptDataEscapedEndTagName => self.end_tag_name(State::ScriptDataEscaped)?,
+            State::ScriptDataDoubleEscapeStart => match self.consume()? {
+                Some(c) if is_space(c) || c == '/' || c == '>' => {
+                    self.state = match &*self.temp {
+                        "script" => State::ScriptDataDoubleEscaped,
+                        _ => State::ScriptDataEscaped,
+                    };
+                    self.text.push(c);
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp.push(c.to_ascii_lowercase());
+                    self.text.push(c);
+                }
+                c => self.reconsume(c, State::ScriptDataEscaped),
+            },
+            State::ScriptDataDoubleEscaped => match self.consume()? {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDash;
+                    self.text.push('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.text.push('<');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => self.text.push(c),
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedDash => match self.consume()? {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDashDash;
+                    self.text.push('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.text.push('<');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.text.push(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedDashDash => match self.consume()? {
+                Some('-') => self.text.push('-'),
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.text.push('<');
+                }
+                Some('>') => {
+                    self.state = State::ScriptData;
+                    self.text.push('>');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.text.push('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.text.push(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedLessThanSign => match self.consume()? {
+                Some('/') => {
+                    self.temp.clear();
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                    self.text.push('/');
+                }
+                c => self.reconsume(c, State::ScriptDataDoubleEscaped),
+            },
+            State::ScriptDataDoubleEscapeEnd => match self.consume()? {
+                Some(c) if is_space(c) || c == '/' || c == '>' => {
+                    self.state = match &*self.temp {
+                        "script" => State::ScriptDataEscaped,
+                        _ => State::ScriptDataDoubleEscaped,
+                    };
+                    self.text.push(c);
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp.push(c.to_ascii_lowercase());
+                    self.text.push(c);
+                }
+                c => self.reconsume(c, State::ScriptDataDoubleEscaped),
+            },
+            State::BeforeAttributeName => match self.consume()? {
+                Some(c) if is_space(c) => {}
+                c @ (Some('/' | '>') | None) => self.reconsume(c, State::AfterAttributeName),
+                Some('=') => {
+                    self.error("unexpected-equals-sign-before-attribute-name");
+                    self.new_attr("=".to_owned());
+                    self.state = State::AttributeName;
+                }
+                c => {
+                    self.new_attr(String::new());
+                    self.reconsume(c, State::AttributeName);
+                }
+            },
+            State::AttributeName => match self.consume()? {
+                Some(c) if is_space(c) => self.state = State::AfterAttributeName,
+                c @ (Some('/' | '>') | None) => self.reconsume(c, State::AfterAttributeName),
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.attr_name().push('\u{FFFD}');
+                }
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<') {
+                        self.error("unexpected-character-in-attribute-name");
+                    }
+                    self.attr_name().push(c.to_ascii_lowercase());
+                }
+            },
+            State::AfterAttributeName => match self.consume()? {
+                Some(c) if is_space(c) => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+                c => {
+                    self.new_attr(String::new());
+                    self.reconsume(c, State::AttributeName);
+                }
+            },
+            State::BeforeAttributeValue => match self.consume()? {
+                Some(c) if is_space(c) => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => {
+                    self.error("missing-attribute-value");
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                c => self.reconsume(c, State::AttributeValueUnquoted),
+            },
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                let quote = match self.state {
+                    State::AttributeValueDoubleQuoted => '"',
+                    _ => '\'',
+                };
+                match self.consume()? {
+                    Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+                    Some('&') => {
+                        self.return_state = self.state;
+                        self.state = State::CharacterReference;
+                    }
+                    Some('\0') => {
+                        self.error("unexpected-null-character");
+                        self.attr_value().push('\u{FFFD}');
+                    }
+                    Some(c) => self.attr_value().push(c),
+                    None => {
+                        self.error("eof-in-tag");
+                        self.emit_eof();
+                    }
+                }
+            }
+            State::AttributeValueUnquoted => match self.consume()? {
+                Some(c) if is_space(c) => self.state = State::BeforeAttributeName,
+                Some('&') => {
+                    self.return_state = State::AttributeValueUnquoted;
+                    self.state = State::CharacterReference;
+                }
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.attr_value().push('\u{FFFD}');
+                }
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<' | '=' | '`') {
+                        self.error("unexpected-character-in-unquoted-attribute-value");
+                    }
+                    self.attr_value().push(c);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::AfterAttributeValueQuoted => match self.consume()? {
+                Some(c) if is_space(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+                c => {
+                    self.error("missing-whitespace-between-attributes");
+                    self.reconsume(c, State::BeforeAttributeName);
+                }
+            },
+            State::SelfClosingStartTag => match self.consume()? {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+                c => {
+                    self.error("unexpected-solidus-in-tag");
+                    self.reconsume(c, State::BeforeAttributeName);
+                }
+            },
+            State::BogusComment => match self.consume()? {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.comment.push('\u{FFFD}');
+                }
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::MarkupDeclarationOpen => {
+                if self.consume_if("--", false)? {
+                    self.comment.clear();
+                    self.state = State::CommentStart;
+                } else if self.consume_if("DOCTYPE", true)? {
+                    self.state = State::Doctype;
+                } else if self.consume_if("[CDATA[", false)? {
+                    if self.allow_cdata {
+                        self.state = State::CdataSection;
+                    } else {
+                        self.error("cdata-in-html-content");
+                        self.comment = "[CDATA[".to_owned();
+                        self.state = State::BogusComment;
+                    }
+                } else {
+                    self.error("incorrectly-opened-comment");
+                    self.comment.clear();
+                    self.state = State::BogusComment;
+                }
+            }
+            State::CommentStart => match self.consume()? {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                c => self.reconsume(c, State::Comment),
+            },
+            State::CommentStartDash => match self.consume()? {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                c => {
+                    self.comment.push('-');
+                    self.reconsume(c, State::Comment);
+                }
+            },
+            State::Comment => match self.consume()? {
+                Some('<') => {
+                    self.comment.push('<');
+                    self.state = State::CommentLessThanSign;
+                }
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.comment.push('\u{FFFD}');
+                }
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentLessThanSign => match self.consume()? {
+                Some('!') => {
+                    self.comment.push('!');
+                    self.state = State::CommentLessThanSignBang;
+                }
+                Some('<') => self.comment.push('<'),
+                c => self.reconsume(c, State::Comment),
+            },
+            State::CommentLessThanSignBang => match self.consume()? {
+                Some('-') => self.state = State::CommentLessThanSignBangDash,
+                c => self.reconsume(c, State::Comment),
+            },
+            State::CommentLessThanSignBangDash => match self.consume()? {
+                Some('-') => self.state = State::CommentLessThanSignBangDashDash,
+                c => self.reconsume(c, State::CommentEndDash),
+            },
+            State::CommentLessThanSignBangDashDash => match self.consume()? {
+                c @ (Some('>') | None) => self.reconsume(c, State::CommentEnd),
+                c => {
+                    self.error("nested-comment");
+                    self.reconsume(c, State::CommentEnd);
+                }
+            },
+            State::CommentEndDash => match self.consume()? {
+                Some('-') => self.state = State::CommentEnd,
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                c => {
+                    self.comment.push('-');
+                    self.reconsume(c, State::Comment);
+                }
+            },
+            State::CommentEnd => match self.consume()? {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                c => {
+                    self.comment += "--";
+                    self.reconsume(c, State::Comment);
+                }
+            },
+            State::CommentEndBang => match self.consume()? {
+                Some('-') => {
+                    self.comment += "--!";
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.error("incorrectly-closed-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                c => {
+                    self.comment += "--!";
+                    self.reconsume(c, State::Comment);
+                }
+            },
+            State::Doctype => match self.consume()? {
+                Some(c) if is_space(c) => self.state = State::BeforeDoctypeName,
+                c @ Some('>') => self.reconsume(c, State::BeforeDoctypeName),
+                None => {
+                    self.doctype = Doctype::default();
+                    self.emit_quirks_doctype("eof-in-doctype");
+                    self.emit_eof();
+                }
+                c => {
+                    self.error("missing-whitespace-before-doctype-name");
+                    self.reconsume(c, State::BeforeDoctypeName);
+                }
+            },
+            State::BeforeDoctypeName => match self.consume()? {
+                Some(c) if is_space(c) => {}
+                Some('>') => {
+                    self.doctype = Doctype::default();
+                    self.state = State::Data;
+                    self.emit_quirks_doctype("missing-doctype-name");
+                }
+                None => {
+                    self.doctype = Doctype::default();
+                    self.emit_quirks_doctype("eof-in-doctype");
+                    self.emit_eof();
+                }
+                Some(c) => {
+                    let c = match c {
+                        '\0' => {
+                            self.error("unexpected-null-character");
+                            '\u{FFFD}'
+                        }
+                        c => c.to_ascii_lowercase(),
+                    };
+                    self.doctype = Doctype {
+                        name: Some(c.to_string()),
+                        ..Default::default()
+                    };
+                    self.state = State::DoctypeName;
+                }
+            },
+            State::DoctypeName => match self.consume()? {
+                Some(c) if is_space(c) => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.doctype
+                        .name
+                        .get_or_insert_with(String::new)
+                        .push('\u{FFFD}');
+                }
+                Some(c) => {
+                    let name = self.doctype.name.get_or_insert_with(String::new);
+                    name.push(c.to_ascii_lowercase());
+                }
+                None => {
+                    self.emit_quirks_doctype("eof-in-doctype");
+                    self.emit_eof();
+                }
+            },
+            State::AfterDoctypeName => {
+                if self.consume_if("PUBLIC", true)? {
+                    self.state = State::AfterDoctypePublicKeyword;
+                    return Ok(());
+                }
+                if self.consume_if("SYSTEM", true)? {
+                    self.state = State::AfterDoctypeSystemKeyword;
+                    return Ok(());
+                }
+                match self.consume()? {
+                    Some(c) if is_space(c) => {}
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_doctype();
+                    }
+                    None => {
+                        self.emit_quirks_doctype("eof-in-doctype");
+                        self.emit_eof();
+                    }
+                    c => {
+                        self.error("invalid-character-sequence-after-doctype-name");
+                        self.doctype.force_quirks = true;
+                        self.reconsume(c, State::BogusDoctype);
+                    }
+                }
+            }
+            State::AfterDoctypePublicKeyword | State::BeforeDoctypePublicIdentifier => {
+                let after_keyword = self.state == State::AfterDoctypePublicKeyword;
+                match self.consume()? {
+                    Some(c) if is_space(c) => self.state = State::BeforeDoctypePublicIdentifier,
+                    Some(quote @ ('"' | '\'')) => {
+                        if after_keyword {
+                            self.error("missing-whitespace-after-doctype-public-keyword");
+                        }
+                        self.doctype.public_id = Some(String::new());
+                        self.state = match quote {
+                            '"' => State::DoctypePublicIdentifierDoubleQuoted,
+                            _ => State::DoctypePublicIdentifierSingleQuoted,
+                        };
+                    }
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_quirks_doctype("missing-doctype-public-identifier");
+                    }
+                    None => {
+                        self.emit_quirks_doctype("eof-in-doctype");
+                        self.emit_eof();
+                    }
+                    c => {
+                        self.error("missing-quote-before-doctype-public-identifier");
+                        self.doctype.force_quirks = true;
+                        self.reconsume(c, State::BogusDoctype);
+                    }
+                }
+            }
+            State::DoctypePublicIdentifierDoubleQuoted
+            | State::DoctypePublicIdentifierSingleQuoted => {
+                let quote = match self.state {
+                    State::DoctypePublicIdentifierDoubleQuoted => '"',
+                    _ => '\'',
+                };
+                match self.consume()? {
+                    Some(c) if c == quote => self.state = State::AfterDoctypePublicIdentifier,
+                    Some('\0') => {
+                        self.error("unexpected-null-character");
+                        self.doctype
+                            .public_id
+                            .get_or_insert_with(String::new)
+                            .push('\u{FFFD}');
+                    }
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_quirks_doctype("abrupt-doctype-public-identifier");
+                    }
+                    Some(c) => self
+                        .doctype
+                        .public_id
+                        .get_or_insert_with(String::new)
+                        .push(c),
+                    None => {
+                        self.emit_quirks_doctype("eof-in-doctype");
+                        self.emit_eof();
+                    }
+                }
+            }
+            State::AfterDoctypePublicIdentifier
+            | State::BetweenDoctypePublicAndSystemIdentifiers => {
+                let after_identifier = self.state == State::AfterDoctypePublicIdentifier;
+                match self.consume()? {
+                    Some(c) if is_space(c) => {
+                        self.state = State::BetweenDoctypePublicAndSystemIdentifiers;
+                    }
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_doctype();
+                    }
+                    Some(quote @ ('"' | '\'')) => {
+                        if after_identifier {
+                            self.error(
+                                "missing-whitespace-between-doctype-public-and-system-identifiers",
+                            );
+                        }
+                        self.doctype.system_id = Some(String::new());
+                        self.state = match quote {
+                            '"' => State::DoctypeSystemIdentifierDoubleQuoted,
+                            _ => State::DoctypeSystemIdentifierSingleQuoted,
+                        };
+                    }
+                    None => {
+                        self.emit_quirks_doctype("eof-in-doctype");
+                        self.emit_eof();
+                    }
+                    c => {
+                        self.error("missing-quote-before-doctype-system-identifier");
+                        self.doctype.force_quirks = true;
+                        self.reconsume(c, State::BogusDoctype);
+                    }
+                }
+            }
+            State::AfterDoctypeSystemKeyword | State::BeforeDoctypeSystemIdentifier => {
+                let after_keyword = self.state == State::AfterDoctypeSystemKeyword;
+                match self.consume()? {
+                    Some(c) if is_space(c) => self.state = State::BeforeDoctypeSystemIdentifier,
+                    Some(quote @ ('"' | '\'')) => {
+                        if after_keyword {
+                            self.error("missing-whitespace-after-doctype-system-keyword");
+                        }
+                        self.doctype.system_id = Some(String::new());
+                        self.state = match quote {
+                            '"' => State::DoctypeSystemIdentifierDoubleQuoted,
+                            _ => State::DoctypeSystemIdentifierSingleQuoted,
+                        };
+                    }
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_quirks_doctype("missing-doctype-system-identifier");
+                    }
+                    None => {
+                        self.emit_quirks_doctype("eof-in-doctype");
+                        self.emit_eof();
+                    }
+                    c => {
+                        self.error("missing-quote-before-doctype-system-identifier");
+                        self.doctype.force_quirks = true;
+                        self.reconsume(c, State::BogusDoctype);
+                    }
+                }
+            }
+            State::DoctypeSystemIdentifierDoubleQuoted
+            | State::DoctypeSystemIdentifierSingleQuoted => {
+                let quote = match self.state {
+                    State::DoctypeSystemIdentifierDoubleQuoted => '"',
+                    _ => '\'',
+                };
+                match self.consume()? {
+                    Some(c) if c == quote => self.state = State::AfterDoctypeSystemIdentifier,
+                    Some('\0') => {
+                        self.error("unexpected-null-character");
+                        self.doctype
+                            .system_id
+                            .get_or_insert_with(String::new)
+                            .push('\u{FFFD}');
+                    }
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_quirks_doctype("abrupt-doctype-system-identifier");
+                    }
+                    Some(c) => self
+                        .doctype
+                        .system_id
+                        .get_or_insert_with(String::new)
+                        .push(c),
+                    None => {
+                        self.emit_quirks_doctype("eof-in-doctype");
+                        self.emit_eof();
+                    }
+                }
+            }
+            State::AfterDoctypeSystemIdentifier => match self.consume()? {
+                Some(c) if is_space(c) => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.emit_quirks_doctype("eof-in-doctype");
+                    self.emit_eof();
+                }
+                c => {
+                    // unlike the other errors in doctypes, this one isn’t quirky
+                    self.error("unexpected-character-after-doctype-system-identifier");
+                    self.reconsume(c, State::BogusDoctype);
+                }
+            },
+            State::BogusDoctype => match self.consume()? {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some('\0') => self.error("unexpected-null-character"),
+                Some(_) => {}
+                None => {
+                    self.emit_doctype();
+                    self.emit_eof();
+                }
+            },
+            State::CdataSection => match self.consume()? {
+                Some(']') => self.state = State::CdataSectionBracket,
+                Some(c) => self.text.push(c),
+                None => {
+                    self.error("eof-in-cdata");
+                    self.emit_eof();
+                }
+            },
+            State::CdataSectionBracket => match self.consume()? {
+                Some(']') => self.state = State::CdataSectionEnd,
+                c => {
+                    self.text.push(']');
+                    self.reconsume(c, State::CdataSection);
+                }
+            },
+            State::CdataSectionEnd => match self.consume()? {
+                Some(']') => self.text.push(']'),
+                Some('>') => self.state = State::Data,
+                c => {
+                    self.text += "]]";
+                    self.reconsume(c, State::CdataSection);
+                }
+            },
+            State::CharacterReference => {
+                self.temp = "&".to_owned();
+                match self.consume()? {
+                    Some(c) if c.is_ascii_alphanumeric() => {
+                        self.reconsume(Some(c), State::NamedCharacterReference);
+                    }
+                    Some('#') => {
+                        self.temp.push('#');
+                        self.state = State::NumericCharacterReference;
+                    }
+                    c => {
+                        self.flush_character_reference();
+                        self.reconsume(c, self.return_state);
+                    }
+                }
+            }
+            State::NamedCharacterReference => {
+                // names are alphanumeric, so we know the longest possible name
+                // once we see anything else
+                let rest = &self.input[self.pos..];
+                let len = rest.find(|c: char| !c.is_ascii_alphanumeric());
+                let Some(len) = len.or(self.eof.then_some(rest.len())) else {
+                    return Err(Wait);
+                };
+                let len = len + rest[len..].starts_with(';') as usize;
+                match named_character_reference(&format!("&{}", &rest[..len])) {
+                    Some((name, value)) => {
+                        self.pos += name.len() - 1;
+                        let next = self.input[self.pos..].chars().next();
+                        let legacy = !name.ends_with(';');
+
+                        // for compatibility, attribute values like `?a=1&copy=2` stay as is
+                        if legacy
+                            && self.is_in_attr_value()
+                            && next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric())
+                        {
+                            self.temp = name.to_owned();
+                        } else {
+                            if legacy {
+                                self.error("missing-semicolon-after-character-reference");
+                            }
+                            self.temp = value.to_owned();
+                        }
+                        self.flush_character_reference();
+                        self.state = self.return_state;
+                    }
+                    None => {
+                        self.flush_character_reference();
+                        self.state = State::AmbiguousAmpersand;
+                    }
+                }
+            }
+            State::AmbiguousAmpersand => match self.consume()? {
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    if self.is_in_attr_value() {
+                        self.attr_value().push(c);
+                    } else {
+                        self.text.push(c);
+                    }
+                }
+                c @ Some(';') => {
+                    self.error("unknown-named-character-reference");
+                    self.reconsume(c, self.return_state);
+                }
+                c => self.reconsume(c, self.return_state),
+            },
+            State::NumericCharacterReference => {
+                self.char_ref_code = 0;
+                match self.consume()? {
+                    Some(c @ ('x' | 'X')) => {
+                        self.temp.push(c);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                    }
+                    c => self.reconsume(c, State::DecimalCharacterReferenceStart),
+                }
+            }
+            State::HexadecimalCharacterReferenceStart => match self.consume()? {
+                Some(c) if c.is_ascii_hexdigit() => {
+                    self.reconsume(Some(c), State::HexadecimalCharacterReference);
+                }
+                c => {
+                    self.error("absence-of-digits-in-numeric-character-reference");
+                    self.flush_character_reference();
+                    self.reconsume(c, self.return_state);
+                }
+            },
+            State::DecimalCharacterReferenceStart => match self.consume()? {
+                Some(c) if c.is_ascii_digit() => {
+                    self.reconsume(Some(c), State::DecimalCharacterReference);
+                }
+                c => {
+                    self.error("absence-of-digits-in-numeric-character-reference");
+                    self.flush_character_reference();
+                    self.reconsume(c, self.return_state);
+                }
+            },
+            State::HexadecimalCharacterReference | State::DecimalCharacterReference => {
+                let radix = match self.state {
+                    State::HexadecimalCharacterReference => 16,
+                    _ => 10,
+                };
+                match self.consume()? {
+                    Some(c) if c.is_digit(radix) => {
+                        // saturate, so huge references stay outside unicode
+                        self.char_ref_code = self
+                            .char_ref_code
+                            .saturating_mul(radix)
+                            .saturating_add(c.to_digit(radix).unwrap());
+                    }
+                    Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                    c => {
+                        self.error("missing-semicolon-after-character-reference");
+                        self.reconsume(c, State::NumericCharacterReferenceEnd);
+                    }
+                }
+            }
+            State::NumericCharacterReferenceEnd => {
+                let (c, error) = numeric_character_reference(self.char_ref_code);
+                if let Some(error) = error {
+                    self.error(error);
+                }
+                self.temp = c.to_string();
+                self.flush_character_reference();
+                self.state = self.return_state;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iterator for Tokenizer {
+    type Item = (Token, SourceSpan);
+
+    /// the next token, or None if there’s no more until more input arrives.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token()
+    }
+}
+
+/// <https://infra.spec.whatwg.org/#ascii-whitespace>, but without carriage
+/// returns, since those are gone by the time the tokenizer sees the input.
+fn is_space(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | ' ')
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_tokenizer() {
+    fn tokenize_with_spans(pieces: &[&str]) -> Vec<(Token, SourceSpan)> {
+        let mut tokenizer = Tokenizer::new();
+        let mut result = vec![];
+        for piece in pieces {
+            tokenizer.push(piece);
+            result.extend(&mut tokenizer);
+        }
+        tokenizer.end();
+        result.extend(&mut tokenizer);
+
+        result
+    }
+    fn tokenize(pieces: &[&str]) -> Vec<Token> {
+        tokenize_with_spans(pieces).into_iter().map(|(token, _)| token).collect()
+    }
+    fn tag(name: &str, attrs: &[(&str, &str)]) -> Tag {
+        let attrs = attrs.iter().map(|&(n, v)| (n.to_owned(), v.to_owned())).collect();
+        Tag { name: name.to_owned(), attrs, self_closing: false }
+    }
+    use Token::*;
+    let text = |x: &str| Text(x.to_owned());
+    let comment = |x: &str| Comment(x.to_owned());
+
+    let html = "<!DOCTYPE html><P Class=a title='b>c' data-x=&amp;y&ampz=1 hidden>x &lt;3 &amp &eacutez &nosuch; a < b</p x><br/><!-- a -- b --><?php ?>";
+    let expected = vec![
+        Doctype(super::Doctype { name: Some("html".to_owned()), ..Default::default() }),
+        Error("unexpected-character-in-unquoted-attribute-value"),
+        StartTag(tag("p", &[("class", "a"), ("title", "b>c"), ("data-x", "&y&ampz=1"), ("hidden", "")])),
+        Error("missing-semicolon-after-character-reference"),
+        Error("missing-semicolon-after-character-reference"),
+        Error("unknown-named-character-reference"),
+        Error("invalid-first-character-of-tag-name"),
+        Error("end-tag-with-attributes"),
+        text("x <3 & éz &nosuch; a < b"),
+        EndTag(tag("p", &[("x", "")])),
+        StartTag(Tag { self_closing: true, ..tag("br", &[]) }),
+        comment(" a -- b "), Error("unexpected-question-mark-instead-of-tag-name"),
+        comment("?php ?"),
+        Eof,
+    ];
+    assert_eq!(tokenize(&[html]), expected);
+
+    // splitting the input anywhere gives the same tokens
+    for i in 0..=html.len() {
+        if html.is_char_boundary(i) {
+            assert_eq!(tokenize(&[&html[..i], &html[i..]]), expected, "split at {}", i);
+        }
+    }
+
+    // numeric character references, in text and attribute values
+    let html = "&#169; &#x1F600;&#X41&#128;&#0;&#xD800;&#x110000;&#;<a title='&#65;&#x'>";
+    let expected = vec![
+        Error("missing-semicolon-after-character-reference"),
+        Error("control-character-reference"),
+        Error("null-character-reference"),
+        Error("surrogate-character-reference"),
+        Error("character-reference-outside-unicode-range"),
+        Error("absence-of-digits-in-numeric-character-reference"),
+        Error("absence-of-digits-in-numeric-character-reference"),
+        text("© 😀A€\u{FFFD}\u{FFFD}\u{FFFD}&#;"),
+        StartTag(tag("a", &[("title", "A&#x")])),
+        Eof,
+    ];
+    for i in 0..=html.len() {
+        if html.is_char_boundary(i) {
+            assert_eq!(tokenize(&[&html[..i], &html[i..]]), expected, "split at {}", i);
+        }
+    }
+
+    // spans are byte offsets into the original input, even when split
+    let html = "<p class=a>x &amp;\r\ny</p>\r<!-- c -->é<br>";
+    let span = |(a, b, c), (d, e, f)| SourceSpan::new(
+        SourcePosition { offset: a, line: b, column: c },
+        SourcePosition { offset: d, line: e, column: f },
+    );
+    let expected = vec![
+        (StartTag(tag("p", &[("class", "a")])), span((0, 1, 1), (11, 1, 12))),
+        (text("x &\ny"), span((11, 1, 12), (21, 2, 2))),
+        (EndTag(tag("p", &[])), span((21, 2, 2), (25, 2, 6))),
+        (text("\n"), span((25, 2, 6), (26, 3, 1))),
+        (comment(" c "), span((26, 3, 1), (36, 3, 11))),
+        (text("é"), span((36, 3, 11), (38, 3, 12))),
+        (StartTag(tag("br", &[])), span((38, 3, 12), (42, 3, 16))),
+        (Eof, span((42, 3, 16), (42, 3, 16))),
+    ];
+    for i in 0..=html.len() {
+        if html.is_char_boundary(i) {
+            assert_eq!(tokenize_with_spans(&[&html[..i], &html[i..]]), expected, "split at {}", i);
+        }
+    }
+    assert_eq!(tokenize_with_spans(&["a &amp b"])[0], (Error("missing-semicolon-after-character-reference"), span((6, 1, 7), (6, 1, 7))));
+
+    // newlines are normalised, even when split
+    assert_eq!(tokenize(&["a\r\nb\r", "\nc\r", "", "d"]), [text("a\nb\nc\nd"), Eof]);
+
+    // the tree builder switches states for elements with special text
+    let mut tokenizer = Tokenizer::new();
+    tokenizer.push("<title>a <b> &amp; </title ></title><script>if (a<!--b) {}</script>");
+    tokenizer.end();
+    let mut tokens = vec![];
+    while let Some((token, _)) = tokenizer.next_token() {
+        match &token {
+            StartTag(x) if x.name == "title" => tokenizer.set_state(State::Rcdata),
+            StartTag(x) if x.name == "script" => tokenizer.set_state(State::ScriptData),
+            _ => {}
+        }
+        tokens.push(token);
+    }
+    assert_eq!(tokens, [
+        StartTag(tag("title", &[])), text("a <b> & "), EndTag(tag("title", &[])), EndTag(tag("title", &[])),
+        StartTag(tag("script", &[])), text("if (a<!--b) {}"), EndTag(tag("script", &[])), Eof,
+    ]);
+
+    // unfinished things at the end of the input
+    assert_eq!(tokenize(&["a<b c"]), [Error("eof-in-tag"), text("a"), Eof]);
+    assert_eq!(tokenize(&["<!--a"]), [Error("eof-in-comment"), comment("a"), Eof]);
+    assert_eq!(tokenize(&["<!doctype"])[..2], [Error("eof-in-doctype"), Doctype(super::Doctype { force_quirks: true, ..Default::default() })]);
+    assert_eq!(tokenize(&["<![CDATA[x]]>"]), [Error("cdata-in-html-content"), comment("[CDATA[x]]"), Eof]);
+}
@@ -0,0 +1,321 @@
+use std::{mem::take, str};
+
+use encoding_rs::{Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+use tracing::{debug, instrument, warn};
+
+/// how far into the document we look for a `<meta>` declaring the encoding.
+const PRESCAN_LIMIT: usize = 1024;
+
+/// decodes an html document, returning the text and the encoding used.
+#[instrument(skip(body))]
+pub fn decode_html(body: &[u8], transport_charset: Option<&str>) -> (String, &'static Encoding) {
+    let encoding = sniff_html_encoding(body, transport_charset);
+    let (text, had_errors) = encoding.decode_with_bom_removal(body);
+    if had_errors {
+        warn!(encoding = encoding.name(), "malformed input replaced");
+    }
+
+    (text.into_owned(), encoding)
+}
+
+/// decodes an html document that arrives in pieces, like [`decode_html`],
+/// but the encoding is sniffed from the start of the document only.
+pub struct HtmlDecoder {
+    transport_charset: Option<String>,
+    /// input we haven’t decoded yet, because we don’t know the encoding.
+    buffer: Vec<u8>,
+    decoder: Option<Decoder>,
+}
+
+impl HtmlDecoder {
+    pub fn new(transport_charset: Option<&str>) -> Self {
+        Self {
+            transport_charset: transport_charset.map(|x| x.to_owned()),
+            buffer: vec![],
+            decoder: None,
+        }
+    }
+
+    /// the encoding, once enough of the document has arrived to sniff it.
+    pub fn encoding(&self) -> Option<&'static Encoding> {
+        self.decoder.as_ref().map(|x| x.encoding())
+    }
+
+    /// decodes the next piece of input, which can end anywhere, even in the
+    /// middle of a character. returns whatever text is ready so far.
+    pub fn push(&mut self, input: &[u8], last: bool) -> String {
+        self.buffer.extend_from_slice(input);
+        if self.decoder.is_none() {
+            if self.buffer.len() < PRESCAN_LIMIT && !last {
+                return String::new();
+            }
+            let encoding = sniff(&self.buffer, self.transport_charset.as_deref(), last);
+            self.decoder = Some(encoding.new_decoder_with_bom_removal());
+        }
+
+        let decoder = self.decoder.as_mut().unwrap();
+        let input = take(&mut self.buffer);
+        let capacity = decoder.max_utf8_buffer_length(input.len());
+        let mut result = String::with_capacity(capacity.expect("input too long"));
+        let (_, _, had_errors) = decoder.decode_to_string(&input, &mut result, last);
+        if had_errors {
+            warn!(
+                encoding = decoder.encoding().name(),
+                "malformed input replaced"
+            );
+        }
+
+        result
+    }
+}
+
+/// <https://html.spec.whatwg.org/#encoding-sniffing-algorithm>
+#[instrument(skip(body))]
+pub fn sniff_html_encoding(body: &[u8], transport_charset: Option<&str>) -> &'static Encoding {
+    sniff(body, transport_charset, true)
+}
+
+/// sniffs the encoding from the given body, which may be only the start of
+/// the document if `complete` is false.
+fn sniff(body: &[u8], transport_charset: Option<&str>, complete: bool) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        debug!(encoding = encoding.name(), "found bom");
+        return encoding;
+    }
+    if let Some(encoding) = transport_charset.and_then(|x| Encoding::for_label(x.as_bytes())) {
+        debug!(encoding = encoding.name(), "found transport charset");
+        return encoding;
+    }
+    if let Some(encoding) = prescan(&body[..body.len().min(PRESCAN_LIMIT)]) {
+        debug!(encoding = encoding.name(), "found meta charset");
+        return encoding;
+    }
+
+    // the spec allows autodetection here, and valid utf-8 is rarely anything else.
+    // otherwise windows-1252, like browsers in most locales
+    match str::from_utf8(body) {
+        Ok(_) => UTF_8,
+        // the start of a document may end in the middle of a character
+        Err(error) if !complete && error.error_len().is_none() => UTF_8,
+        Err(_) => WINDOWS_1252,
+    }
+}
+
+/// <https://html.spec.whatwg.org/#prescan-a-byte-stream-to-determine-its-encoding>
+fn prescan(input: &[u8]) -> Option<&'static Encoding> {
+    let mut position = 0;
+    while position < input.len() {
+        let rest = &input[position..];
+        if rest.starts_with(b"<!--") {
+            // the dashes of <!-- can double as the dashes of -->
+            let end = find(&input[position + 2..], b"-->")?;
+            position += 2 + end + 2;
+        } else if starts_with_ignore_case(rest, b"<meta")
+            && rest.get(5).is_some_and(|x| is_space(*x) || *x == b'/')
+        {
+            position += 5;
+            let mut names = vec![];
+            let mut got_pragma = false;
+            let mut need_pragma = None;
+            let mut charset = None;
+            while let Some((name, value)) = get_attribute(input, &mut position)? {
+                if names.contains(&name) {
+                    continue;
+                }
+                match &*name {
+                    b"http-equiv" => got_pragma |= value == b"content-type",
+                    b"content" if charset.is_none() => {
+                        charset = extract_charset_from_meta(&value);
+                        if charset.is_some() {
+                            need_pragma = Some(true);
+                        }
+                    }
+                    b"charset" if charset.is_none() => {
+                        charset = Encoding::for_label(&value);
+                        need_pragma = Some(false);
+                    }
+                    _ => {}
+                }
+                names.push(name);
+            }
+            // a charset in content only counts with http-equiv=content-type
+            let declared = match need_pragma {
+                Some(true) if !got_pragma => None,
+                Some(_) => charset,
+                None => None,
+            };
+            if let Some(encoding) = declared {
+                if encoding == UTF_16BE || encoding == UTF_16LE {
+                    return Some(UTF_8);
+                }
+                if encoding == X_USER_DEFINED {
+                    return Some(WINDOWS_1252);
+                }
+                return Some(encoding);
+            }
+        } else if rest.len() >= 2
+            && rest[0] == b'<'
+            && (rest[1].is_ascii_alphabetic()
+                || rest[1] == b'/' && rest.get(2).is_some_and(|x| x.is_ascii_alphabetic()))
+        {
+            // skip the tag name, then any attributes
+            position += rest
+                .iter()
+                .position(|x| is_space(*x) || *x == b'>')
+                .unwrap_or(rest.len());
+            while get_attribute(input, &mut position)?.is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position += find(rest, b">")?;
+        }
+        position += 1;
+    }
+
+    None
+}
+
+/// <https://html.spec.whatwg.org/#concept-get-attributes-when-sniffing>
+/// returns None at the end of the input, or Some(None) at the end of the tag.
+fn get_attribute(input: &[u8], position: &mut usize) -> Option<Option<(Vec<u8>, Vec<u8>)>> {
+    let byte = |position: usize| input.get(position).copied();
+    while is_space(byte(*position)?) || byte(*position)? == b'/' {
+        *position += 1;
+    }
+    if byte(*position)? == b'>' {
+        return Some(None);
+    }
+
+    let mut name = vec![];
+    let mut value = vec![];
+    loop {
+        match byte(*position)? {
+            b'=' if !name.is_empty() => {
+                *position += 1;
+                break;
+            }
+            x if is_space(x) => {
+                while is_space(byte(*position)?) {
+                    *position += 1;
+                }
+                if byte(*position)? != b'=' {
+                    return Some(Some((name, value)));
+                }
+                *position += 1;
+                break;
+            }
+            b'/' | b'>' => return Some(Some((name, value))),
+            x => name.push(x.to_ascii_lowercase()),
+        }
+        *position += 1;
+    }
+
+    while is_space(byte(*position)?) {
+        *position += 1;
+    }
+    match byte(*position)? {
+        quote @ (b'"' | b'\'') => loop {
+            *position += 1;
+            match byte(*position)? {
+                x if x == quote => {
+                    *position += 1;
+                    return Some(Some((name, value)));
+                }
+                x => value.push(x.to_ascii_lowercase()),
+            }
+        },
+        b'>' => return Some(Some((name, value))),
+        _ => {}
+    }
+    loop {
+        match byte(*position)? {
+            x if is_space(x) || x == b'>' => return Some(Some((name, value))),
+            x => value.push(x.to_ascii_lowercase()),
+        }
+        *position += 1;
+    }
+}
+
+/// <https://html.spec.whatwg.org/#algorithm-for-extracting-a-character-encoding-from-a-meta-element>
+fn extract_charset_from_meta(input: &[u8]) -> Option<&'static Encoding> {
+    let mut position = 0;
+    loop {
+        position += find_ignore_case(&input[position..], b"charset")? + b"charset".len();
+        while input.get(position).is_some_and(|x| is_space(*x)) {
+            position += 1;
+        }
+        if input.get(position) == Some(&b'=') {
+            position += 1;
+            break;
+        }
+    }
+    while input.get(position).is_some_and(|x| is_space(*x)) {
+        position += 1;
+    }
+
+    let rest = &input[position..];
+    match rest.first()? {
+        quote @ (b'"' | b'\'') => {
+            let end = find(&rest[1..], &[*quote])?;
+            Encoding::for_label(&rest[1..][..end])
+        }
+        _ => {
+            let end = rest
+                .iter()
+                .position(|x| is_space(*x) || *x == b';')
+                .unwrap_or(rest.len());
+            Encoding::for_label(&rest[..end])
+        }
+    }
+}
+
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|x| x == needle)
+}
+
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|x| x.eq_ignore_ascii_case(needle))
+}
+
+fn starts_with_ignore_case(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.len() >= needle.len() && haystack[..needle.len()].eq_ignore_ascii_case(needle)
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_sniff_html_encoding() {
+    use encoding_rs::{EUC_JP, ISO_8859_2, SHIFT_JIS, WINDOWS_1251};
+
+    assert_eq!(sniff_html_encoding(b"\xEF\xBB\xBF<meta charset=shift_jis>", Some("latin1")), UTF_8);
+    assert_eq!(sniff_html_encoding(b"\xFF\xFEh\0i\0", None), UTF_16LE);
+    assert_eq!(sniff_html_encoding(b"<meta charset=shift_jis>", Some("iso-8859-2")), ISO_8859_2);
+    assert_eq!(sniff_html_encoding(b"<!doctype html><META CHARSET='Shift_JIS'>", None), SHIFT_JIS);
+    assert_eq!(sniff_html_encoding(b"<meta http-equiv=Content-Type content=\"text/html; charset=euc-jp\">", None), EUC_JP);
+    assert_eq!(sniff_html_encoding(b"<meta content=\"text/html; charset=euc-jp\" http-equiv=\"content-type\">", None), EUC_JP);
+    assert_eq!(sniff_html_encoding(b"<meta content=\"text/html; charset=euc-jp\">caf\xC3\xA9", None), UTF_8);
+    assert_eq!(sniff_html_encoding(b"<meta content=\"text/html; charset=euc-jp\" charset=shift_jis http-equiv=content-type>", None), EUC_JP);
+    assert_eq!(sniff_html_encoding(b"<meta content=\"text/html; charset=euc-jp\" charset=shift_jis>caf\xC3\xA9", None), UTF_8);
+    assert_eq!(sniff_html_encoding(b"<!-- <meta charset=euc-jp> --><meta charset=windows-1251>", None), WINDOWS_1251);
+    assert_eq!(sniff_html_encoding(b"<p title='<meta charset=euc-jp>'><meta charset=utf-16le>", None), UTF_8);
+    assert_eq!(sniff_html_encoding(b"<meta charset=x-user-defined>", None), WINDOWS_1252);
+    assert_eq!(sniff_html_encoding(b"<meta charset=\"nonsense\">caf\xE9", None), WINDOWS_1252);
+    assert_eq!(decode_html(b"<meta charset=shift_jis>\x82\xB1\x82\xF1\x82\xC9\x82\xBF\x82\xCD", None).0, "<meta charset=shift_jis>こんにちは");
+
+    // decoding in pieces, which can split characters
+    let decode_in_pieces = |body: &[u8], transport_charset| {
+        let mut decoder = HtmlDecoder::new(transport_charset);
+        let mut result = body.iter().map(|x| decoder.push(&[*x], false)).collect::<String>();
+        result += &decoder.push(b"", true);
+        (result, decoder.encoding())
+    };
+    assert_eq!(decode_in_pieces(b"<meta charset=shift_jis>\x82\xB1\x82\xF1\x82\xC9\x82\xBF\x82\xCD", None), ("<meta charset=shift_jis>こんにちは".to_owned(), Some(SHIFT_JIS)));
+    assert_eq!(decode_in_pieces(b"\xEF\xBB\xBFcaf\xC3\xA9", Some("latin1")), ("café".to_owned(), Some(UTF_8)));
+    let mut body = vec![b' '; PRESCAN_LIMIT - 1];
+    body.extend_from_slice(b"\xC3\xA9");
+    assert_eq!(decode_in_pieces(&body, None).1, Some(UTF_8));
+    assert_eq!(decode_in_pieces(b"caf\xE9", None), ("café".to_owned(), Some(WINDOWS_1252)));
+}
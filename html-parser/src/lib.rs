@@ -1,121 +1,101 @@
-use eyre::bail;
-use tracing::{error, trace};
-
-use wbe_dom::{Node, NodeData};
-use wbe_html_lexer::{html_token, HtmlToken};
-
-// ([if the child is one of these], [the stack must not end with this sequence])
-const NO_NEST: &[(&[&str], &[&str])] = &[
-    (
-        &["p", "table", "form", "h1", "h2", "h3", "h4", "h5", "h6"],
-        &["p"],
-    ),
-    (&["li"], &["li"]),
-    (&["dt", "dd"], &["dt"]),
-    (&["dt", "dd"], &["dd"]),
-    (&["tr"], &["tr"]),
-    (&["tr"], &["tr", "td"]),
-    (&["tr"], &["tr", "th"]),
-    (&["td", "th"], &["td"]),
-    (&["td", "th"], &["th"]),
-];
-const SELF_CLOSING: &[&str] = &[
-    "!doctype", "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta",
-    "param", "source", "track", "wbr",
-];
-
-pub fn parse_html(response_body: &str) -> eyre::Result<Node> {
-    let mut parent = Node::new(NodeData::Document);
-    let mut stack = vec![parent.clone()];
-    let mut names_stack: Vec<String> = vec![];
-    let mut input = &*response_body;
-
-    while !input.is_empty() {
-        let (rest, token) = match html_token(input) {
-            Ok(result) => result,
-            // Err(nom::Err::Incomplete(_)) => ("", HtmlToken::Text(input)),
-            Err(e) => bail!("{}; input={:?}", e, input),
-        };
-        match token {
-            HtmlToken::Comment(text) => {
-                parent.append(&[Node::comment(text.to_owned())]);
-            }
-            HtmlToken::Script(attrs, text) => {
-                let attrs = attrs.into_iter().map(|(n, v)| (n.to_owned(), v)).collect();
-                parent.append(&[Node::element("script".to_owned(), attrs)
-                    .append(&[Node::text(text.to_owned())])]);
-            }
-            HtmlToken::Style(attrs, text) => {
-                let attrs = attrs.into_iter().map(|(n, v)| (n.to_owned(), v)).collect();
-                parent
-                    .append(&[Node::element("style".to_owned(), attrs)
-                        .append(&[Node::text(text.to_owned())])]);
-            }
-            HtmlToken::Tag(false, name, attrs) => {
-                // html spec says parser can ascii lowercase tag and attr names
-                let name = name.to_ascii_lowercase();
-                let attrs = attrs
-                    .into_iter()
-                    .map(|(n, v)| (n.to_ascii_lowercase(), v))
-                    .collect();
-                let element = Node::element(name, attrs);
-
-                for &(child_names, suffix) in NO_NEST {
-                    if child_names.contains(&&*element.name()) {
-                        if names_stack.len() < suffix.len() {
-                            continue;
-                        }
-                        let i = names_stack.len() - suffix.len();
-                        if names_stack[i..].eq(suffix) {
-                            trace!(
-                                true,
-                                name = &*element.name(),
-                                ?child_names,
-                                ?suffix,
-                                ?names_stack
-                            );
-                            for _ in 0..suffix.len() {
-                                let _ = stack.pop().unwrap();
-                                let _ = names_stack.pop().unwrap();
-                                parent = parent.parent().unwrap();
-                            }
-                        }
-                    }
-                }
-
-                parent.append(&[element.clone()]);
-
-                if !SELF_CLOSING.contains(&&*element.name()) {
-                    stack.push(element.clone());
-                    names_stack.push(element.name().to_owned());
-                    parent = element;
-                }
-            }
-            HtmlToken::Tag(true, name, _attrs) => {
-                // html spec says parser can ascii lowercase tag and attr names
-                let name = name.to_ascii_lowercase();
-                if let Some(i) = names_stack.iter().rposition(|x| x == &name) {
-                    for _ in 0..(names_stack.len() - i) {
-                        let _ = stack.pop().unwrap();
-                        let _ = names_stack.pop().unwrap();
-                        parent = parent.parent().unwrap();
-                    }
-                } else {
-                    error!(
-                        "failed to find match for closing tag: {:?} in {:?}",
-                        name, names_stack
-                    );
-                }
-            }
-            HtmlToken::Text(text) => {
-                parent.append(&[Node::text(text.to_owned())]);
-            }
-            HtmlToken::Doctype(_) => {
-                // TODO
+pub mod encoding;
+mod tree_builder;
+
+pub use crate::encoding::{decode_html, sniff_html_encoding, HtmlDecoder};
+
+use wbe_core::CancellationToken;
+use wbe_dom::Node;
+use wbe_html_lexer::Tokenizer;
+
+use crate::tree_builder::TreeBuilder;
+
+/// builds a dom tree from html that arrives in pieces, so that the tree can
+/// be rendered before the whole document has arrived.
+pub struct HtmlParser {
+    tokenizer: Tokenizer,
+    tree: TreeBuilder,
+}
+
+pub fn parse_html(response_body: &str, cancellation: &CancellationToken) -> eyre::Result<Node> {
+    let mut parser = HtmlParser::new();
+    parser.push(response_body, cancellation)?;
+
+    parser.finish(cancellation)
+}
+
+impl HtmlParser {
+    pub fn new() -> Self {
+        Self {
+            tokenizer: Tokenizer::new(),
+            tree: TreeBuilder::new(),
+        }
+    }
+
+    /// the document so far, which grows as more input is pushed.
+    pub fn document(&self) -> Node {
+        self.tree.document()
+    }
+
+    /// adds the next piece of input, which can end anywhere, even in the
+    /// middle of a tag.
+    pub fn push(&mut self, input: &str, cancellation: &CancellationToken) -> eyre::Result<()> {
+        self.tokenizer.push(input);
+
+        self.parse(cancellation)
+    }
+
+    /// uses any input that was waiting for more, returning the whole document.
+    pub fn finish(mut self, cancellation: &CancellationToken) -> eyre::Result<Node> {
+        self.tokenizer.end();
+        self.parse(cancellation)?;
+
+        Ok(self.document())
+    }
+
+    fn parse(&mut self, cancellation: &CancellationToken) -> eyre::Result<()> {
+        while let Some((token, span)) = self.tokenizer.next_token() {
+            cancellation.check()?;
+            self.tree.process(token, span);
+            if let Some(state) = self.tree.take_tokenizer_state() {
+                self.tokenizer.set_state(state);
             }
         }
-        input = rest;
+
+        Ok(())
     }
+}
+
+impl Default for HtmlParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_html_parser() -> eyre::Result<()> {
+    let cancellation = CancellationToken::new();
+    let html = "<!doctype html><!-- a > b --><style>p > b { color: red }</style><P class=x title='a>b'>caf&eacute; &amp &lt;3 a < b<li>one<li>two</p><script>if (a<b) {}</script></P>x<y";
+    let expected = parse_html(html, &cancellation)?.to_string();
+
+    // splitting the input anywhere gives the same tree
+    for i in 0..=html.len() {
+        let mut parser = HtmlParser::new();
+        parser.push(&html[..i], &cancellation)?;
+        parser.push(&html[i..], &cancellation)?;
+        assert_eq!(parser.finish(&cancellation)?.to_string(), expected, "split at {}", i);
+    }
+    let mut parser = HtmlParser::new();
+    for c in html.chars() {
+        parser.push(&c.to_string(), &cancellation)?;
+    }
+    assert_eq!(parser.finish(&cancellation)?.to_string(), expected);
+
+    // and we get the start of the document before the rest arrives
+    let mut parser = HtmlParser::new();
+    parser.push("<p>hello</p><p>wor", &cancellation)?;
+    assert_eq!(parser.document().descendants().filter(|x| &*x.name() == "p").count(), 2);
+    assert_eq!(parser.document().text_content(), "hello");
 
-    Ok(stack[0].clone())
+    Ok(())
 }
@@ -0,0 +1,2228 @@
+use std::{
+    mem::{replace, take},
+    slice,
+};
+
+use tracing::{debug, error};
+
+use wbe_core::{SourcePosition, SourceSpan};
+use wbe_dom::{Node, NodeData};
+use wbe_html_lexer::{is_html_space, Doctype, State, Tag, Token};
+
+/// <https://html.spec.whatwg.org/multipage/parsing.html#special>
+const SPECIAL: &[&str] = &[
+    "address",
+    "applet",
+    "area",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "bgsound",
+    "blockquote",
+    "body",
+    "br",
+    "button",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "iframe",
+    "img",
+    "input",
+    "keygen",
+    "li",
+    "link",
+    "listing",
+    "main",
+    "marquee",
+    "menu",
+    "meta",
+    "nav",
+    "noembed",
+    "noframes",
+    "noscript",
+    "object",
+    "ol",
+    "p",
+    "param",
+    "plaintext",
+    "pre",
+    "script",
+    "search",
+    "section",
+    "select",
+    "source",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+    "wbr",
+    "xmp",
+];
+/// <https://html.spec.whatwg.org/multipage/parsing.html#formatting>
+const FORMATTING: &[&str] = &[
+    "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+/// <https://html.spec.whatwg.org/multipage/parsing.html#generate-implied-end-tags>
+const IMPLIED_END_TAGS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+const IMPLIED_END_TAGS_THOROUGHLY: &[&str] = &[
+    "caption", "colgroup", "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+    "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+/// start tags in body that close any open p element, then open normally.
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "header",
+    "hgroup",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "search",
+    "section",
+    "summary",
+    "ul",
+];
+/// end tags in body that close the element with that name, if in scope.
+const BLOCK_END_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "button",
+    "center",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "header",
+    "hgroup",
+    "listing",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "pre",
+    "search",
+    "section",
+    "summary",
+    "ul",
+];
+/// start tags handled by the rules for the "in head" insertion mode, even
+/// when we’re not in the head.
+const HEAD_START_TAGS: &[&str] = &[
+    "base", "basefont", "bgsound", "link", "meta", "noframes", "script", "style", "template",
+    "title",
+];
+const TABLE_SECTIONS: &[&str] = &["tbody", "tfoot", "thead"];
+const TABLE_STRUCTURE: &[&str] = &[
+    "caption", "col", "colgroup", "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+
+/// doctypes with these public identifiers put the document in quirks mode.
+/// <https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode>
+const QUIRKY_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//Silmaril//dtd html Pro v0r11 19970101//",
+    "-//AS//DTD HTML 3.0 asWedit + extensions//",
+    "-//AdvaSoft Ltd//DTD HTML 3.0 asWedit + extensions//",
+    "-//IETF//DTD HTML 2.0 Level 1//",
+    "-//IETF//DTD HTML 2.0 Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict Level 1//",
+    "-//IETF//DTD HTML 2.0 Strict Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict//",
+    "-//IETF//DTD HTML 2.0//",
+    "-//IETF//DTD HTML 2.1E//",
+    "-//IETF//DTD HTML 3.0//",
+    "-//IETF//DTD HTML 3.2 Final//",
+    "-//IETF//DTD HTML 3.2//",
+    "-//IETF//DTD HTML 3//",
+    "-//IETF//DTD HTML Level 0//",
+    "-//IETF//DTD HTML Level 1//",
+    "-//IETF//DTD HTML Level 2//",
+    "-//IETF//DTD HTML Level 3//",
+    "-//IETF//DTD HTML Strict Level 0//",
+    "-//IETF//DTD HTML Strict Level 1//",
+    "-//IETF//DTD HTML Strict Level 2//",
+    "-//IETF//DTD HTML Strict Level 3//",
+    "-//IETF//DTD HTML Strict//",
+    "-//IETF//DTD HTML//",
+    "-//Metrius//DTD Metrius Presentational//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 2.0 Tables//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 3.0 Tables//",
+    "-//Netscape Comm. Corp.//DTD HTML//",
+    "-//Netscape Comm. Corp.//DTD Strict HTML//",
+    "-//O'Reilly and Associates//DTD HTML 2.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended 1.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended Relaxed 1.0//",
+    "-//SQ//DTD HTML 2.0 HoTMetaL + extensions//",
+    "-//SoftQuad Software//DTD HoTMetaL PRO 6.0::19990601::extensions to HTML 4.0//",
+    "-//SoftQuad//DTD HoTMetaL PRO 4.0::19971010::extensions to HTML 4.0//",
+    "-//Spyglass//DTD HTML 2.0 Extended//",
+    "-//Sun Microsystems Corp.//DTD HotJava HTML//",
+    "-//Sun Microsystems Corp.//DTD HotJava Strict HTML//",
+    "-//W3C//DTD HTML 3 1995-03-24//",
+    "-//W3C//DTD HTML 3.2 Draft//",
+    "-//W3C//DTD HTML 3.2 Final//",
+    "-//W3C//DTD HTML 3.2//",
+    "-//W3C//DTD HTML 3.2S Draft//",
+    "-//W3C//DTD HTML 4.0 Frameset//",
+    "-//W3C//DTD HTML 4.0 Transitional//",
+    "-//W3C//DTD HTML Experimental 19960712//",
+    "-//W3C//DTD HTML Experimental 970421//",
+    "-//W3C//DTD W3 HTML//",
+    "-//W3O//DTD W3 HTML 3.0//",
+    "-//WebTechs//DTD Mozilla HTML 2.0//",
+    "-//WebTechs//DTD Mozilla HTML//",
+];
+
+/// <https://html.spec.whatwg.org/multipage/parsing.html#the-insertion-mode>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+/// elements that stop the search for an element in scope.
+/// <https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-scope>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+/// builds a dom tree from tokens, fixing any author errors the same way as
+/// other browsers, like missing `<html>`, `<head>`, and `<body>` elements,
+/// misnested formatting elements, and content inside tables but not cells.
+/// we don’t run scripts, so the scripting flag is always disabled, and we
+/// treat svg and mathml like html, because we can’t render them anyway.
+/// <https://html.spec.whatwg.org/multipage/parsing.html#tree-construction>
+#[derive(Debug)]
+pub(crate) struct TreeBuilder {
+    document: Node,
+    mode: InsertionMode,
+    original_mode: InsertionMode,
+    template_modes: Vec<InsertionMode>,
+    /// open elements, starting with the html element.
+    stack: Vec<Node>,
+    /// active formatting elements and the tags that created them, with None
+    /// for markers.
+    formatting: Vec<Option<(Node, Tag)>>,
+    head: Option<Node>,
+    form: Option<Node>,
+    frameset_ok: bool,
+    foster_parenting: bool,
+    quirks: bool,
+    /// text in a table, which may need to be foster parented, and where it
+    /// came from.
+    table_text: String,
+    table_text_span: Option<SourceSpan>,
+    /// where the token we’re processing came from.
+    span: SourceSpan,
+    /// whether to ignore a line feed at the start of the next token, like
+    /// the one after `<pre>`.
+    skip_newline: bool,
+    /// the state the tokenizer needs to switch to before the next token.
+    tokenizer_state: Option<State>,
+    done: bool,
+}
+
+impl TreeBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            document: Node::document(),
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            template_modes: vec![],
+            stack: vec![],
+            formatting: vec![],
+            head: None,
+            form: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            quirks: false,
+            table_text: String::new(),
+            table_text_span: None,
+            span: SourceSpan::at(SourcePosition::start()),
+            skip_newline: false,
+            tokenizer_state: None,
+            done: false,
+        }
+    }
+
+    pub(crate) fn document(&self) -> Node {
+        self.document.clone()
+    }
+
+    /// the state the tokenizer needs to switch to, after the last token.
+    pub(crate) fn take_tokenizer_state(&mut self) -> Option<State> {
+        self.tokenizer_state.take()
+    }
+
+    pub(crate) fn process(&mut self, token: Token, span: SourceSpan) {
+        self.span = span;
+        let token = match token {
+            Token::Error(code) => {
+                debug!(code, %span, "html parse error");
+                return;
+            }
+            Token::Text(text) if take(&mut self.skip_newline) => match text.strip_prefix('\n') {
+                Some("") => return,
+                Some(text) => Token::Text(text.to_owned()),
+                None => Token::Text(text),
+            },
+            token => {
+                self.skip_newline = false;
+                token
+            }
+        };
+        if !self.done {
+            self.process_in(self.mode, token);
+        }
+    }
+
+    fn process_in(&mut self, mode: InsertionMode, token: Token) {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::InHeadNoscript => self.in_head_noscript(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::InTemplate => self.in_template(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token),
+        }
+    }
+
+    /// switches to the given mode, then processes the token again.
+    fn reprocess(&mut self, mode: InsertionMode, token: Token) {
+        self.mode = mode;
+        self.process_in(mode, token);
+    }
+
+    fn error(&self, message: &str) {
+        debug!(message, mode = ?self.mode, span = %self.span, "html parse error");
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode>
+    fn initial(&mut self, token: Token) {
+        let Some(token) = self.leading_space(token, false) else {
+            return;
+        };
+        match token {
+            Token::Comment(text) => {
+                self.document.append(&[self.spanned(Node::comment(text))]);
+            }
+            Token::Doctype(doctype) => {
+                self.quirks = is_quirky(&doctype);
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            token => {
+                self.quirks = true;
+                self.reprocess(InsertionMode::BeforeHtml, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#the-before-html-insertion-mode>
+    fn before_html(&mut self, token: Token) {
+        let Some(token) = self.leading_space(token, false) else {
+            return;
+        };
+        match token {
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::Comment(text) => {
+                self.document.append(&[self.spanned(Node::comment(text))]);
+            }
+            Token::StartTag(tag) if tag.name == "html" => {
+                let html = self.spanned(Node::element(tag.name, tag.attrs));
+                self.document.append(slice::from_ref(&html));
+                self.stack.push(html);
+                self.mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag(tag) if !is(&tag.name, &["head", "body", "html", "br"]) => {
+                self.unmatched_end_tag(&tag.name);
+            }
+            token => {
+                let html = Node::element("html".to_owned(), vec![]);
+                self.document.append(slice::from_ref(&html));
+                self.stack.push(html);
+                self.reprocess(InsertionMode::BeforeHead, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#the-before-head-insertion-mode>
+    fn before_head(&mut self, token: Token) {
+        let Some(token) = self.leading_space(token, false) else {
+            return;
+        };
+        match token {
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "head" => {
+                self.head = Some(self.insert_element(tag));
+                self.mode = InsertionMode::InHead;
+            }
+            Token::EndTag(tag) if !is(&tag.name, &["head", "body", "html", "br"]) => {
+                self.unmatched_end_tag(&tag.name);
+            }
+            token => {
+                self.head = Some(self.insert_implied_element("head"));
+                self.reprocess(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inhead>
+    fn in_head(&mut self, token: Token) {
+        let Some(token) = self.leading_space(token, true) else {
+            return;
+        };
+        match token {
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag)
+                if is(&tag.name, &["base", "basefont", "bgsound", "link", "meta"]) =>
+            {
+                self.insert_element(tag);
+                self.stack.pop();
+            }
+            Token::StartTag(tag) if tag.name == "title" => {
+                self.insert_text_element(tag, State::Rcdata)
+            }
+            Token::StartTag(tag) if is(&tag.name, &["noframes", "style"]) => {
+                self.insert_text_element(tag, State::Rawtext)
+            }
+            Token::StartTag(tag) if tag.name == "noscript" => {
+                self.insert_element(tag);
+                self.mode = InsertionMode::InHeadNoscript;
+            }
+            Token::StartTag(tag) if tag.name == "script" => {
+                self.insert_text_element(tag, State::ScriptData)
+            }
+            Token::EndTag(tag) if tag.name == "head" => {
+                self.stack.pop();
+                self.mode = InsertionMode::AfterHead;
+            }
+            Token::StartTag(tag) if tag.name == "template" => {
+                self.insert_element(tag);
+                self.formatting.push(None);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTemplate;
+                self.template_modes.push(InsertionMode::InTemplate);
+            }
+            Token::EndTag(tag) if tag.name == "template" => {
+                if !self.has_open("template") {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.generate_implied_end_tags_thoroughly();
+                if !self.current_is(&["template"]) {
+                    self.error("template closed with other elements open");
+                }
+                self.pop_until(&["template"]);
+                self.clear_formatting_to_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+            }
+            Token::StartTag(tag) if tag.name == "head" => self.error("unexpected head"),
+            Token::EndTag(tag) if !is(&tag.name, &["body", "html", "br"]) => {
+                self.unmatched_end_tag(&tag.name);
+            }
+            token => {
+                self.stack.pop();
+                self.reprocess(InsertionMode::AfterHead, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inheadnoscript>
+    fn in_head_noscript(&mut self, token: Token) {
+        let Some(token) = self.leading_space(token, true) else {
+            return;
+        };
+        match token {
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::EndTag(tag) if tag.name == "noscript" => {
+                self.stack.pop();
+                self.mode = InsertionMode::InHead;
+            }
+            token @ Token::Comment(_) => self.in_head(token),
+            Token::StartTag(tag)
+                if is(
+                    &tag.name,
+                    &["basefont", "bgsound", "link", "meta", "noframes", "style"],
+                ) =>
+            {
+                self.in_head(Token::StartTag(tag))
+            }
+            Token::StartTag(tag) if is(&tag.name, &["head", "noscript"]) => {
+                self.error("unexpected start tag in noscript")
+            }
+            Token::EndTag(tag) if tag.name != "br" => self.unmatched_end_tag(&tag.name),
+            token => {
+                self.error("unexpected token in noscript");
+                self.stack.pop();
+                self.reprocess(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#the-after-head-insertion-mode>
+    fn after_head(&mut self, token: Token) {
+        let Some(token) = self.leading_space(token, true) else {
+            return;
+        };
+        match token {
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "body" => {
+                self.insert_element(tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InBody;
+            }
+            Token::StartTag(tag) if tag.name == "frameset" => {
+                self.insert_element(tag);
+                self.mode = InsertionMode::InFrameset;
+            }
+            Token::StartTag(tag) if is(&tag.name, HEAD_START_TAGS) => {
+                self.error("head element after head");
+                let Some(head) = self.head.clone() else {
+                    return;
+                };
+                self.stack.push(head.clone());
+                self.in_head(Token::StartTag(tag));
+                if let Some(i) = self.stack.iter().rposition(|x| *x == head) {
+                    self.stack.remove(i);
+                }
+            }
+            Token::EndTag(tag) if tag.name == "template" => self.in_head(Token::EndTag(tag)),
+            Token::StartTag(tag) if tag.name == "head" => self.error("unexpected head"),
+            Token::EndTag(tag) if !is(&tag.name, &["body", "html", "br"]) => {
+                self.unmatched_end_tag(&tag.name);
+            }
+            token => {
+                self.insert_implied_element("body");
+                self.reprocess(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inbody>
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                if text.contains('\0') {
+                    self.error("unexpected null character");
+                }
+                let text = text.replace('\0', "");
+                if text.is_empty() {
+                    return;
+                }
+                self.reconstruct_formatting();
+                self.insert_text(&text);
+                if !text.chars().all(is_html_space) {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "html" => {
+                self.error("unexpected html");
+                if self.has_open("template") {
+                    return;
+                }
+                if let Some(html) = self.stack.first() {
+                    add_missing_attrs(html, tag.attrs);
+                }
+            }
+            Token::StartTag(tag) if is(&tag.name, HEAD_START_TAGS) => {
+                self.in_head(Token::StartTag(tag))
+            }
+            Token::EndTag(tag) if tag.name == "template" => self.in_head(Token::EndTag(tag)),
+            Token::StartTag(tag) if tag.name == "body" => {
+                self.error("unexpected body");
+                match self.stack.get(1) {
+                    Some(body) if is(&body.name(), &["body"]) && !self.has_open("template") => {
+                        self.frameset_ok = false;
+                        add_missing_attrs(body, tag.attrs);
+                    }
+                    _ => {}
+                }
+            }
+            Token::StartTag(tag) if tag.name == "frameset" => {
+                self.error("unexpected frameset");
+                match self.stack.get(1) {
+                    Some(body) if is(&body.name(), &["body"]) && self.frameset_ok => {
+                        body.remove();
+                        self.stack.truncate(1);
+                        self.insert_element(tag);
+                        self.mode = InsertionMode::InFrameset;
+                    }
+                    _ => {}
+                }
+            }
+            Token::Eof => {
+                if !self.template_modes.is_empty() {
+                    self.in_template(Token::Eof);
+                } else {
+                    self.done = true;
+                }
+            }
+            Token::EndTag(tag) if is(&tag.name, &["body", "html"]) => {
+                if !self.in_scope(&["body"], Scope::Default) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.mode = InsertionMode::AfterBody;
+                if tag.name == "html" {
+                    self.reprocess(InsertionMode::AfterBody, Token::EndTag(tag));
+                }
+            }
+            Token::StartTag(tag) if is(&tag.name, BLOCKS) => {
+                self.close_p_in_button_scope();
+                self.insert_element(tag);
+            }
+            Token::StartTag(tag) if is(&tag.name, HEADINGS) => {
+                self.close_p_in_button_scope();
+                if self.current_is(HEADINGS) {
+                    self.error("nested heading");
+                    self.stack.pop();
+                }
+                self.insert_element(tag);
+            }
+            Token::StartTag(tag) if is(&tag.name, &["pre", "listing"]) => {
+                self.close_p_in_button_scope();
+                self.insert_element(tag);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            }
+            Token::StartTag(tag) if tag.name == "form" => {
+                let has_template = self.has_open("template");
+                if self.form.is_some() && !has_template {
+                    return self.error("nested form");
+                }
+                self.close_p_in_button_scope();
+                let form = self.insert_element(tag);
+                if !has_template {
+                    self.form = Some(form);
+                }
+            }
+            Token::StartTag(tag) if is(&tag.name, &["li", "dd", "dt"]) => {
+                self.frameset_ok = false;
+                let closes: &[&str] = match &*tag.name {
+                    "li" => &["li"],
+                    _ => &["dd", "dt"],
+                };
+                for node in self.stack.clone().iter().rev() {
+                    let name = node.name().to_owned();
+                    if is(&name, closes) {
+                        self.generate_implied_end_tags(Some(&name));
+                        if !self.current_is(&[&name]) {
+                            self.error("list item closed with other elements open");
+                        }
+                        self.pop_until(&[&name]);
+                        break;
+                    }
+                    if is(&name, SPECIAL) && !is(&name, &["address", "div", "p"]) {
+                        break;
+                    }
+                }
+                self.close_p_in_button_scope();
+                self.insert_element(tag);
+            }
+            Token::StartTag(tag) if tag.name == "plaintext" => {
+                self.close_p_in_button_scope();
+                self.insert_element(tag);
+                self.tokenizer_state = Some(State::Plaintext);
+            }
+            Token::StartTag(tag) if tag.name == "button" => {
+                if self.in_scope(&["button"], Scope::Default) {
+                    self.error("nested button");
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.reconstruct_formatting();
+                self.insert_element(tag);
+                self.frameset_ok = false;
+            }
+            Token::EndTag(tag) if is(&tag.name, BLOCK_END_TAGS) => {
+                if !self.in_scope(&[&tag.name], Scope::Default) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.generate_implied_end_tags(None);
+                if !self.current_is(&[&tag.name]) {
+                    self.error("end tag with other elements open");
+                }
+                self.pop_until(&[&tag.name]);
+            }
+            Token::EndTag(tag) if tag.name == "form" => {
+                if self.has_open("template") {
+                    if !self.in_scope(&["form"], Scope::Default) {
+                        return self.unmatched_end_tag(&tag.name);
+                    }
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["form"]);
+                    return;
+                }
+                let form = self.form.take();
+                let Some(form) = form.filter(|x| self.element_in_scope(x)) else {
+                    return self.unmatched_end_tag(&tag.name);
+                };
+                self.generate_implied_end_tags(None);
+                if self.stack.last() != Some(&form) {
+                    self.error("form closed with other elements open");
+                }
+                self.stack.retain(|x| *x != form);
+            }
+            Token::EndTag(tag) if tag.name == "p" => {
+                if !self.in_scope(&["p"], Scope::Button) {
+                    self.error("unmatched p end tag");
+                    self.insert_implied_element("p");
+                }
+                self.close_p();
+            }
+            Token::EndTag(tag) if is(&tag.name, &["li", "dd", "dt"]) => {
+                let scope = match &*tag.name {
+                    "li" => Scope::ListItem,
+                    _ => Scope::Default,
+                };
+                if !self.in_scope(&[&tag.name], scope) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.generate_implied_end_tags(Some(&tag.name));
+                self.pop_until(&[&tag.name]);
+            }
+            Token::EndTag(tag) if is(&tag.name, HEADINGS) => {
+                if !self.in_scope(HEADINGS, Scope::Default) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(HEADINGS);
+            }
+            Token::StartTag(tag) if tag.name == "a" => {
+                let open = self
+                    .formatting_after_marker()
+                    .iter()
+                    .rev()
+                    .flatten()
+                    .find(|(node, _)| is(&node.name(), &["a"]))
+                    .map(|(node, _)| node.clone());
+                if let Some(open) = open {
+                    self.error("nested a");
+                    self.adoption_agency("a");
+                    self.formatting
+                        .retain(|x| x.as_ref().is_none_or(|x| x.0 != open));
+                    self.stack.retain(|x| *x != open);
+                }
+                self.reconstruct_formatting();
+                let element = self.insert_element(tag.clone());
+                self.push_formatting(element, tag);
+            }
+            Token::StartTag(tag) if is(&tag.name, FORMATTING) => {
+                self.reconstruct_formatting();
+                if tag.name == "nobr" && self.in_scope(&["nobr"], Scope::Default) {
+                    self.error("nested nobr");
+                    self.adoption_agency("nobr");
+                    self.reconstruct_formatting();
+                }
+                let element = self.insert_element(tag.clone());
+                self.push_formatting(element, tag);
+            }
+            Token::EndTag(tag) if is(&tag.name, FORMATTING) => {
+                if !self.adoption_agency(&tag.name) {
+                    self.any_other_end_tag(&tag.name);
+                }
+            }
+            Token::StartTag(tag) if is(&tag.name, &["applet", "marquee", "object"]) => {
+                self.reconstruct_formatting();
+                self.insert_element(tag);
+                self.formatting.push(None);
+                self.frameset_ok = false;
+            }
+            Token::EndTag(tag) if is(&tag.name, &["applet", "marquee", "object"]) => {
+                if !self.in_scope(&[&tag.name], Scope::Default) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(&[&tag.name]);
+                self.clear_formatting_to_marker();
+            }
+            Token::StartTag(tag) if tag.name == "table" => {
+                if !self.quirks {
+                    self.close_p_in_button_scope();
+                }
+                self.insert_element(tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            Token::EndTag(tag) if tag.name == "br" => {
+                self.error("br end tag");
+                self.in_body(Token::StartTag(new_tag("br")));
+            }
+            Token::StartTag(tag)
+                if is(
+                    &tag.name,
+                    &["area", "br", "embed", "img", "keygen", "wbr", "input"],
+                ) =>
+            {
+                let hidden = tag.name == "input"
+                    && tag
+                        .attrs
+                        .iter()
+                        .any(|(n, v)| n == "type" && v.eq_ignore_ascii_case("hidden"));
+                self.reconstruct_formatting();
+                self.insert_element(tag);
+                self.stack.pop();
+                if !hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::StartTag(tag) if is(&tag.name, &["param", "source", "track"]) => {
+                self.insert_element(tag);
+                self.stack.pop();
+            }
+            Token::StartTag(tag) if tag.name == "hr" => {
+                self.close_p_in_button_scope();
+                self.insert_element(tag);
+                self.stack.pop();
+                self.frameset_ok = false;
+            }
+            Token::StartTag(tag) if tag.name == "image" => {
+                self.error("image start tag");
+                let tag = Tag {
+                    name: "img".to_owned(),
+                    ..tag
+                };
+                self.in_body(Token::StartTag(tag));
+            }
+            Token::StartTag(tag) if tag.name == "textarea" => {
+                self.skip_newline = true;
+                self.frameset_ok = false;
+                self.insert_text_element(tag, State::Rcdata);
+            }
+            Token::StartTag(tag) if tag.name == "xmp" => {
+                self.close_p_in_button_scope();
+                self.reconstruct_formatting();
+                self.frameset_ok = false;
+                self.insert_text_element(tag, State::Rawtext);
+            }
+            Token::StartTag(tag) if tag.name == "iframe" => {
+                self.frameset_ok = false;
+                self.insert_text_element(tag, State::Rawtext);
+            }
+            Token::StartTag(tag) if tag.name == "noembed" => {
+                self.insert_text_element(tag, State::Rawtext)
+            }
+            Token::StartTag(tag) if tag.name == "select" => {
+                self.reconstruct_formatting();
+                self.insert_element(tag);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable
+                    | InsertionMode::InCaption
+                    | InsertionMode::InTableBody
+                    | InsertionMode::InRow
+                    | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            Token::StartTag(tag) if is(&tag.name, &["optgroup", "option"]) => {
+                if self.current_is(&["option"]) {
+                    self.stack.pop();
+                }
+                self.reconstruct_formatting();
+                self.insert_element(tag);
+            }
+            Token::StartTag(tag) if is(&tag.name, &["rb", "rtc", "rp", "rt"]) => {
+                if self.in_scope(&["ruby"], Scope::Default) {
+                    let except = match &*tag.name {
+                        "rp" | "rt" => Some("rtc"),
+                        _ => None,
+                    };
+                    self.generate_implied_end_tags(except);
+                }
+                self.insert_element(tag);
+            }
+            Token::StartTag(tag)
+                if is(&tag.name, TABLE_STRUCTURE) || is(&tag.name, &["frame", "head"]) =>
+            {
+                self.error("unexpected start tag in body");
+            }
+            Token::StartTag(tag) => {
+                let self_closing = tag.self_closing && is(&tag.name, &["math", "svg"]);
+                self.reconstruct_formatting();
+                self.insert_element(tag);
+                if self_closing {
+                    self.stack.pop();
+                }
+            }
+            Token::EndTag(tag) => self.any_other_end_tag(&tag.name),
+            Token::Error(_) => {}
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incdata>
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => self.insert_text(&text),
+            Token::Eof => {
+                self.error("eof in text");
+                self.stack.pop();
+                self.reprocess(self.original_mode, Token::Eof);
+            }
+            _ => {
+                self.stack.pop();
+                self.mode = self.original_mode;
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intable>
+    fn in_table(&mut self, token: Token) {
+        match token {
+            token @ Token::Text(_)
+                if self.current_is(&["table", "tbody", "template", "tfoot", "thead", "tr"]) =>
+            {
+                self.table_text.clear();
+                self.original_mode = self.mode;
+                self.reprocess(InsertionMode::InTableText, token);
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "caption" => {
+                self.clear_stack_to(&["table", "template", "html"]);
+                self.formatting.push(None);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InCaption;
+            }
+            Token::StartTag(tag) if tag.name == "colgroup" => {
+                self.clear_stack_to(&["table", "template", "html"]);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InColumnGroup;
+            }
+            Token::StartTag(tag) if tag.name == "col" => {
+                self.clear_stack_to(&["table", "template", "html"]);
+                self.insert_implied_element("colgroup");
+                self.reprocess(InsertionMode::InColumnGroup, Token::StartTag(tag));
+            }
+            Token::StartTag(tag) if is(&tag.name, TABLE_SECTIONS) => {
+                self.clear_stack_to(&["table", "template", "html"]);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InTableBody;
+            }
+            Token::StartTag(tag) if is(&tag.name, &["td", "th", "tr"]) => {
+                self.clear_stack_to(&["table", "template", "html"]);
+                self.insert_implied_element("tbody");
+                self.reprocess(InsertionMode::InTableBody, Token::StartTag(tag));
+            }
+            Token::StartTag(tag) if tag.name == "table" => {
+                self.error("nested table");
+                if self.in_scope(&["table"], Scope::Table) {
+                    self.pop_until(&["table"]);
+                    self.reset_insertion_mode();
+                    self.process_in(self.mode, Token::StartTag(tag));
+                }
+            }
+            Token::EndTag(tag) if tag.name == "table" => {
+                if !self.in_scope(&["table"], Scope::Table) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.pop_until(&["table"]);
+                self.reset_insertion_mode();
+            }
+            Token::EndTag(tag)
+                if is(&tag.name, TABLE_STRUCTURE) || is(&tag.name, &["body", "html"]) =>
+            {
+                self.unmatched_end_tag(&tag.name);
+            }
+            Token::StartTag(tag) if is(&tag.name, &["style", "script", "template"]) => {
+                self.in_head(Token::StartTag(tag))
+            }
+            Token::EndTag(tag) if tag.name == "template" => self.in_head(Token::EndTag(tag)),
+            Token::StartTag(tag)
+                if tag.name == "input"
+                    && tag
+                        .attrs
+                        .iter()
+                        .any(|(n, v)| n == "type" && v.eq_ignore_ascii_case("hidden")) =>
+            {
+                self.error("hidden input in table");
+                self.insert_element(tag);
+                self.stack.pop();
+            }
+            Token::StartTag(tag) if tag.name == "form" => {
+                self.error("form in table");
+                if self.has_open("template") || self.form.is_some() {
+                    return;
+                }
+                self.form = Some(self.insert_element(tag));
+                self.stack.pop();
+            }
+            Token::Eof => self.in_body(Token::Eof),
+            token => {
+                self.error("unexpected token in table");
+                self.foster_parenting = true;
+                self.in_body(token);
+                self.foster_parenting = false;
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intabletext>
+    fn in_table_text(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                if text.contains('\0') {
+                    self.error("unexpected null character");
+                }
+                self.table_text += &text.replace('\0', "");
+                self.table_text_span = join(self.table_text_span, Some(self.span));
+            }
+            token => {
+                let text = take(&mut self.table_text);
+                // the text came from before this token
+                let table_text_span = self.table_text_span.take().unwrap_or(self.span);
+                let span = replace(&mut self.span, table_text_span);
+                if text.chars().all(is_html_space) {
+                    self.insert_text(&text);
+                } else {
+                    // same as anything else in a table
+                    self.error("text in table");
+                    self.foster_parenting = true;
+                    self.in_body(Token::Text(text));
+                    self.foster_parenting = false;
+                }
+                self.span = span;
+                self.reprocess(self.original_mode, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incaption>
+    fn in_caption(&mut self, token: Token) {
+        match token {
+            Token::EndTag(tag) if tag.name == "caption" => {
+                if self.close_caption() {
+                    self.mode = InsertionMode::InTable;
+                } else {
+                    self.unmatched_end_tag(&tag.name);
+                }
+            }
+            Token::StartTag(tag) if is(&tag.name, TABLE_STRUCTURE) => {
+                if self.close_caption() {
+                    self.reprocess(InsertionMode::InTable, Token::StartTag(tag));
+                } else {
+                    self.error("unexpected start tag in caption");
+                }
+            }
+            Token::EndTag(tag) if tag.name == "table" => {
+                if self.close_caption() {
+                    self.reprocess(InsertionMode::InTable, Token::EndTag(tag));
+                } else {
+                    self.unmatched_end_tag(&tag.name);
+                }
+            }
+            Token::EndTag(tag)
+                if is(&tag.name, TABLE_STRUCTURE) || is(&tag.name, &["body", "html"]) =>
+            {
+                self.unmatched_end_tag(&tag.name);
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incolgroup>
+    fn in_column_group(&mut self, token: Token) {
+        let Some(token) = self.leading_space(token, true) else {
+            return;
+        };
+        match token {
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "col" => {
+                self.insert_element(tag);
+                self.stack.pop();
+            }
+            Token::EndTag(tag) if tag.name == "colgroup" => {
+                if !self.current_is(&["colgroup"]) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.stack.pop();
+                self.mode = InsertionMode::InTable;
+            }
+            Token::EndTag(tag) if tag.name == "col" => self.unmatched_end_tag(&tag.name),
+            Token::StartTag(tag) if tag.name == "template" => self.in_head(Token::StartTag(tag)),
+            Token::EndTag(tag) if tag.name == "template" => self.in_head(Token::EndTag(tag)),
+            Token::Eof => self.in_body(Token::Eof),
+            token => {
+                if !self.current_is(&["colgroup"]) {
+                    return self.error("unexpected token in column group");
+                }
+                self.stack.pop();
+                self.reprocess(InsertionMode::InTable, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intbody>
+    fn in_table_body(&mut self, token: Token) {
+        const CONTEXT: &[&str] = &["tbody", "tfoot", "thead", "template", "html"];
+        match token {
+            Token::StartTag(tag) if tag.name == "tr" => {
+                self.clear_stack_to(CONTEXT);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag(tag) if is(&tag.name, &["th", "td"]) => {
+                self.error("cell outside row");
+                self.clear_stack_to(CONTEXT);
+                self.insert_implied_element("tr");
+                self.reprocess(InsertionMode::InRow, Token::StartTag(tag));
+            }
+            Token::EndTag(tag) if is(&tag.name, TABLE_SECTIONS) => {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.clear_stack_to(CONTEXT);
+                self.stack.pop();
+                self.mode = InsertionMode::InTable;
+            }
+            Token::StartTag(ref tag)
+                if is(
+                    &tag.name,
+                    &["caption", "col", "colgroup", "tbody", "tfoot", "thead"],
+                ) =>
+            {
+                self.close_table_section(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => self.close_table_section(token),
+            Token::EndTag(tag)
+                if is(
+                    &tag.name,
+                    &[
+                        "body", "caption", "col", "colgroup", "html", "td", "th", "tr",
+                    ],
+                ) =>
+            {
+                self.unmatched_end_tag(&tag.name);
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intr>
+    fn in_row(&mut self, token: Token) {
+        match token {
+            Token::StartTag(tag) if is(&tag.name, &["th", "td"]) => {
+                self.clear_stack_to(&["tr", "template", "html"]);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InCell;
+                self.formatting.push(None);
+            }
+            Token::EndTag(tag) if tag.name == "tr" => {
+                if !self.close_row() {
+                    self.unmatched_end_tag(&tag.name);
+                }
+            }
+            Token::StartTag(ref tag)
+                if is(
+                    &tag.name,
+                    &[
+                        "caption", "col", "colgroup", "tbody", "tfoot", "thead", "tr",
+                    ],
+                ) =>
+            {
+                if self.close_row() {
+                    self.reprocess(InsertionMode::InTableBody, token);
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_row() {
+                    self.reprocess(InsertionMode::InTableBody, token);
+                }
+            }
+            Token::EndTag(ref tag) if is(&tag.name, TABLE_SECTIONS) => {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                if self.close_row() {
+                    self.reprocess(InsertionMode::InTableBody, token);
+                }
+            }
+            Token::EndTag(tag)
+                if is(
+                    &tag.name,
+                    &["body", "caption", "col", "colgroup", "html", "td", "th"],
+                ) =>
+            {
+                self.unmatched_end_tag(&tag.name);
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intd>
+    fn in_cell(&mut self, token: Token) {
+        match token {
+            Token::EndTag(tag) if is(&tag.name, &["td", "th"]) => {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.generate_implied_end_tags(None);
+                if !self.current_is(&[&tag.name]) {
+                    self.error("cell closed with other elements open");
+                }
+                self.pop_until(&[&tag.name]);
+                self.clear_formatting_to_marker();
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag(ref tag) if is(&tag.name, TABLE_STRUCTURE) => {
+                if !self.in_scope(&["td", "th"], Scope::Table) {
+                    return self.error("unexpected start tag in cell");
+                }
+                self.close_cell();
+                self.process_in(self.mode, token);
+            }
+            Token::EndTag(tag)
+                if is(&tag.name, &["body", "caption", "col", "colgroup", "html"]) =>
+            {
+                self.unmatched_end_tag(&tag.name);
+            }
+            Token::EndTag(ref tag)
+                if is(&tag.name, &["table", "tbody", "tfoot", "thead", "tr"]) =>
+            {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.close_cell();
+                self.process_in(self.mode, token);
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inselect>
+    fn in_select(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                if text.contains('\0') {
+                    self.error("unexpected null character");
+                }
+                self.insert_text(&text.replace('\0', ""));
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if is(&tag.name, &["option", "optgroup", "hr"]) => {
+                if self.current_is(&["option"]) {
+                    self.stack.pop();
+                }
+                if tag.name != "option" && self.current_is(&["optgroup"]) {
+                    self.stack.pop();
+                }
+                let void = tag.name == "hr";
+                self.insert_element(tag);
+                if void {
+                    self.stack.pop();
+                }
+            }
+            Token::EndTag(tag) if tag.name == "optgroup" => {
+                let len = self.stack.len();
+                if self.current_is(&["option"])
+                    && len > 1
+                    && is(&self.stack[len - 2].name(), &["optgroup"])
+                {
+                    self.stack.pop();
+                }
+                if self.current_is(&["optgroup"]) {
+                    self.stack.pop();
+                } else {
+                    self.unmatched_end_tag(&tag.name);
+                }
+            }
+            Token::EndTag(tag) if tag.name == "option" => {
+                if self.current_is(&["option"]) {
+                    self.stack.pop();
+                } else {
+                    self.unmatched_end_tag(&tag.name);
+                }
+            }
+            Token::EndTag(tag) if tag.name == "select" => {
+                if !self.in_scope(&["select"], Scope::Select) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+            }
+            Token::StartTag(tag) if tag.name == "select" => {
+                self.error("nested select");
+                if self.in_scope(&["select"], Scope::Select) {
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                }
+            }
+            Token::StartTag(tag) if is(&tag.name, &["input", "keygen", "textarea"]) => {
+                self.error("unexpected start tag in select");
+                if self.in_scope(&["select"], Scope::Select) {
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                    self.process_in(self.mode, Token::StartTag(tag));
+                }
+            }
+            Token::StartTag(tag) if is(&tag.name, &["script", "template"]) => {
+                self.in_head(Token::StartTag(tag))
+            }
+            Token::EndTag(tag) if tag.name == "template" => self.in_head(Token::EndTag(tag)),
+            Token::Eof => self.in_body(Token::Eof),
+            Token::EndTag(tag) => self.unmatched_end_tag(&tag.name),
+            _ => self.error("unexpected token in select"),
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inselectintable>
+    fn in_select_in_table(&mut self, token: Token) {
+        const TABLE: &[&str] = &[
+            "caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th",
+        ];
+        match token {
+            Token::StartTag(ref tag) if is(&tag.name, TABLE) => {
+                self.error("table start tag in select");
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                self.process_in(self.mode, token);
+            }
+            Token::EndTag(ref tag) if is(&tag.name, TABLE) => {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                self.process_in(self.mode, token);
+            }
+            token => self.in_select(token),
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intemplate>
+    fn in_template(&mut self, token: Token) {
+        let mode = match &token {
+            Token::Text(_) | Token::Comment(_) | Token::Doctype(_) => return self.in_body(token),
+            Token::StartTag(tag) if is(&tag.name, HEAD_START_TAGS) => return self.in_head(token),
+            Token::EndTag(tag) if tag.name == "template" => return self.in_head(token),
+            Token::StartTag(tag)
+                if is(
+                    &tag.name,
+                    &["caption", "colgroup", "tbody", "tfoot", "thead"],
+                ) =>
+            {
+                InsertionMode::InTable
+            }
+            Token::StartTag(tag) if tag.name == "col" => InsertionMode::InColumnGroup,
+            Token::StartTag(tag) if tag.name == "tr" => InsertionMode::InTableBody,
+            Token::StartTag(tag) if is(&tag.name, &["td", "th"]) => InsertionMode::InRow,
+            Token::StartTag(_) => InsertionMode::InBody,
+            Token::EndTag(tag) => return self.unmatched_end_tag(&tag.name),
+            Token::Eof => {
+                if !self.has_open("template") {
+                    self.done = true;
+                    return;
+                }
+                self.error("eof in template");
+                self.pop_until(&["template"]);
+                self.clear_formatting_to_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+                return self.process_in(self.mode, token);
+            }
+            Token::Error(_) => return,
+        };
+        self.template_modes.pop();
+        self.template_modes.push(mode);
+        self.reprocess(mode, token);
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-afterbody>
+    fn after_body(&mut self, token: Token) {
+        let token = match token {
+            Token::Text(text) => {
+                let (space, rest) = split_space(&text);
+                if !space.is_empty() {
+                    self.in_body(Token::Text(space.to_owned()));
+                }
+                if rest.is_empty() {
+                    return;
+                }
+                Token::Text(rest.to_owned())
+            }
+            token => token,
+        };
+        match token {
+            Token::Comment(text) => {
+                if let Some(html) = self.stack.first() {
+                    html.append(&[self.spanned(Node::comment(text))]);
+                }
+            }
+            Token::Doctype(_) => self.error("unexpected doctype"),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::EndTag(tag) if tag.name == "html" => self.mode = InsertionMode::AfterAfterBody,
+            Token::Eof => self.done = true,
+            token => {
+                self.error("unexpected token after body");
+                self.reprocess(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inframeset>
+    fn in_frameset(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                // anything but whitespace is ignored
+                let space = text
+                    .chars()
+                    .filter(|&c| is_html_space(c))
+                    .collect::<String>();
+                if !space.is_empty() {
+                    self.insert_text(&space);
+                }
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "frameset" => {
+                self.insert_element(tag);
+            }
+            Token::EndTag(tag) if tag.name == "frameset" => {
+                if self.stack.len() <= 1 {
+                    return self.unmatched_end_tag(&tag.name);
+                }
+                self.stack.pop();
+                if !self.current_is(&["frameset"]) {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+            }
+            Token::StartTag(tag) if tag.name == "frame" => {
+                self.insert_element(tag);
+                self.stack.pop();
+            }
+            Token::StartTag(tag) if tag.name == "noframes" => self.in_head(Token::StartTag(tag)),
+            Token::Eof => self.done = true,
+            _ => self.error("unexpected token in frameset"),
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-afterframeset>
+    fn after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                let space = text
+                    .chars()
+                    .filter(|&c| is_html_space(c))
+                    .collect::<String>();
+                if !space.is_empty() {
+                    self.insert_text(&space);
+                }
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::EndTag(tag) if tag.name == "html" => {
+                self.mode = InsertionMode::AfterAfterFrameset;
+            }
+            Token::StartTag(tag) if tag.name == "noframes" => self.in_head(Token::StartTag(tag)),
+            Token::Eof => self.done = true,
+            _ => self.error("unexpected token after frameset"),
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#the-after-after-body-insertion-mode>
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Comment(text) => {
+                self.document.append(&[self.spanned(Node::comment(text))]);
+            }
+            Token::Text(ref text) if text.chars().all(is_html_space) => self.in_body(token),
+            token @ Token::Doctype(_) => self.in_body(token),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::Eof => self.done = true,
+            token => {
+                self.error("unexpected token after body");
+                self.reprocess(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#the-after-after-frameset-insertion-mode>
+    fn after_after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Comment(text) => {
+                self.document.append(&[self.spanned(Node::comment(text))]);
+            }
+            Token::Text(text) => {
+                let space = text
+                    .chars()
+                    .filter(|&c| is_html_space(c))
+                    .collect::<String>();
+                if !space.is_empty() {
+                    self.in_body(Token::Text(space));
+                }
+            }
+            token @ Token::Doctype(_) => self.in_body(token),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "noframes" => self.in_head(Token::StartTag(tag)),
+            Token::Eof => self.done = true,
+            _ => self.error("unexpected token after frameset"),
+        }
+    }
+
+    /// handles any whitespace at the start of a text token, for modes that
+    /// treat whitespace differently to other text, by inserting it if
+    /// `insert` is true or ignoring it otherwise. returns the rest of the
+    /// token, if any.
+    fn leading_space(&mut self, token: Token, insert: bool) -> Option<Token> {
+        let Token::Text(text) = token else {
+            return Some(token);
+        };
+        let (space, rest) = split_space(&text);
+        if insert && !space.is_empty() {
+            self.insert_text(space);
+        }
+
+        (!rest.is_empty()).then(|| Token::Text(rest.to_owned()))
+    }
+
+    /// the body rules for end tags that have no rules of their own.
+    fn any_other_end_tag(&mut self, name: &str) {
+        for i in (0..self.stack.len()).rev() {
+            let node = self.stack[i].clone();
+            if is(&node.name(), &[name]) {
+                self.generate_implied_end_tags(Some(name));
+                if i != self.stack.len() - 1 {
+                    self.error("end tag with other elements open");
+                }
+                self.stack.truncate(i);
+                return;
+            }
+            if is(&node.name(), SPECIAL) {
+                return self.unmatched_end_tag(name);
+            }
+        }
+    }
+
+    fn unmatched_end_tag(&self, name: &str) {
+        let names = self
+            .stack
+            .iter()
+            .map(|x| x.name().to_owned())
+            .collect::<Vec<_>>();
+        error!(
+            "failed to find match for closing tag: {:?} at {} in {:?}",
+            name, self.span, names
+        );
+    }
+
+    /// the node to insert into, and the node to insert before, if any.
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#appropriate-place-for-inserting-a-node>
+    fn insertion_place(&self, target: Option<Node>) -> (Node, Option<Node>) {
+        let Some(target) = target.or_else(|| self.stack.last().cloned()) else {
+            return (self.document.clone(), None);
+        };
+        if !self.foster_parenting
+            || !is(&target.name(), &["table", "tbody", "tfoot", "thead", "tr"])
+        {
+            return (target, None);
+        }
+
+        // foster parenting puts the node before the table
+        let last_template = self
+            .stack
+            .iter()
+            .rposition(|x| is(&x.name(), &["template"]));
+        let last_table = self.stack.iter().rposition(|x| is(&x.name(), &["table"]));
+        match (last_template, last_table) {
+            (Some(template), Some(table)) if template > table => {
+                (self.stack[template].clone(), None)
+            }
+            (Some(template), None) => (self.stack[template].clone(), None),
+            (_, None) => (self.stack[0].clone(), None),
+            (_, Some(table)) => {
+                let table_node = self.stack[table].clone();
+                match table_node.parent() {
+                    Some(parent) => (parent, Some(table_node)),
+                    None => (self.stack[table.saturating_sub(1)].clone(), None),
+                }
+            }
+        }
+    }
+
+    fn insert_text(&mut self, text: &str) {
+        let (parent, before) = self.insertion_place(None);
+        if parent.r#type() == wbe_dom::NodeType::Document {
+            return;
+        }
+
+        // text next to text joins it, rather than making another node
+        let previous = {
+            let children = parent.children();
+            let index = match &before {
+                Some(before) => children.iter().position(|x| x == before),
+                None => Some(children.len()),
+            };
+            index
+                .and_then(|x| x.checked_sub(1))
+                .map(|x| children[x].clone())
+        };
+        if let Some(previous) = previous {
+            let mut previous = previous.write();
+            if let NodeData::Text(previous_text, _) = &mut previous.inner {
+                *previous_text += text;
+                previous.source_span = join(previous.source_span, Some(self.span));
+                return;
+            }
+        }
+        let node = self.spanned(Node::text(text.to_owned()));
+        parent.insert_before(&[node], before.as_ref());
+    }
+
+    fn insert_comment(&mut self, text: String) {
+        let (parent, before) = self.insertion_place(None);
+        parent.insert_before(&[self.spanned(Node::comment(text))], before.as_ref());
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#insert-a-foreign-element>
+    fn insert_element(&mut self, tag: Tag) -> Node {
+        let (parent, before) = self.insertion_place(None);
+        let element = self.spanned(Node::element(tag.name, tag.attrs));
+        parent.insert_before(slice::from_ref(&element), before.as_ref());
+        self.stack.push(element.clone());
+
+        element
+    }
+
+    /// inserts an element that has no tag in the source, like the `<tbody>`
+    /// around rows that aren’t in a table section.
+    fn insert_implied_element(&mut self, name: &str) -> Node {
+        let element = self.insert_element(new_tag(name));
+        element.set_source_span(None);
+
+        element
+    }
+
+    /// marks a new node as coming from the token we’re processing.
+    fn spanned(&self, node: Node) -> Node {
+        node.set_source_span(Some(self.span));
+
+        node
+    }
+
+    /// inserts an element whose text is tokenised in another state, like
+    /// `<title>` or `<script>`.
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#generic-raw-text-element-parsing-algorithm>
+    fn insert_text_element(&mut self, tag: Tag, state: State) {
+        self.insert_element(tag);
+        self.tokenizer_state = Some(state);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    fn current_is(&self, names: &[&str]) -> bool {
+        self.stack.last().is_some_and(|x| is(&x.name(), names))
+    }
+
+    fn has_open(&self, name: &str) -> bool {
+        self.stack.iter().any(|x| *x.name() == *name)
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-the-specific-scope>
+    fn in_scope(&self, names: &[&str], scope: Scope) -> bool {
+        for node in self.stack.iter().rev() {
+            let name = node.name();
+            if is(&name, names) {
+                return true;
+            }
+            if is_scope_boundary(&name, scope) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    fn element_in_scope(&self, element: &Node) -> bool {
+        for node in self.stack.iter().rev() {
+            if node == element {
+                return true;
+            }
+            if is_scope_boundary(&node.name(), Scope::Default) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// pops elements until one of the given names has been popped.
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(node) = self.stack.pop() {
+            if is(&node.name(), names) {
+                break;
+            }
+        }
+    }
+
+    /// pops elements until the current node has one of the given names.
+    fn clear_stack_to(&mut self, names: &[&str]) {
+        while self.stack.len() > 1 && !self.current_is(names) {
+            self.stack.pop();
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#generate-implied-end-tags>
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        while self.current_is(IMPLIED_END_TAGS) && !except.is_some_and(|x| self.current_is(&[x])) {
+            self.stack.pop();
+        }
+    }
+
+    fn generate_implied_end_tags_thoroughly(&mut self) {
+        while self.current_is(IMPLIED_END_TAGS_THOROUGHLY) {
+            self.stack.pop();
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#close-a-p-element>
+    fn close_p(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        if !self.current_is(&["p"]) {
+            self.error("p closed with other elements open");
+        }
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.in_scope(&["p"], Scope::Button) {
+            self.close_p();
+        }
+    }
+
+    /// closes the caption, returning false if there wasn’t one in scope.
+    fn close_caption(&mut self) -> bool {
+        if !self.in_scope(&["caption"], Scope::Table) {
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        if !self.current_is(&["caption"]) {
+            self.error("caption closed with other elements open");
+        }
+        self.pop_until(&["caption"]);
+        self.clear_formatting_to_marker();
+
+        true
+    }
+
+    /// closes the row, returning false if there wasn’t one in scope.
+    fn close_row(&mut self) -> bool {
+        if !self.in_scope(&["tr"], Scope::Table) {
+            self.error("no row to close");
+            return false;
+        }
+        self.clear_stack_to(&["tr", "template", "html"]);
+        self.stack.pop();
+        self.mode = InsertionMode::InTableBody;
+
+        true
+    }
+
+    /// closes the table section, then processes the token in the table.
+    fn close_table_section(&mut self, token: Token) {
+        if !self.in_scope(TABLE_SECTIONS, Scope::Table) {
+            return self.error("no table section to close");
+        }
+        self.clear_stack_to(&["tbody", "tfoot", "thead", "template", "html"]);
+        self.stack.pop();
+        self.reprocess(InsertionMode::InTable, token);
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#close-the-cell>
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        if !self.current_is(&["td", "th"]) {
+            self.error("cell closed with other elements open");
+        }
+        self.pop_until(&["td", "th"]);
+        self.clear_formatting_to_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#reset-the-insertion-mode-appropriately>
+    fn reset_insertion_mode(&mut self) {
+        for (i, node) in self.stack.iter().enumerate().rev() {
+            let last = i == 0;
+            self.mode = match &*node.name() {
+                "select" => {
+                    let in_table = self.stack[..i]
+                        .iter()
+                        .rev()
+                        .map(|x| x.name().to_owned())
+                        .take_while(|x| x != "template")
+                        .any(|x| x == "table");
+                    match in_table {
+                        true => InsertionMode::InSelectInTable,
+                        false => InsertionMode::InSelect,
+                    }
+                }
+                "td" | "th" if !last => InsertionMode::InCell,
+                "tr" => InsertionMode::InRow,
+                "tbody" | "thead" | "tfoot" => InsertionMode::InTableBody,
+                "caption" => InsertionMode::InCaption,
+                "colgroup" => InsertionMode::InColumnGroup,
+                "table" => InsertionMode::InTable,
+                "template" => *self
+                    .template_modes
+                    .last()
+                    .unwrap_or(&InsertionMode::InTemplate),
+                "head" if !last => InsertionMode::InHead,
+                "body" => InsertionMode::InBody,
+                "frameset" => InsertionMode::InFrameset,
+                "html" => match self.head {
+                    None => InsertionMode::BeforeHead,
+                    Some(_) => InsertionMode::AfterHead,
+                },
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            return;
+        }
+    }
+
+    /// active formatting elements after the last marker.
+    fn formatting_after_marker(&self) -> &[Option<(Node, Tag)>] {
+        let start = self.formatting.iter().rposition(|x| x.is_none());
+
+        &self.formatting[start.map_or(0, |x| x + 1)..]
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#push-onto-the-list-of-active-formatting-elements>
+    fn push_formatting(&mut self, element: Node, tag: Tag) {
+        // no more than three of the same element, like the animals in the ark
+        let start = self.formatting.len() - self.formatting_after_marker().len();
+        let same = (start..self.formatting.len())
+            .filter(|&i| match &self.formatting[i] {
+                Some((_, x)) => x.name == tag.name && same_attrs(&x.attrs, &tag.attrs),
+                None => false,
+            })
+            .collect::<Vec<_>>();
+        if same.len() >= 3 {
+            self.formatting.remove(same[0]);
+        }
+        self.formatting.push(Some((element, tag)));
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#reconstruct-the-active-formatting-elements>
+    fn reconstruct_formatting(&mut self) {
+        let is_open = |entry: &Option<(Node, Tag)>| match entry {
+            Some((node, _)) => self.stack.contains(node),
+            None => true,
+        };
+        let Some(last) = self.formatting.last() else {
+            return;
+        };
+        if is_open(last) {
+            return;
+        }
+        let mut start = self.formatting.len() - 1;
+        while start > 0 && !is_open(&self.formatting[start - 1]) {
+            start -= 1;
+        }
+        for i in start..self.formatting.len() {
+            let Some((old, tag)) = self.formatting[i].clone() else {
+                continue;
+            };
+            let element = self.insert_element(tag.clone());
+            element.set_source_span(old.source_span());
+            self.formatting[i] = Some((element, tag));
+        }
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#clear-the-list-of-active-formatting-elements-up-to-the-last-marker>
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.formatting.pop() {
+            if entry.is_none() {
+                break;
+            }
+        }
+    }
+
+    fn formatting_index(&self, element: &Node) -> Option<usize> {
+        self.formatting
+            .iter()
+            .position(|x| x.as_ref().is_some_and(|x| x.0 == *element))
+    }
+
+    /// fixes misnested formatting elements for an end tag, returning false
+    /// if the end tag should be handled like any other end tag instead.
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm>
+    fn adoption_agency(&mut self, subject: &str) -> bool {
+        if let Some(current) = self.stack.last() {
+            if is(&current.name(), &[subject]) && self.formatting_index(current).is_none() {
+                self.stack.pop();
+                return true;
+            }
+        }
+
+        for _ in 0..8 {
+            let Some((formatting_element, formatting_tag)) = self
+                .formatting_after_marker()
+                .iter()
+                .rev()
+                .flatten()
+                .find(|(node, _)| is(&node.name(), &[subject]))
+                .cloned()
+            else {
+                return false;
+            };
+            let Some(formatting_index) = self.stack.iter().position(|x| *x == formatting_element)
+            else {
+                self.error("formatting element not open");
+                self.formatting
+                    .retain(|x| x.as_ref().is_none_or(|x| x.0 != formatting_element));
+                return true;
+            };
+            if !self.element_in_scope(&formatting_element) {
+                self.error("formatting element not in scope");
+                return true;
+            }
+            if self.stack.last() != Some(&formatting_element) {
+                self.error("formatting element closed with other elements open");
+            }
+
+            let furthest_block = self.stack[formatting_index + 1..]
+                .iter()
+                .position(|x| is(&x.name(), SPECIAL))
+                .map(|x| formatting_index + 1 + x);
+            let Some(furthest_block_index) = furthest_block else {
+                self.stack.truncate(formatting_index);
+                self.formatting
+                    .retain(|x| x.as_ref().is_none_or(|x| x.0 != formatting_element));
+                return true;
+            };
+            let furthest_block = self.stack[furthest_block_index].clone();
+            let common_ancestor = self.stack[formatting_index - 1].clone();
+
+            // where the new formatting element goes in the list: in place
+            // of the old one, or after the given element
+            let mut bookmark: Option<Node> = None;
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block.clone();
+            let mut inner = 0;
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let node = self.stack[node_index].clone();
+                if node == formatting_element {
+                    break;
+                }
+                let mut entry = self.formatting_index(&node);
+                if inner > 3 {
+                    if let Some(i) = entry.take() {
+                        self.formatting.remove(i);
+                    }
+                }
+                let Some(entry) = entry else {
+                    self.stack.remove(node_index);
+                    continue;
+                };
+
+                let tag = self.formatting[entry].as_ref().unwrap().1.clone();
+                let new_node = Node::element(tag.name.clone(), tag.attrs.clone());
+                new_node.set_source_span(node.source_span());
+                self.formatting[entry] = Some((new_node.clone(), tag));
+                self.stack[node_index] = new_node.clone();
+                if last_node == furthest_block {
+                    bookmark = Some(new_node.clone());
+                }
+                new_node.insert_before(&[last_node.clone()], None);
+                last_node = new_node;
+            }
+
+            let (parent, before) = self.insertion_place(Some(common_ancestor));
+            parent.insert_before(&[last_node], before.as_ref());
+
+            let new_element =
+                Node::element(formatting_tag.name.clone(), formatting_tag.attrs.clone());
+            new_element.set_source_span(formatting_element.source_span());
+            let children = furthest_block.children().to_vec();
+            new_element.insert_before(&children, None);
+            furthest_block.insert_before(slice::from_ref(&new_element), None);
+
+            let entry = Some((new_element.clone(), formatting_tag));
+            match bookmark.and_then(|x| self.formatting_index(&x)) {
+                Some(i) => {
+                    self.formatting.insert(i + 1, entry);
+                    self.formatting
+                        .retain(|x| x.as_ref().is_none_or(|x| x.0 != formatting_element));
+                }
+                None => {
+                    let i = self.formatting_index(&formatting_element).unwrap();
+                    self.formatting[i] = entry;
+                }
+            }
+            self.stack.retain(|x| *x != formatting_element);
+            let i = self
+                .stack
+                .iter()
+                .position(|x| *x == furthest_block)
+                .unwrap();
+            self.stack.insert(i + 1, new_element);
+        }
+
+        true
+    }
+}
+
+/// the span that covers both spans, if either exists.
+fn join(a: Option<SourceSpan>, b: Option<SourceSpan>) -> Option<SourceSpan> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.to(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn new_tag(name: &str) -> Tag {
+    Tag {
+        name: name.to_owned(),
+        ..Default::default()
+    }
+}
+
+fn is(name: &str, names: &[&str]) -> bool {
+    names.contains(&name)
+}
+
+fn is_scope_boundary(name: &str, scope: Scope) -> bool {
+    const DEFAULT: &[&str] = &[
+        "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template",
+    ];
+    match scope {
+        Scope::Default => is(name, DEFAULT),
+        Scope::ListItem => is(name, DEFAULT) || is(name, &["ol", "ul"]),
+        Scope::Button => is(name, DEFAULT) || name == "button",
+        Scope::Table => is(name, &["html", "table", "template"]),
+        Scope::Select => !is(name, &["optgroup", "option"]),
+    }
+}
+
+fn same_attrs(a: &[(String, String)], b: &[(String, String)]) -> bool {
+    a.len() == b.len() && a.iter().all(|x| b.contains(x))
+}
+
+fn add_missing_attrs(element: &Node, attrs: Vec<(String, String)>) {
+    for (name, value) in attrs {
+        if element.attr(&name).is_none() {
+            element.set_attr(&name, value);
+        }
+    }
+}
+
+/// splits text into its leading whitespace and the rest.
+fn split_space(text: &str) -> (&str, &str) {
+    let rest = text.trim_start_matches(is_html_space);
+
+    text.split_at(text.len() - rest.len())
+}
+
+/// <https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode>
+fn is_quirky(doctype: &Doctype) -> bool {
+    let public_id = doctype.public_id.as_deref().map(|x| x.to_ascii_lowercase());
+    let system_id = doctype.system_id.as_deref().map(|x| x.to_ascii_lowercase());
+    let public_starts_with = |prefix: &str| {
+        public_id
+            .as_deref()
+            .is_some_and(|x| x.starts_with(&prefix.to_ascii_lowercase()))
+    };
+
+    doctype.force_quirks
+        || doctype.name.as_deref() != Some("html")
+        || matches!(
+            public_id.as_deref(),
+            Some(
+                "-//w3o//dtd w3 html strict 3.0//en//"
+                    | "-/w3c/dtd html 4.0 transitional/en"
+                    | "html"
+            )
+        )
+        || system_id.as_deref()
+            == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || QUIRKY_PUBLIC_ID_PREFIXES
+            .iter()
+            .any(|x| public_starts_with(x))
+        || (system_id.is_none()
+            && (public_starts_with("-//W3C//DTD HTML 4.01 Frameset//")
+                || public_starts_with("-//W3C//DTD HTML 4.01 Transitional//")))
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_tree_builder() -> eyre::Result<()> {
+    use crate::parse_html;
+
+    fn outline(node: &Node) -> String {
+        let children = node.children().iter().map(outline).collect::<Vec<_>>().join(" ");
+        match &*node.data() {
+            NodeData::Document => children,
+            NodeData::Element(name, ..) => format!("{}({})", name, children),
+            NodeData::Text(text, _) => format!("{:?}", text),
+            NodeData::Comment(text) => format!("<!--{}-->", text),
+        }
+    }
+    let body = |html: &str| -> eyre::Result<String> {
+        let dom = parse_html(html, &Default::default())?;
+        let body = dom.descendants().find(|x| &*x.name() == "body").unwrap();
+        Ok(outline(&body))
+    };
+
+    // implicit html, head, and body
+    assert_eq!(outline(&parse_html("x", &Default::default())?), r#"html(head() body("x"))"#);
+    assert_eq!(outline(&parse_html("<!doctype html><!--a--><title>a<b></title><meta charset=utf-8>\n<p>x", &Default::default())?),
+        r#"<!--a--> html(head(title("a<b>") meta() "\n") body(p("x")))"#);
+
+    // implied end tags
+    assert_eq!(body("<ul><li>a<li>b</ul><dl><dt>c<dd>d</dl><p>e<div>f</div>")?,
+        r#"body(ul(li("a") li("b")) dl(dt("c") dd("d")) p("e") div("f"))"#);
+    assert_eq!(body("<select><option>a<option>b</select><pre>\nc</pre>")?,
+        r#"body(select(option("a") option("b")) pre("c"))"#);
+
+    // misnested formatting elements, with the adoption agency algorithm
+    assert_eq!(body("<p>1<b>2<i>3</b>4</i>5</p>")?, r#"body(p("1" b("2" i("3")) i("4") "5"))"#);
+    assert_eq!(body("<b>1<p>2</b>3</p>")?, r#"body(b("1") p(b("2") "3"))"#);
+    assert_eq!(body("<a>1<a>2</a>")?, r#"body(a("1") a("2"))"#);
+    assert_eq!(body("<b><p>1</p>2")?, r#"body(b(p("1") "2"))"#);
+    assert_eq!(body("<p><b>1</p>2")?, r#"body(p(b("1")) b("2"))"#);
+
+    // foster parenting content in tables but not cells
+    assert_eq!(body("<table>a<tr><td>b</td></tr>c</table>")?, r#"body("ac" table(tbody(tr(td("b")))))"#);
+    assert_eq!(body("<table><td>a<td>b<tr><th>c</table>")?, r#"body(table(tbody(tr(td("a") td("b")) tr(th("c")))))"#);
+
+    // stray end tags are ignored
+    assert_eq!(body("a</span>b</p>c")?, r#"body("ab" p() "c")"#);
+
+    // nodes remember their start tag or text, even when reconstructed
+    let dom = parse_html("<title>t</title>\n<p class=x>a <b>b</p>c", &Default::default())?;
+    let span = |name: &str, i: usize| {
+        let node = dom.descendants().filter(|x| &*x.name() == name).nth(i).unwrap();
+        node.source_span().map(|x| x.to_string())
+    };
+    assert_eq!(span("head", 0), None);
+    assert_eq!(span("title", 0).as_deref(), Some("1:1-1:8"));
+    assert_eq!(span("#text", 0).as_deref(), Some("1:8-1:9"));
+    assert_eq!(span("#text", 1).as_deref(), Some("1:17-2:1"));
+    assert_eq!(span("p", 0).as_deref(), Some("2:1-2:12"));
+    assert_eq!(span("b", 0).as_deref(), Some("2:14-2:17"));
+    assert_eq!(span("b", 1).as_deref(), Some("2:14-2:17"));
+    assert_eq!(span("#text", 4).as_deref(), Some("2:22-2:23"));
+
+    Ok(())
+}
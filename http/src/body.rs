@@ -0,0 +1,194 @@
+use std::{
+    fmt::Debug,
+    io::{self, Cursor, ErrorKind, Read, Take},
+};
+
+use tracing::{debug, trace};
+
+use crate::{
+    pool::{Connection, PoolKey},
+    ChunkedReader, POOL,
+};
+
+/// a response body that can be read as it arrives, rather than all at once.
+pub struct Body(Box<dyn Read + Send>);
+
+/// how the server marks the end of a response body.
+/// <https://httpwg.org/specs/rfc9112.html#message.body.length>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Chunked,
+    Length(u64),
+    /// the body ends when the server closes the connection.
+    Close,
+}
+
+/// the body of a response on a connection, which goes back to the pool once
+/// the body has been read to the end (if the connection can be kept alive).
+pub struct MessageBody {
+    /// None once the body has been read to the end.
+    reader: Option<MessageReader>,
+    pool_key: Option<PoolKey>,
+    trailers: Vec<(String, String)>,
+}
+
+enum MessageReader {
+    Chunked(ChunkedReader<Connection>),
+    Length(Take<Connection>, u64),
+    Close(Connection),
+}
+
+impl Body {
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        Self(Box::new(reader))
+    }
+
+    pub fn empty() -> Self {
+        Self::new(io::empty())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(body: Vec<u8>) -> Self {
+        Self::new(Cursor::new(body))
+    }
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Body").finish_non_exhaustive()
+    }
+}
+
+impl MessageBody {
+    /// reads a body with the given framing from the connection, then returns
+    /// the connection to the pool with the given key, if any.
+    pub fn new(connection: Connection, framing: Framing, pool_key: Option<PoolKey>) -> Self {
+        let reader = match framing {
+            Framing::Chunked => MessageReader::Chunked(ChunkedReader::new(connection)),
+            Framing::Length(length) => MessageReader::Length(connection.take(length), length),
+            Framing::Close => MessageReader::Close(connection),
+        };
+        let mut result = Self {
+            reader: Some(reader),
+            pool_key: pool_key.filter(|_| framing != Framing::Close),
+            trailers: vec![],
+        };
+
+        // nobody needs to read an empty body before the connection can be reused
+        if framing == Framing::Length(0) {
+            result.finish();
+        }
+
+        result
+    }
+
+    /// whether the body has been read to the end, which is true from the
+    /// start for empty bodies, like responses to HEAD.
+    pub fn is_done(&self) -> bool {
+        self.reader.is_none()
+    }
+
+    /// trailer fields, which are only available once the body has been read to the end.
+    pub fn into_trailers(self) -> Vec<(String, String)> {
+        self.trailers
+    }
+
+    fn finish(&mut self) {
+        let mut connection = match self.reader.take() {
+            Some(MessageReader::Chunked(reader)) => {
+                let (connection, trailers) = reader.into_parts();
+                self.trailers = trailers;
+                connection
+            }
+            Some(MessageReader::Length(reader, _)) => reader.into_inner(),
+            Some(MessageReader::Close(connection)) => connection,
+            None => return,
+        };
+
+        // if the server sent more than the body, we can’t trust its framing
+        match self.pool_key.take() {
+            Some(key) if connection.buffer().is_empty() => {
+                connection.get_mut().set_limits(None);
+                POOL.checkin(key, connection);
+            }
+            _ => debug!("closing connection"),
+        }
+    }
+}
+
+impl Read for MessageBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match &mut self.reader {
+            None => return Ok(0),
+            Some(MessageReader::Chunked(reader)) => reader.read(buf)?,
+            Some(MessageReader::Length(reader, length)) => {
+                let len = reader.read(buf)?;
+                if len == 0 && reader.limit() > 0 && !buf.is_empty() {
+                    let received = *length - reader.limit();
+                    let reason =
+                        format!("connection closed after {} of {} bytes", received, length);
+                    return Err(io::Error::new(ErrorKind::UnexpectedEof, reason));
+                }
+                len
+            }
+            Some(MessageReader::Close(reader)) => reader.read(buf)?,
+        };
+        trace!(len);
+        if len == 0 && !buf.is_empty() {
+            self.finish();
+        }
+
+        Ok(len)
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_streaming() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{io::{BufRead, BufReader, Write}, net::TcpListener, sync::mpsc::channel, thread};
+
+    use crate::{Request, Settings, Url};
+
+    // a server that sends half of a chunked body, waits until we’ve read it,
+    // then sends the rest, and answers a second request on the same connection
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = Url::parse(&format!("http://{}/", listener.local_addr()?), None)?;
+    let (read_tx, read_rx) = channel();
+    let server = thread::spawn(move || -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        let mut reader = BufReader::new(stream);
+        for response in ["HTTP/1.1 200 OK\r\nCache-Control: no-store\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n", "7\r\n, world\r\n0\r\n\r\n", "HTTP/1.1 200 OK\r\nCache-Control: no-store\r\nContent-Length: 2\r\n\r\nok"] {
+            if response.starts_with("HTTP/") {
+                let mut request = String::new();
+                while !request.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut request)?;
+                }
+            } else {
+                read_rx.recv().unwrap();
+            }
+            reader.get_mut().write_all(response.as_bytes())?;
+        }
+        Ok(())
+    });
+
+    let mut response = Request::get(url.clone()).send_streaming(&Settings::default())?;
+    let mut buf = [0; 64];
+    let len = response.body.read(&mut buf)?;
+    assert_eq!(&buf[..len], b"hello");
+    read_tx.send(())?;
+    let mut rest = vec![];
+    response.body.read_to_end(&mut rest)?;
+    assert_eq!(rest, b", world");
+    let response = Request::get(url).send(&Settings::default())?;
+    assert_eq!(response.body, b"ok");
+    server.join().unwrap()?;
+
+    Ok(())
+}
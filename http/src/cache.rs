@@ -0,0 +1,565 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind, Read},
+    path::PathBuf,
+    str::{self, FromStr},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{debug, instrument, warn};
+
+use wbe_core::trim_ascii;
+
+use crate::{date::parse_http_date, Body, Headers, HttpError, Response, StreamingResponse, Url};
+
+/// statuses we can cache without explicit freshness information.
+/// <https://httpwg.org/specs/rfc9110.html#overview.of.status.codes>
+const HEURISTICALLY_CACHEABLE: [usize; 11] =
+    [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// fields in a 304 response that describe its own (empty) body, rather than
+/// the stored response, so they shouldn’t replace the stored values.
+const NOT_UPDATED_BY_304: [&str; 3] = ["content-length", "content-encoding", "transfer-encoding"];
+
+/// a private http cache, kept in memory and optionally on disk. clones
+/// share the same entries.
+/// <https://httpwg.org/specs/rfc9111.html>
+#[derive(Debug, Default, Clone)]
+pub struct HttpCache {
+    entries: Arc<Mutex<BTreeMap<String, CacheEntry>>>,
+    directory: Arc<Mutex<Option<PathBuf>>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub status: usize,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// when we sent the request for this response.
+    pub request_time: SystemTime,
+    /// when we received this response.
+    pub response_time: SystemTime,
+}
+
+impl HttpCache {
+    /// also keep entries in the given directory, so they outlive the process.
+    pub fn set_directory(&self, directory: Option<PathBuf>) {
+        if let Some(directory) = &directory {
+            if let Err(error) = fs::create_dir_all(directory) {
+                warn!(?directory, %error, "failed to create cache directory");
+            }
+        }
+        *self.directory.lock().unwrap() = directory;
+    }
+
+    /// forgets everything in memory, leaving anything on disk alone.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// the entries in memory, keyed by url without fragment.
+    pub fn entries(&self) -> BTreeMap<String, CacheEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// gets a response for the given url, from the cache if we have a fresh
+    /// response, otherwise by calling `fetch` with any extra request headers
+    /// needed to revalidate a stale response.
+    pub fn fetch(
+        &self,
+        url: &Url,
+        fetch: impl FnOnce(&[(&str, &str)]) -> Result<Response, HttpError>,
+    ) -> Result<Response, HttpError> {
+        self.fetch_streaming(url, |x| fetch(x).map(Response::into_streaming))?
+            .into_response()
+    }
+
+    /// like [`HttpCache::fetch`], but for responses whose bodies arrive as
+    /// they’re read. we only store the response once its body has been read
+    /// to the end, so an interrupted body never gets reused.
+    #[instrument(skip(self, fetch))]
+    pub fn fetch_streaming(
+        &self,
+        url: &Url,
+        fetch: impl FnOnce(&[(&str, &str)]) -> Result<StreamingResponse, HttpError>,
+    ) -> Result<StreamingResponse, HttpError> {
+        let key = key(url);
+        let stored = self.get(&key);
+        let validators = match &stored {
+            Some(entry) if entry.is_fresh(SystemTime::now()) => {
+                debug!(%url, "cache hit");
+                return Ok(entry.to_response(url).into_streaming());
+            }
+            Some(entry) => {
+                debug!(%url, "cache hit, but stale");
+                entry.validators()
+            }
+            None => {
+                debug!(%url, "cache miss");
+                vec![]
+            }
+        };
+
+        let request_time = SystemTime::now();
+        let headers = validators
+            .iter()
+            .map(|(field, value)| (*field, &**value))
+            .collect::<Vec<_>>();
+        let mut response = fetch(&headers)?;
+        let response_time = SystemTime::now();
+
+        // https://httpwg.org/specs/rfc9111.html#freshening.responses
+        if let (304, Some(mut entry)) = (response.status, stored) {
+            debug!(%url, "not modified");
+            io::copy(&mut response.body, &mut io::sink())?;
+            for (field, _) in response.headers.iter() {
+                if !NOT_UPDATED_BY_304.contains(&field) {
+                    entry.headers.remove(field);
+                }
+            }
+            for (field, value) in response.headers {
+                if !NOT_UPDATED_BY_304.contains(&&*field) {
+                    entry.headers.append(field, value);
+                }
+            }
+            entry.request_time = request_time;
+            entry.response_time = response_time;
+            let result = entry.to_response(url);
+            self.put(key, entry);
+            return Ok(result.into_streaming());
+        }
+
+        let entry = CacheEntry {
+            status: response.status,
+            headers: response.headers.clone(),
+            body: vec![],
+            request_time,
+            response_time,
+        };
+        if entry.is_storable() {
+            response.body = Body::new(StoringBody {
+                inner: response.body,
+                cache: self.clone(),
+                key,
+                entry: Some(entry),
+            });
+        } else {
+            self.remove(&key);
+        }
+
+        Ok(response)
+    }
+
+    /// forgets any response for the given url, like after an unsafe request.
+    /// <https://httpwg.org/specs/rfc9111.html#invalidation>
+    pub fn invalidate(&self, url: &Url) {
+        debug!(%url, "invalidating");
+        self.remove(&key(url));
+    }
+
+    fn get(&self, key: &str) -> Option<CacheEntry> {
+        if let Some(entry) = self.entries.lock().unwrap().get(key) {
+            return Some(entry.clone());
+        }
+        let path = self.path(key)?;
+        let entry = match fs::read(&path) {
+            Ok(contents) => CacheEntry::read(&contents, key)?,
+            Err(error) if error.kind() == ErrorKind::NotFound => return None,
+            Err(error) => {
+                warn!(?path, %error, "failed to read cache entry");
+                return None;
+            }
+        };
+        debug!(?path, "loaded from disk");
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), entry.clone());
+
+        Some(entry)
+    }
+
+    fn put(&self, key: String, entry: CacheEntry) {
+        if let Some(path) = self.path(&key) {
+            if let Err(error) = fs::write(&path, entry.write(&key)) {
+                warn!(?path, %error, "failed to write cache entry");
+            }
+        }
+        self.entries.lock().unwrap().insert(key, entry);
+    }
+
+    fn remove(&self, key: &str) {
+        if let Some(path) = self.path(key) {
+            match fs::remove_file(&path) {
+                Err(error) if error.kind() != ErrorKind::NotFound => {
+                    warn!(?path, %error, "failed to remove cache entry");
+                }
+                _ => {}
+            }
+        }
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        let directory = self.directory.lock().unwrap();
+
+        Some(
+            directory
+                .as_ref()?
+                .join(format!("{:016x}", fnv1a(key.as_bytes()))),
+        )
+    }
+}
+
+/// a body that stores its response in the cache once read to the end.
+struct StoringBody {
+    inner: Body,
+    cache: HttpCache,
+    key: String,
+    /// None once stored.
+    entry: Option<CacheEntry>,
+}
+
+impl Read for StoringBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if let Some(entry) = &mut self.entry {
+            entry.body.extend_from_slice(&buf[..len]);
+            if len == 0 && !buf.is_empty() {
+                let entry = self.entry.take().unwrap();
+                debug!(key = self.key, lifetime = ?entry.freshness_lifetime(), "storing response");
+                self.cache.put(self.key.clone(), entry);
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+impl CacheEntry {
+    /// <https://httpwg.org/specs/rfc9111.html#response.cacheability>
+    pub fn is_storable(&self) -> bool {
+        let directives = cache_control(&self.headers);
+        if directives.contains_key("no-store")
+            || !(200..600).contains(&self.status)
+            || matches!(self.status, 206 | 304)
+        {
+            return false;
+        }
+        // vary: * means the response depends on more than our request headers
+        if self.headers.get_list("vary").any(|x| x == "*") {
+            return false;
+        }
+
+        // there’s no point keeping a response we can neither reuse nor revalidate
+        self.freshness_lifetime() > Duration::ZERO || !self.validators().is_empty()
+    }
+
+    /// <https://httpwg.org/specs/rfc9111.html#expiration.model>
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        // no-cache means we always have to revalidate first
+        if cache_control(&self.headers).contains_key("no-cache") {
+            return false;
+        }
+
+        self.freshness_lifetime() > self.current_age(now)
+    }
+
+    /// <https://httpwg.org/specs/rfc9111.html#calculating.freshness.lifetime>
+    pub fn freshness_lifetime(&self) -> Duration {
+        let directives = cache_control(&self.headers);
+        if let Some(max_age) = directives.get("max-age") {
+            // an invalid max-age means the response is already stale
+            let max_age = max_age.as_deref().and_then(|x| u64::from_str(x).ok());
+            return Duration::from_secs(max_age.unwrap_or(0));
+        }
+        if let Some(expires) = self.headers.get("expires") {
+            // and so does an invalid expires, like “0”
+            return parse_http_date(expires)
+                .and_then(|x| x.duration_since(self.date()).ok())
+                .unwrap_or_default();
+        }
+
+        // https://httpwg.org/specs/rfc9111.html#heuristic.freshness
+        let last_modified = self.headers.get("last-modified").and_then(parse_http_date);
+        match last_modified {
+            Some(last_modified)
+                if HEURISTICALLY_CACHEABLE.contains(&self.status)
+                    || directives.contains_key("public") =>
+            {
+                self.date()
+                    .duration_since(last_modified)
+                    .unwrap_or_default()
+                    / 10
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// <https://httpwg.org/specs/rfc9111.html#age.calculations>
+    pub fn current_age(&self, now: SystemTime) -> Duration {
+        let age_value = self.headers.get("age").and_then(|x| u64::from_str(x).ok());
+        let age_value = Duration::from_secs(age_value.unwrap_or(0));
+        let apparent_age = self
+            .response_time
+            .duration_since(self.date())
+            .unwrap_or_default();
+        let response_delay = self
+            .response_time
+            .duration_since(self.request_time)
+            .unwrap_or_default();
+        let corrected_initial_age = apparent_age.max(age_value + response_delay);
+        let resident_time = now.duration_since(self.response_time).unwrap_or_default();
+
+        corrected_initial_age + resident_time
+    }
+
+    /// extra request headers that let the server tell us the stored response
+    /// is still good, with a 304 Not Modified.
+    /// <https://httpwg.org/specs/rfc9110.html#conditional.requests>
+    pub fn validators(&self) -> Vec<(&'static str, String)> {
+        let mut result = vec![];
+        if let Some(etag) = self.headers.get("etag") {
+            result.push(("If-None-Match", etag.to_owned()));
+        }
+        if let Some(last_modified) = self.headers.get("last-modified") {
+            result.push(("If-Modified-Since", last_modified.to_owned()));
+        }
+
+        result
+    }
+
+    fn date(&self) -> SystemTime {
+        self.headers
+            .get("date")
+            .and_then(parse_http_date)
+            .unwrap_or(self.response_time)
+    }
+
+    fn to_response(&self, url: &Url) -> Response {
+        Response {
+            url: url.clone(),
+            status: self.status,
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+        }
+    }
+
+    /// serialises the entry for disk, as the key, then the status and times,
+    /// then the headers, then a blank line and the body.
+    fn write(&self, key: &str) -> Vec<u8> {
+        let seconds = |x: SystemTime| x.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut result = vec![];
+        let mut head = format!("{}\n", key);
+        head += &format!(
+            "{} {} {}\n",
+            self.status,
+            seconds(self.request_time),
+            seconds(self.response_time)
+        );
+        for (field, value) in self.headers.iter() {
+            head += &format!("{}: {}\n", field, value);
+        }
+        head += "\n";
+        result.extend_from_slice(head.as_bytes());
+        result.extend_from_slice(&self.body);
+
+        result
+    }
+
+    fn read(mut contents: &[u8], key: &str) -> Option<Self> {
+        let mut line = || -> Option<&str> {
+            let end = contents.iter().position(|x| *x == b'\n')?;
+            let line = str::from_utf8(&contents[..end]).ok()?;
+            contents = &contents[end + 1..];
+            Some(line)
+        };
+
+        // different urls might hash to the same file
+        if line()? != key {
+            return None;
+        }
+        let time = |x: &str| Some(UNIX_EPOCH + Duration::from_secs(u64::from_str(x).ok()?));
+        let (status, request_time, response_time) = match line()?.split(' ').collect::<Vec<_>>()[..]
+        {
+            [status, request_time, response_time] => (
+                usize::from_str(status).ok()?,
+                time(request_time)?,
+                time(response_time)?,
+            ),
+            _ => return None,
+        };
+        let mut headers = Headers::new();
+        loop {
+            match line()? {
+                "" => break,
+                header => {
+                    let (field, value) = header.split_once(": ")?;
+                    headers.append(field, value);
+                }
+            }
+        }
+
+        Some(Self {
+            status,
+            headers,
+            body: contents.to_vec(),
+            request_time,
+            response_time,
+        })
+    }
+}
+
+/// parses the cache-control directives, with names in lowercase, and values
+/// unquoted if they were quoted.
+/// <https://httpwg.org/specs/rfc9111.html#field.cache-control>
+fn cache_control(headers: &Headers) -> BTreeMap<String, Option<String>> {
+    let mut result = BTreeMap::default();
+    let Some(input) = headers.get_combined("cache-control") else { return result };
+    let mut input = &*input;
+    while !input.is_empty() {
+        // find the end of the directive, skipping over commas in quotes
+        let mut quoted = false;
+        let end = input
+            .char_indices()
+            .find(|(_, x)| {
+                quoted ^= *x == '"';
+                *x == ',' && !quoted
+            })
+            .map_or(input.len(), |(i, _)| i);
+        let (directive, rest) = input.split_at(end);
+        input = rest.strip_prefix(',').unwrap_or(rest);
+
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => {
+                let value = trim_ascii(value);
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|x| x.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name, Some(value.to_owned()))
+            }
+            None => (directive, None),
+        };
+        let name = trim_ascii(name).to_ascii_lowercase();
+        if !name.is_empty() {
+            // when a directive is repeated, the first one wins
+            result.entry(name).or_insert(value);
+        }
+    }
+
+    result
+}
+
+fn key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+
+    url.to_string()
+}
+
+/// a stable hash for cache file names.
+/// <http://www.isthe.com/chongo/tech/comp/fnv/>
+fn fnv1a(input: &[u8]) -> u64 {
+    input.iter().fold(0xcbf29ce484222325, |hash, x| {
+        (hash ^ *x as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_http_cache() -> Result<(), HttpError> {
+    let url = Url::parse("http://example.org/", None)?;
+    let respond = |status, headers: &[(&str, &str)], body: &str| Response {
+        url: url.clone(),
+        status,
+        headers: headers.iter().copied().collect(),
+        body: body.into(),
+    };
+    let fetch = |cache: &HttpCache, headers: &[(&str, &str)], body: &str| -> Result<(Option<Vec<String>>, Response), HttpError> {
+        let mut sent = None;
+        let response = cache.fetch(&url, |x| {
+            sent = Some(x.iter().map(|(k, v)| format!("{}: {}", k, v)).collect());
+            Ok(respond(200, headers, body))
+        })?;
+        Ok((sent, response))
+    };
+
+    // fresh responses are reused without a request, even with a fragment
+    let cache = HttpCache::default();
+    assert_eq!(fetch(&cache, &[("cache-control", "max-age=3600")], "one")?.0, Some(vec![]));
+    let (sent, response) = fetch(&cache, &[], "two")?;
+    assert_eq!((sent, &*response.body), (None, &b"one"[..]));
+    assert_eq!(cache.fetch(&Url::parse("http://example.org/#top", None)?, |_| unreachable!())?.body, b"one");
+
+    // responses we can’t reuse or revalidate aren’t stored
+    for headers in [
+        &[("cache-control", "no-store, max-age=3600")][..],
+        &[("cache-control", "max-age=3600"), ("vary", "*")][..],
+        &[("cache-control", "max-age=0")][..],
+        &[("expires", "0")][..],
+        &[][..],
+    ] {
+        let cache = HttpCache::default();
+        fetch(&cache, headers, "one")?;
+        assert_eq!(fetch(&cache, headers, "two")?.1.body, b"two", "{:?}", headers);
+    }
+
+    // stale responses are revalidated, and a 304 freshens the stored response
+    let cache = HttpCache::default();
+    fetch(&cache, &[("cache-control", "no-cache"), ("etag", "\"abc\""), ("content-type", "text/plain")], "one")?;
+    let mut sent = None;
+    let response = cache.fetch(&url, |x| {
+        sent = Some(x.iter().map(|(k, v)| format!("{}: {}", k, v)).collect::<Vec<_>>());
+        Ok(respond(304, &[("cache-control", "max-age=3600"), ("content-length", "0")], ""))
+    })?;
+    assert_eq!(sent, Some(vec!["If-None-Match: \"abc\"".to_owned()]));
+    assert_eq!((response.status, &*response.body), (200, &b"one"[..]));
+    assert_eq!(response.headers.get("content-type"), Some("text/plain"));
+    assert_eq!(response.headers.get("content-length"), None);
+    assert_eq!(fetch(&cache, &[], "two")?.1.body, b"one");
+
+    // responses that were already old when we got them are stale
+    let cache = HttpCache::default();
+    let headers = [("date", "Sun, 06 Nov 1994 08:49:37 GMT"), ("cache-control", "max-age=60"), ("last-modified", "Sat, 05 Nov 1994 08:49:37 GMT")];
+    fetch(&cache, &headers, "one")?;
+    let (sent, response) = fetch(&cache, &headers, "two")?;
+    assert_eq!(sent, Some(vec!["If-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT".to_owned()]));
+    assert_eq!(response.body, b"two");
+
+    // heuristic freshness is a tenth of the time since last modified
+    let entry = CacheEntry { status: 200, headers: respond(200, &headers[..1], "").headers, body: vec![], request_time: UNIX_EPOCH, response_time: UNIX_EPOCH };
+    let mut headers = entry.headers.clone();
+    headers.insert("last-modified", "Sun, 06 Nov 1994 07:49:37 GMT");
+    assert_eq!(CacheEntry { headers, ..entry.clone() }.freshness_lifetime(), Duration::from_secs(360));
+    let mut headers = entry.headers.clone();
+    headers.insert("expires", "Sun, 06 Nov 1994 09:49:37 GMT");
+    assert_eq!(CacheEntry { headers, ..entry.clone() }.freshness_lifetime(), Duration::from_secs(3600));
+    let mut headers = entry.headers.clone();
+    headers.insert("cache-control", "Public, MAX-AGE=\"120\", max-age=60");
+    headers.insert("age", "100");
+    let entry = CacheEntry { headers, ..entry };
+    assert_eq!(entry.freshness_lifetime(), Duration::from_secs(120));
+    assert!(entry.is_fresh(UNIX_EPOCH + Duration::from_secs(19)));
+    assert!(!entry.is_fresh(UNIX_EPOCH + Duration::from_secs(20)));
+
+    // entries on disk outlive the cache that wrote them
+    let directory = std::env::temp_dir().join(format!("wbe-http-cache-{}", std::process::id()));
+    let cache = HttpCache::default();
+    cache.set_directory(Some(directory.clone()));
+    fetch(&cache, &[("cache-control", "max-age=3600"), ("x-foo", "bar: baz")], "one\n\ntwo")?;
+    let cache = HttpCache::default();
+    cache.set_directory(Some(directory.clone()));
+    let (sent, response) = fetch(&cache, &[], "three")?;
+    assert_eq!((sent, &*response.body), (None, &b"one\n\ntwo"[..]));
+    assert_eq!(response.headers.get("x-foo"), Some("bar: baz"));
+    cache.invalidate(&url);
+    let cache = HttpCache::default();
+    cache.set_directory(Some(directory.clone()));
+    assert_eq!(fetch(&cache, &[], "three")?.1.body, b"three");
+    fs::remove_dir_all(directory).ok();
+
+    Ok(())
+}
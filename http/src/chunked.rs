@@ -0,0 +1,155 @@
+use std::{
+    io::{self, BufRead, ErrorKind, Read},
+    str,
+};
+
+use tracing::{debug, trace};
+
+use wbe_core::trim_ascii;
+
+/// decodes a body sent with `transfer-encoding: chunked`.
+/// <https://httpwg.org/specs/rfc9112.html#chunked.encoding>
+pub struct ChunkedReader<R> {
+    inner: R,
+    state: ChunkedState,
+    trailers: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChunkedState {
+    Size,
+    Data(usize),
+    DataEnd,
+    Done,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            state: ChunkedState::Size,
+            trailers: vec![],
+        }
+    }
+
+    /// trailer fields, which are only available once the body has been read to the end.
+    pub fn into_trailers(self) -> Vec<(String, String)> {
+        self.trailers
+    }
+
+    /// the inner reader and any trailer fields, so the connection can be reused.
+    pub fn into_parts(self) -> (R, Vec<(String, String)>) {
+        (self.inner, self.trailers)
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = vec![];
+        if self.inner.read_until(b'\n', &mut line)? == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "unexpected end of chunked body",
+            ));
+        }
+
+        // be lenient about bare LF, like most clients are
+        let line = line.strip_suffix(b"\n").unwrap_or(&line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Ok(line) = str::from_utf8(line)
+            else { return Err(invalid_data("non-utf-8 line in chunked body")) };
+
+        Ok(line.to_owned())
+    }
+
+    fn read_size(&mut self) -> io::Result<usize> {
+        // chunk-size [ chunk-ext ] CRLF, where chunk extensions are ignored
+        let line = self.read_line()?;
+        let (size, extensions) = line.split_once(';').unwrap_or((&line, ""));
+        trace!(size, extensions);
+        let size = trim_ascii(size);
+        if size.is_empty() || !size.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(invalid_data(format!("malformed chunk size: {:?}", line)));
+        }
+
+        usize::from_str_radix(size, 16)
+            .map_err(|_| invalid_data(format!("chunk size too large: {:?}", line)))
+    }
+
+    fn read_trailers(&mut self) -> io::Result<()> {
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(());
+            }
+            let Some((field, value)) = line.split_once(':')
+                else { return Err(invalid_data(format!("malformed trailer field: {:?}", line))) };
+            debug!(field, value, "trailer");
+            self.trailers.push((
+                trim_ascii(field).to_ascii_lowercase(),
+                trim_ascii(value).to_owned(),
+            ));
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                ChunkedState::Size => match self.read_size()? {
+                    0 => {
+                        self.read_trailers()?;
+                        self.state = ChunkedState::Done;
+                    }
+                    size => self.state = ChunkedState::Data(size),
+                },
+                ChunkedState::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let len = buf.len().min(remaining);
+                    let len = self.inner.read(&mut buf[..len])?;
+                    if len == 0 {
+                        return Err(io::Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "unexpected end of chunk data",
+                        ));
+                    }
+                    self.state = match remaining - len {
+                        0 => ChunkedState::DataEnd,
+                        remaining => ChunkedState::Data(remaining),
+                    };
+
+                    return Ok(len);
+                }
+                ChunkedState::DataEnd => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(invalid_data("missing CRLF after chunk data"));
+                    }
+                    self.state = ChunkedState::Size;
+                }
+                ChunkedState::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_chunked() -> io::Result<()> {
+    let mut body = vec![];
+    let mut reader = ChunkedReader::new(&b"4;ext=\"x\"\r\nWiki\r\n5\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\n"[..]);
+    reader.read_to_end(&mut body)?;
+    assert_eq!(body, b"Wikipedia in\r\n\r\nchunks.");
+    assert_eq!(reader.into_trailers(), [("expires".to_owned(), "never".to_owned())]);
+
+    let mut body = vec![];
+    assert_eq!(ChunkedReader::new(&b"0x10\r\n"[..]).read_to_end(&mut body).map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+    assert_eq!(ChunkedReader::new(&b"4\r\nWikipedia"[..]).read_to_end(&mut body).map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+    assert_eq!(ChunkedReader::new(&b"4\r\nWi"[..]).read_to_end(&mut body).map_err(|e| e.kind()), Err(ErrorKind::UnexpectedEof));
+
+    Ok(())
+}
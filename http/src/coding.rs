@@ -0,0 +1,121 @@
+use std::io::{self, Cursor, ErrorKind, Read};
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use tracing::{debug, instrument};
+
+use wbe_core::trim_ascii;
+
+/// value of the `accept-encoding` request header, listing codings we can decode.
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// undoes the content codings listed in a `content-encoding` header value,
+/// in the reverse of the order they were applied.
+/// <https://httpwg.org/specs/rfc9110.html#field.content-encoding>
+#[instrument(skip(body))]
+pub fn decode_content(body: Vec<u8>, codings: &str) -> io::Result<Vec<u8>> {
+    // e.g. responses to HEAD, or 204 and 304 responses
+    if body.is_empty() {
+        return Ok(body);
+    }
+
+    let encoded = body.len();
+    let mut result = vec![];
+    content_decoder(Box::new(Cursor::new(body)), codings)?.read_to_end(&mut result)?;
+    debug!(codings, encoded, decoded = result.len());
+
+    Ok(result)
+}
+
+/// wraps a reader in decoders for the content codings listed in a
+/// `content-encoding` header value, so the body can be decoded as it arrives.
+pub fn content_decoder(
+    mut reader: Box<dyn Read + Send>,
+    codings: &str,
+) -> io::Result<Box<dyn Read + Send>> {
+    for coding in codings.rsplit(',').map(trim_ascii) {
+        reader = match &*coding.to_ascii_lowercase() {
+            "" | "identity" => continue,
+            "gzip" | "x-gzip" => Box::new(MultiGzDecoder::new(reader)),
+            "deflate" => {
+                // deflate is supposed to be zlib-wrapped, but some servers send raw deflate
+                let header = read_up_to(&mut reader, 2)?;
+                let zlib = is_zlib_header(&header);
+                let reader = Cursor::new(header).chain(reader);
+                match zlib {
+                    true => Box::new(ZlibDecoder::new(reader)),
+                    false => Box::new(DeflateDecoder::new(reader)),
+                }
+            }
+            "br" => Box::new(brotli::Decompressor::new(reader, 4096)),
+            other => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported content-encoding: {:?}", other),
+                ))
+            }
+        };
+        debug!(coding, "decoding");
+    }
+
+    Ok(reader)
+}
+
+/// reads until we have the given number of bytes, or there are no more.
+fn read_up_to(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut result = vec![];
+    reader.take(len as u64).read_to_end(&mut result)?;
+
+    Ok(result)
+}
+
+/// whether the data starts with a zlib header, using the deflate method.
+/// <https://www.rfc-editor.org/rfc/rfc1950#section-2.2>
+fn is_zlib_header(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => cmf & 0x0F == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_decode_content() -> io::Result<()> {
+    use std::io::Write;
+    use flate2::{write::{DeflateEncoder, GzEncoder, ZlibEncoder}, Compression};
+
+    let mut gzip = GzEncoder::new(vec![], Compression::default());
+    gzip.write_all(b"hello, world")?;
+    let gzip = gzip.finish()?;
+    let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+    zlib.write_all(b"hello, world")?;
+    let zlib = zlib.finish()?;
+    let mut deflate = DeflateEncoder::new(vec![], Compression::default());
+    deflate.write_all(b"hello, world")?;
+    let deflate = deflate.finish()?;
+    let mut br = vec![];
+    brotli::CompressorReader::new(&gzip[..], 4096, 5, 22).read_to_end(&mut br)?;
+
+    assert_eq!(decode_content(gzip.clone(), "gzip")?, b"hello, world");
+    assert_eq!(decode_content(zlib.clone(), "Deflate")?, b"hello, world");
+    assert_eq!(decode_content(deflate.clone(), "deflate")?, b"hello, world");
+    assert_eq!(decode_content(br.clone(), "gzip, br")?, b"hello, world");
+    assert_eq!(decode_content(b"hello".to_vec(), "identity")?, b"hello");
+
+    // the zlib header can arrive split across reads
+    struct Trickle(Cursor<Vec<u8>>);
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+    let mut result = vec![];
+    content_decoder(Box::new(Trickle(Cursor::new(zlib.clone()))), "deflate")?.read_to_end(&mut result)?;
+    assert_eq!(result, b"hello, world");
+    let mut result = vec![];
+    content_decoder(Box::new(Trickle(Cursor::new(deflate.clone()))), "deflate")?.read_to_end(&mut result)?;
+    assert_eq!(result, b"hello, world");
+    assert_eq!(decode_content(b"hello".to_vec(), "compress").map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+
+    Ok(())
+}
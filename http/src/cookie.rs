@@ -0,0 +1,483 @@
+use std::{
+    cmp::Reverse,
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{debug, instrument};
+
+use wbe_core::trim_ascii;
+
+use crate::{date::days_from_civil, Url};
+
+/// browsers are only required to store cookies up to this size.
+/// <https://httpwg.org/specs/rfc6265.html#implementation-limits>
+const MAX_COOKIE_SIZE: usize = 4096;
+
+/// cookies can’t last longer than this, however far off they say they expire.
+/// <https://httpwg.org/http-extensions/draft-ietf-httpbis-rfc6265bis.html#name-the-max-age-attribute-2>
+const MAX_AGE: Duration = Duration::from_secs(400 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    pub path: String,
+    /// when the cookie expires, or None if it lasts until the session ends.
+    pub expiry: Option<SystemTime>,
+    /// whether the cookie is only for the exact host, not its subdomains.
+    pub host_only: bool,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSite,
+    pub creation_time: SystemTime,
+    pub last_access_time: SystemTime,
+}
+
+impl Cookie {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        matches!(self.expiry, Some(expiry) if expiry <= now)
+    }
+}
+
+/// <https://httpwg.org/http-extensions/draft-ietf-httpbis-rfc6265bis.html#name-the-samesite-attribute-2>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    /// also the default, like in most browsers.
+    Lax,
+    None,
+}
+
+/// cookies from every site, as received in set-cookie fields.
+/// <https://httpwg.org/specs/rfc6265.html#storage-model>
+#[derive(Debug, Default)]
+pub struct CookieJar(Mutex<Vec<Cookie>>);
+
+impl CookieJar {
+    /// stores the cookie from a set-cookie field in a response to the given url.
+    #[instrument(skip(self))]
+    pub fn set_cookie(&self, url: &Url, set_cookie: &str) {
+        let now = SystemTime::now();
+        let Some(mut cookie) = parse_set_cookie(url, set_cookie, now) else {
+            debug!("ignoring cookie");
+            return;
+        };
+        let mut cookies = self.0.lock().unwrap();
+
+        // insecure sites can’t overwrite secure cookies
+        // https://httpwg.org/http-extensions/draft-ietf-httpbis-rfc6265bis.html#name-storage-model
+        if !cookie.secure
+            && url.scheme() != "https"
+            && cookies.iter().any(|x| {
+                x.secure
+                    && x.name == cookie.name
+                    && (domain_match(&x.domain, &cookie.domain)
+                        || domain_match(&cookie.domain, &x.domain))
+                    && path_match(&cookie.path, &x.path)
+            })
+        {
+            debug!("ignoring insecure cookie that would overwrite secure cookie");
+            return;
+        }
+
+        let existing = cookies.iter().position(|x| {
+            x.name == cookie.name && x.domain == cookie.domain && x.path == cookie.path
+        });
+        if let Some(index) = existing {
+            cookie.creation_time = cookies[index].creation_time;
+        }
+        if cookie.is_expired(now) {
+            debug!(name = cookie.name, "removing expired cookie");
+            if let Some(index) = existing {
+                cookies.remove(index);
+            }
+            return;
+        }
+        debug!(
+            name = cookie.name,
+            domain = cookie.domain,
+            path = cookie.path,
+            "storing cookie"
+        );
+        match existing {
+            Some(index) => cookies[index] = cookie,
+            None => cookies.push(cookie),
+        }
+    }
+
+    /// the cookie field to send in a request to the given url, if any.
+    ///
+    /// `site_for_cookies` is the url of the document that caused the request,
+    /// or None if the user did, like when navigating with the location bar.
+    /// cross-site requests only get lax cookies if `safe_navigation`, that is,
+    /// if they are top-level navigations with a safe method like GET.
+    /// <https://httpwg.org/http-extensions/draft-ietf-httpbis-rfc6265bis.html#name-retrieval-algorithm>
+    #[instrument(skip(self))]
+    pub fn cookie_header(
+        &self,
+        url: &Url,
+        site_for_cookies: Option<&Url>,
+        safe_navigation: bool,
+    ) -> Option<String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+        let now = SystemTime::now();
+        let host = url.hostname().to_ascii_lowercase();
+        let path = url.pathname();
+        let same_site = match site_for_cookies {
+            Some(site_for_cookies) => is_same_site(url, site_for_cookies),
+            None => true,
+        };
+
+        let mut cookies = self.0.lock().unwrap();
+        cookies.retain(|x| !x.is_expired(now));
+        let mut result = cookies
+            .iter_mut()
+            .filter(|x| match x.host_only {
+                true => host == x.domain,
+                false => domain_match(&host, &x.domain),
+            })
+            .filter(|x| path_match(&path, &x.path))
+            .filter(|x| !x.secure || url.scheme() == "https")
+            .filter(|x| match x.same_site {
+                _ if same_site => true,
+                SameSite::Strict => false,
+                SameSite::Lax => safe_navigation,
+                SameSite::None => true,
+            })
+            .collect::<Vec<_>>();
+        if result.is_empty() {
+            return None;
+        }
+
+        // longer paths first, then older cookies first
+        result.sort_by_key(|x| (Reverse(x.path.len()), x.creation_time));
+        for cookie in result.iter_mut() {
+            cookie.last_access_time = now;
+        }
+        let result = result
+            .iter()
+            .map(|x| match x.name.is_empty() {
+                true => x.value.clone(),
+                false => format!("{}={}", x.name, x.value),
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        debug!(cookie = result);
+
+        Some(result)
+    }
+
+    /// every cookie we have that hasn’t expired yet.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = SystemTime::now();
+
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| !x.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+/// <https://httpwg.org/specs/rfc6265.html#set-cookie>
+fn parse_set_cookie(url: &Url, set_cookie: &str, now: SystemTime) -> Option<Cookie> {
+    if !matches!(url.scheme(), "http" | "https") || set_cookie.len() > MAX_COOKIE_SIZE {
+        return None;
+    }
+    let (pair, attributes) = set_cookie.split_once(';').unwrap_or((set_cookie, ""));
+
+    // a pair without “=” is a cookie with an empty name, per rfc 6265bis
+    let (name, value) = pair.split_once('=').unwrap_or(("", pair));
+    let (name, value) = (trim_ascii(name), trim_ascii(value));
+    if name.is_empty() && value.is_empty() {
+        return None;
+    }
+
+    let host = url.hostname().to_ascii_lowercase();
+    let mut cookie = Cookie {
+        name: name.to_owned(),
+        value: value.to_owned(),
+        domain: host.clone(),
+        path: default_path(url),
+        expiry: None,
+        host_only: true,
+        secure: false,
+        http_only: false,
+        same_site: SameSite::Lax,
+        creation_time: now,
+        last_access_time: now,
+    };
+
+    // for each attribute, the last one wins, but max-age beats expires
+    let mut max_age = None;
+    let mut expires = None;
+    let mut domain = None;
+    for attribute in attributes.split(';') {
+        let (name, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let (name, value) = (trim_ascii(name), trim_ascii(value));
+        match &*name.to_ascii_lowercase() {
+            "expires" => expires = parse_cookie_date(value).or(expires),
+            "max-age" => {
+                let valid = value.starts_with(|x: char| x == '-' || x.is_ascii_digit())
+                    && value[1..].bytes().all(|x| x.is_ascii_digit());
+                if let (true, Ok(seconds)) = (valid, i64::from_str(value)) {
+                    // zero or negative means the cookie has already expired
+                    max_age = Some(match u64::try_from(seconds) {
+                        Ok(seconds) if seconds > 0 => {
+                            now + Duration::from_secs(seconds).min(MAX_AGE)
+                        }
+                        _ => UNIX_EPOCH,
+                    });
+                }
+            }
+            "domain" if !value.is_empty() => {
+                let value = value.strip_prefix('.').unwrap_or(value);
+                domain = Some(value.to_ascii_lowercase());
+            }
+            "path" => {
+                cookie.path = match value.starts_with('/') {
+                    true => value.to_owned(),
+                    false => default_path(url),
+                };
+            }
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "samesite" => {
+                cookie.same_site = match &*value.to_ascii_lowercase() {
+                    "strict" => SameSite::Strict,
+                    "none" => SameSite::None,
+                    _ => SameSite::Lax,
+                }
+            }
+            _ => {}
+        }
+    }
+    cookie.expiry = max_age.or(expires).map(|x| x.min(now + MAX_AGE));
+
+    // we don’t have the public suffix list, so we can’t reject cookies for
+    // domains like “co.uk”, but we can at least insist on the request host
+    // being in the given domain, and reject single-label domains like “com”
+    // unless they are the request host, in which case the cookie stays
+    // host-only (rfc 6265 § 5.3 step 5)
+    if let Some(domain) = domain {
+        if !domain_match(&host, &domain) {
+            return None;
+        }
+        if domain.contains('.') {
+            cookie.host_only = false;
+            cookie.domain = domain;
+        } else if domain != host {
+            return None;
+        }
+    }
+
+    // https://httpwg.org/http-extensions/draft-ietf-httpbis-rfc6265bis.html#name-storage-model
+    if cookie.secure && url.scheme() != "https" {
+        return None;
+    }
+    if cookie.same_site == SameSite::None && !cookie.secure {
+        return None;
+    }
+    let name = cookie.name.to_ascii_lowercase();
+    if name.starts_with("__secure-") && !cookie.secure {
+        return None;
+    }
+    if name.starts_with("__host-") && !(cookie.secure && cookie.host_only && cookie.path == "/") {
+        return None;
+    }
+
+    Some(cookie)
+}
+
+/// <https://httpwg.org/specs/rfc6265.html#cookie-date>
+fn parse_cookie_date(input: &str) -> Option<SystemTime> {
+    let is_delimiter = |x: char| matches!(x, '\x09' | '\x20'..='\x2F' | '\x3B'..='\x40' | '\x5B'..='\x60' | '\x7B'..='\x7E');
+    // 1*2DIGIT [ non-digit *OCTET ], and so on
+    let digits = |token: &str, min: usize, max: usize| -> Option<u64> {
+        let end = token
+            .find(|x: char| !x.is_ascii_digit())
+            .unwrap_or(token.len());
+        if !(min..=max).contains(&end) {
+            return None;
+        }
+        u64::from_str(&token[..end]).ok()
+    };
+
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    for token in input.split(is_delimiter).filter(|x| !x.is_empty()) {
+        if time.is_none() {
+            let mut parts = token.splitn(3, ':');
+            if let (Some(hour), Some(minute), Some(second)) =
+                (parts.next(), parts.next(), parts.next())
+            {
+                if let (Some(hour), Some(minute), Some(second)) = (
+                    digits(hour, 1, 2).filter(|_| hour.len() <= 2),
+                    digits(minute, 1, 2).filter(|_| minute.len() <= 2),
+                    digits(second, 1, 2),
+                ) {
+                    time = Some((hour, minute, second));
+                    continue;
+                }
+            }
+        }
+        if day.is_none() {
+            if let Some(x) = digits(token, 1, 2) {
+                day = Some(x);
+                continue;
+            }
+        }
+        if month.is_none() && token.len() >= 3 {
+            const MONTHS: [&str; 12] = [
+                "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+            ];
+            let prefix = token.get(..3).map(|x| x.to_ascii_lowercase());
+            if let Some(index) = MONTHS.iter().position(|x| Some(*x) == prefix.as_deref()) {
+                month = Some(index as u64 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(x) = digits(token, 2, 4) {
+                year = Some(x);
+                continue;
+            }
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let year = match year? {
+        x @ 70..=99 => x + 1900,
+        x @ 0..=69 => x + 2000,
+        x => x,
+    };
+    let (day, month) = (day?, month?);
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let days = days_from_civil(year as i64, month, day);
+    let seconds = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    match u64::try_from(seconds) {
+        Ok(seconds) => Some(UNIX_EPOCH + Duration::from_secs(seconds)),
+        // dates before 1970 have expired anyway
+        Err(_) => Some(UNIX_EPOCH),
+    }
+}
+
+/// <https://httpwg.org/specs/rfc6265.html#cookie-path>
+fn default_path(url: &Url) -> String {
+    let path = url.pathname();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_owned(),
+        Some(index) => path[..index].to_owned(),
+    }
+}
+
+/// <https://httpwg.org/specs/rfc6265.html#cookie-domain>
+fn domain_match(string: &str, domain: &str) -> bool {
+    if string == domain {
+        return true;
+    }
+    let is_ip_address = Ipv4Addr::from_str(string).is_ok()
+        || string
+            .strip_prefix('[')
+            .and_then(|x| x.strip_suffix(']'))
+            .and_then(|x| Ipv6Addr::from_str(x).ok())
+            .is_some();
+
+    !is_ip_address
+        && string
+            .strip_suffix(domain)
+            .filter(|x| x.ends_with('.'))
+            .is_some()
+}
+
+/// <https://httpwg.org/specs/rfc6265.html#cookie-path>
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// without the public suffix list, we can’t find registrable domains, so we
+/// only treat urls with the same scheme and host as the same site.
+/// <https://html.spec.whatwg.org/#same-site>
+fn is_same_site(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme() && a.hostname().eq_ignore_ascii_case(&b.hostname())
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_cookie_jar() -> Result<(), crate::url::ParseError> {
+    let url = |x| Url::parse(x, None);
+    let jar = CookieJar::default();
+    jar.set_cookie(&url("http://example.org/a/b")?, "a=1");
+    jar.set_cookie(&url("http://example.org/a/b")?, " b = 2 ; Path=/; HttpOnly");
+    jar.set_cookie(&url("http://www.example.org/")?, "c=3; Domain=.EXAMPLE.org");
+    jar.set_cookie(&url("http://www.example.org/")?, "d=4; Domain=other.org");
+    jar.set_cookie(&url("http://example.org/")?, "e=5; Domain=www.example.org");
+    jar.set_cookie(&url("http://example.org/")?, "f=6; Secure");
+    jar.set_cookie(&url("https://example.org/")?, "g=7; Secure; SameSite=None");
+    jar.set_cookie(&url("https://example.org/")?, "h=8; SameSite=None");
+    jar.set_cookie(&url("http://example.org/")?, "i=9; Expires=Sun, 06 Nov 1994 08:49:37 GMT");
+    jar.set_cookie(&url("http://example.org/")?, "j=10; Max-Age=3600; Expires=Sun, 06 Nov 1994 08:49:37 GMT");
+    jar.set_cookie(&url("http://example.org/")?, "k=11; Expires=Sat, 01 Jan 2101 00:00:00 GMT; Max-Age=0");
+    jar.set_cookie(&url("https://example.org/")?, "__Host-l=12; Secure; Path=/; Domain=example.org");
+    jar.set_cookie(&url("https://example.org/")?, "__Host-m=13; Secure; Path=/");
+    jar.set_cookie(&url("http://example.org/")?, "=");
+    jar.set_cookie(&url("http://example.org/")?, "bare");
+    jar.set_cookie(&url("http://example.org/")?, "n=14; Domain=org");
+    jar.set_cookie(&url("http://localhost/")?, "o=15; Domain=localhost");
+
+    assert_eq!(jar.cookie_header(&url("http://example.org/a/b/c")?, None, true).as_deref(), Some("a=1; b=2; c=3; j=10; bare"));
+    assert_eq!(jar.cookie_header(&url("http://example.org/ab")?, None, true).as_deref(), Some("b=2; c=3; j=10; bare"));
+    assert_eq!(jar.cookie_header(&url("http://www.example.org/")?, None, true).as_deref(), Some("c=3"));
+    assert_eq!(jar.cookie_header(&url("https://example.org/")?, None, true).as_deref(), Some("b=2; c=3; g=7; j=10; __Host-m=13; bare"));
+    assert_eq!(jar.cookie_header(&url("https://example.org/")?, Some(&url("https://other.org/")?), false).as_deref(), Some("g=7"));
+    assert_eq!(jar.cookie_header(&url("http://other.org/")?, None, true), None);
+    assert_eq!(jar.cookie_header(&url("http://localhost/")?, None, true).as_deref(), Some("o=15"));
+    assert!(jar.cookies().iter().any(|x| x.name == "o" && x.host_only && x.domain == "localhost"));
+
+    // replacing keeps the creation time, and so the order
+    jar.set_cookie(&url("http://example.org/")?, "b=two; Path=/");
+    jar.set_cookie(&url("http://example.org/")?, "c=three; Domain=example.org; Max-Age=-1");
+    jar.set_cookie(&url("http://example.org/")?, "g=seven");
+    assert_eq!(jar.cookie_header(&url("http://example.org/")?, None, true).as_deref(), Some("b=two; j=10; bare"));
+    assert_eq!(jar.cookies().len(), 7);
+
+    // cross-site requests never get strict cookies, and only get lax cookies
+    // for top-level navigations with safe methods, so not for form posts
+    let jar = CookieJar::default();
+    let (target, other) = (url("https://example.org/")?, url("https://other.org/")?);
+    jar.set_cookie(&target, "lax=1");
+    jar.set_cookie(&target, "strict=2; SameSite=Strict");
+    jar.set_cookie(&target, "none=3; SameSite=None; Secure");
+    assert_eq!(jar.cookie_header(&target, Some(&target), false).as_deref(), Some("lax=1; strict=2; none=3"));
+    assert_eq!(jar.cookie_header(&target, Some(&other), true).as_deref(), Some("lax=1; none=3"));
+    assert_eq!(jar.cookie_header(&target, Some(&other), false).as_deref(), Some("none=3"));
+
+    assert_eq!(parse_cookie_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(UNIX_EPOCH + Duration::from_secs(784111777)));
+    assert_eq!(parse_cookie_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(UNIX_EPOCH + Duration::from_secs(784111777)));
+    assert_eq!(parse_cookie_date("Sun Nov  6 08:49:37 1994"), Some(UNIX_EPOCH + Duration::from_secs(784111777)));
+    assert_eq!(parse_cookie_date("6 november 1994 8:49:37"), Some(UNIX_EPOCH + Duration::from_secs(784111777)));
+    assert_eq!(parse_cookie_date("Sun, 06 Nov 1994"), None);
+    assert_eq!(parse_cookie_date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use tracing::{debug, instrument};
+
+use crate::{mime::MimeType, percent_decode, HttpError, Response, Url};
+
+/// <https://fetch.spec.whatwg.org/#data-url-processor>
+#[instrument]
+pub fn request_data(url: &Url) -> Result<Response, HttpError> {
+    // the fragment is not part of the data
+    let mut input = url.clone();
+    input.set_fragment(None);
+    let input = input.to_string();
+    let input = &input["data:".len()..];
+
+    let Some((mime_type, body)) = input.split_once(',')
+        else { return Err(HttpError::InvalidDataUrl("no comma".to_owned())) };
+    let mut mime_type = mime_type.trim_matches(|c: char| c.is_ascii_whitespace());
+    let mut body = percent_decode(body);
+
+    // check for a trailing ;base64, allowing spaces before the base64
+    let base64 = mime_type.len() >= 6
+        && mime_type.is_char_boundary(mime_type.len() - 6)
+        && mime_type[mime_type.len() - 6..].eq_ignore_ascii_case("base64")
+        && mime_type[..mime_type.len() - 6]
+            .trim_end_matches(' ')
+            .ends_with(';');
+    if base64 {
+        let Some(decoded) = forgiving_base64_decode(&body)
+            else { return Err(HttpError::InvalidDataUrl("invalid base64".to_owned())) };
+        body = decoded;
+        mime_type = mime_type[..mime_type.len() - 6].trim_end_matches(' ');
+        mime_type = &mime_type[..mime_type.len() - 1];
+    }
+
+    let mime_type = match mime_type.starts_with(';') {
+        true => MimeType::parse(&format!("text/plain{}", mime_type)),
+        false => MimeType::parse(mime_type),
+    };
+    let mime_type =
+        mime_type.unwrap_or_else(|| MimeType::parse("text/plain;charset=US-ASCII").unwrap());
+    debug!(%mime_type, base64, body = body.len());
+
+    Ok(Response {
+        url: url.clone(),
+        status: 200,
+        headers: [("content-type", mime_type.to_string())].into(),
+        body,
+    })
+}
+
+/// <https://infra.spec.whatwg.org/#forgiving-base64-decode>
+pub fn forgiving_base64_decode(input: &[u8]) -> Option<Vec<u8>> {
+    let mut input = input
+        .iter()
+        .copied()
+        .filter(|x| !matches!(x, b'\t' | b'\n' | b'\x0C' | b'\r' | b' '))
+        .collect::<Vec<_>>();
+    if input.len() % 4 == 0 {
+        if input.ends_with(b"==") {
+            input.truncate(input.len() - 2);
+        } else if input.ends_with(b"=") {
+            input.truncate(input.len() - 1);
+        }
+    }
+    if input.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut result = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for octet in input {
+        let value = match octet {
+            b'A'..=b'Z' => octet - b'A',
+            b'a'..=b'z' => octet - b'a' + 26,
+            b'0'..=b'9' => octet - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    // any leftover bits are discarded, even if they’re not zero
+    Some(result)
+}
+
+/// standard base64 with padding, for things like basic auth credentials.
+/// <https://datatracker.ietf.org/doc/html/rfc4648#section-4>
+pub fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut result = String::new();
+    for chunk in input.chunks(3) {
+        let buffer = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |a, (i, x)| a | (*x as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => result.push(ALPHABET[(buffer >> (18 - 6 * i) & 63) as usize] as char),
+                false => result.push('='),
+            }
+        }
+    }
+
+    result
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_request_data() {
+    fn data(url: &str) -> Option<(String, Vec<u8>)> {
+        let response = request_data(&Url::parse(url, None).ok()?).ok()?;
+        Some((response.headers.get("content-type")?.to_owned(), response.body))
+    }
+
+    assert_eq!(data("data:,hello%20world"), Some(("text/plain;charset=US-ASCII".to_owned(), b"hello world".to_vec())));
+    assert_eq!(data("data:text/html,<p>hi#frag"), Some(("text/html".to_owned(), b"<p>hi".to_vec())));
+    assert_eq!(data("data:text/css;charset=utf-8;base64,cCB7IH0="), Some(("text/css;charset=utf-8".to_owned(), b"p { }".to_vec())));
+    assert_eq!(data("data:;BASE64,cCB7IH0"), Some(("text/plain;charset=US-ASCII".to_owned(), b"p { }".to_vec())));
+    assert_eq!(data("data:text/plain ; base64,cC B7%20I H0="), Some(("text/plain".to_owned(), b"p { }".to_vec())));
+    assert_eq!(data("data:text/plain;xbase64,cCB7IH0="), Some(("text/plain".to_owned(), b"cCB7IH0=".to_vec())));
+    assert_eq!(data("data:IMAGE/GIF;Charset=\"UTF\\-8\";x=\"\";a=b;a=c,"), Some(("image/gif;charset=UTF-8;x=\"\";a=b".to_owned(), vec![])));
+    assert_eq!(data("data:nonsense;base64,aGk="), Some(("text/plain;charset=US-ASCII".to_owned(), b"hi".to_vec())));
+    assert_eq!(data("data:;base64,aGk=a"), None);
+    assert_eq!(data("data:;base64,a"), None);
+    assert_eq!(data("data:text/plain"), None);
+
+    assert_eq!(["", "f", "fo", "foo", "foob"].map(|x| base64_encode(x.as_bytes())), ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg=="]);
+}
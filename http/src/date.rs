@@ -0,0 +1,93 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use wbe_core::trim_ascii;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// parses an http date in any of the three formats we’re meant to accept.
+/// <https://httpwg.org/specs/rfc9110.html#http.date>
+pub fn parse_http_date(input: &str) -> Option<SystemTime> {
+    let input = trim_ascii(input);
+    let (day, month, year, time) = match input.split_once(", ") {
+        // imf-fixdate, like “Sun, 06 Nov 1994 08:49:37 GMT”, or the
+        // obsolete rfc 850 format, like “Sunday, 06-Nov-94 08:49:37 GMT”
+        Some((_, rest)) => match rest.split([' ', '-']).collect::<Vec<_>>()[..] {
+            [day, month, year, time, "GMT"] => (day, month, year, time),
+            _ => return None,
+        },
+        // asctime, like “Sun Nov  6 08:49:37 1994”
+        None => match input.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+            [_, month, day, time, year] => (day, month, year, time),
+            _ => return None,
+        },
+    };
+
+    let day = number(day, 1..=2).filter(|x| (1..=31).contains(x))?;
+    let month = MONTHS.iter().position(|x| *x == month)? as u64 + 1;
+    let year = match year.len() {
+        4 => number(year, 4..=4)?,
+        // two-digit years in the past fifty years or so, per rfc 9110
+        2 => match number(year, 2..=2)? {
+            x if x < 70 => 2000 + x,
+            x => 1900 + x,
+        },
+        _ => return None,
+    };
+    let (hour, minute, second) = match time.split(':').collect::<Vec<_>>()[..] {
+        [hour, minute, second] => (
+            number(hour, 2..=2).filter(|x| *x < 24)?,
+            number(minute, 2..=2).filter(|x| *x < 60)?,
+            // allow for leap seconds
+            number(second, 2..=2).filter(|x| *x < 61)?,
+        ),
+        _ => return None,
+    };
+
+    let days = days_from_civil(year as i64, month, day);
+    let seconds = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    match u64::try_from(seconds) {
+        Ok(seconds) => Some(UNIX_EPOCH + Duration::from_secs(seconds)),
+        Err(_) => UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs())),
+    }
+}
+
+/// days since 1970-01-01 in the proleptic gregorian calendar.
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+pub(crate) fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year as i64;
+
+    era * 146097 + day_of_era - 719468
+}
+
+fn number(input: &str, digits: std::ops::RangeInclusive<usize>) -> Option<u64> {
+    if !digits.contains(&input.len()) || !input.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    u64::from_str(input).ok()
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_parse_http_date() {
+    let expected = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+    assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+    assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+    assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(UNIX_EPOCH));
+    assert_eq!(parse_http_date("Wed, 31 Dec 1969 23:59:59 GMT"), UNIX_EPOCH.checked_sub(Duration::from_secs(1)));
+    assert_eq!(parse_http_date("Tue, 29 Feb 2000 12:00:00 GMT"), Some(UNIX_EPOCH + Duration::from_secs(951825600)));
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+    assert_eq!(parse_http_date("0"), None);
+    assert_eq!(parse_http_date("-1"), None);
+}
@@ -0,0 +1,96 @@
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    io::{self, ErrorKind},
+};
+
+use crate::url::ParseError;
+use wbe_core::Cancelled;
+
+/// everything that can go wrong when requesting a url.
+#[derive(Debug)]
+pub enum HttpError {
+    InvalidUrl(ParseError),
+    UnsupportedScheme(String),
+    /// failed to resolve the hostname.
+    Dns(String, io::Error),
+    Connect(String, io::Error),
+    Tls(String),
+    /// the proxy refused to connect us to the server.
+    Proxy(String),
+    Timeout,
+    /// the request’s cancellation token was cancelled.
+    Cancelled,
+    /// some other i/o error after connecting.
+    Io(io::Error),
+    MalformedResponse(String),
+    ContentDecoding(io::Error),
+    TooManyRedirects(String),
+    RedirectLoop(String),
+    InvalidDataUrl(String),
+    /// non-local host, or a path we can’t represent.
+    InvalidFileUrl(String),
+    File(String, io::Error),
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(error) => write!(f, "{}", error),
+            Self::UnsupportedScheme(scheme) => write!(f, "unsupported scheme: {:?}", scheme),
+            Self::Dns(hostname, error) => write!(f, "failed to resolve {}: {}", hostname, error),
+            Self::Connect(address, error) => {
+                write!(f, "failed to connect to {}: {}", address, error)
+            }
+            Self::Tls(error) => write!(f, "tls error: {}", error),
+            Self::Proxy(reason) => write!(f, "proxy error: {}", reason),
+            Self::Timeout => write!(f, "timed out"),
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::Io(error) => write!(f, "i/o error: {}", error),
+            Self::MalformedResponse(reason) => write!(f, "malformed response: {}", reason),
+            Self::ContentDecoding(error) => write!(f, "failed to decode content: {}", error),
+            Self::TooManyRedirects(url) => write!(f, "too many redirects: {}", url),
+            Self::RedirectLoop(url) => write!(f, "redirect loop: {}", url),
+            Self::InvalidDataUrl(reason) => write!(f, "invalid data url: {}", reason),
+            Self::InvalidFileUrl(url) => write!(f, "invalid file url: {}", url),
+            Self::File(path, error) => write!(f, "failed to read {}: {}", path, error),
+        }
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidUrl(error) => Some(error),
+            Self::Dns(_, error)
+            | Self::Connect(_, error)
+            | Self::Io(error)
+            | Self::ContentDecoding(error)
+            | Self::File(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for HttpError {
+    fn from(error: ParseError) -> Self {
+        Self::InvalidUrl(error)
+    }
+}
+
+/// for i/o errors while talking to a server. our readers (like [`crate::ChunkedReader`])
+/// report bad framing as InvalidData or UnexpectedEof, so those are malformed responses.
+impl From<io::Error> for HttpError {
+    fn from(error: io::Error) -> Self {
+        if matches!(error.get_ref(), Some(x) if x.is::<Cancelled>()) {
+            return Self::Cancelled;
+        }
+        match error.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Self::Timeout,
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => {
+                Self::MalformedResponse(error.to_string())
+            }
+            _ => Self::Io(error),
+        }
+    }
+}
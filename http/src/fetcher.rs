@@ -0,0 +1,119 @@
+use std::{collections::BTreeMap, fmt::Debug, sync::Mutex};
+
+use tracing::{debug, instrument};
+
+use crate::{
+    request_data, Headers, HttpError, Request, Response, Settings, StreamingResponse, Url,
+};
+
+/// something that can turn a request into a response, so that the rest of
+/// the browser can run against the network or against canned responses.
+pub trait Fetcher: Debug + Send + Sync {
+    fn fetch(&self, request: &Request) -> Result<Response, HttpError>;
+
+    /// like [`Fetcher::fetch`], but the body can be read as it arrives.
+    fn fetch_streaming(&self, request: &Request) -> Result<StreamingResponse, HttpError> {
+        self.fetch(request).map(Response::into_streaming)
+    }
+}
+
+/// fetches over real sockets (and from files), with the given settings.
+#[derive(Debug, Default)]
+pub struct SocketFetcher {
+    pub settings: Settings,
+}
+
+impl SocketFetcher {
+    pub fn new(settings: Settings) -> Self {
+        Self { settings }
+    }
+}
+
+impl Fetcher for SocketFetcher {
+    fn fetch(&self, request: &Request) -> Result<Response, HttpError> {
+        request.send(&self.settings)
+    }
+
+    fn fetch_streaming(&self, request: &Request) -> Result<StreamingResponse, HttpError> {
+        request.send_streaming(&self.settings)
+    }
+}
+
+/// fetches canned responses from memory, keyed by url without fragment.
+/// data urls work too, since they need no network, but any other url that
+/// wasn’t given a response gets a 404, and other schemes are unsupported.
+#[derive(Debug, Default)]
+pub struct MemoryFetcher {
+    responses: BTreeMap<String, CannedResponse>,
+    requests: Mutex<Vec<Request>>,
+}
+
+type CannedResponse = (usize, Headers, Vec<u8>);
+
+impl MemoryFetcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds a response with the given status, headers, and body.
+    pub fn with(
+        mut self,
+        url: &str,
+        status: usize,
+        headers: &[(&str, &str)],
+        body: impl Into<Vec<u8>>,
+    ) -> Self {
+        let url = Url::parse(url, None).expect("failed to parse url for canned response");
+        let headers = headers.iter().copied().collect();
+        self.responses
+            .insert(key(&url), (status, headers, body.into()));
+
+        self
+    }
+
+    /// adds a 200 response with the given content-type and body.
+    pub fn with_ok(self, url: &str, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        self.with(url, 200, &[("content-type", content_type)], body)
+    }
+
+    /// every request fetched so far, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Fetcher for MemoryFetcher {
+    #[instrument(skip(self))]
+    fn fetch(&self, request: &Request) -> Result<Response, HttpError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let url = &request.url;
+        match url.scheme() {
+            "http" | "https" | "file" => {}
+            "data" => return request_data(url),
+            other => return Err(HttpError::UnsupportedScheme(other.to_owned())),
+        }
+        let Some((status, headers, body)) = self.responses.get(&key(url)) else {
+            debug!("no canned response");
+            return Ok(Response {
+                url: url.clone(),
+                status: 404,
+                headers: Default::default(),
+                body: vec![],
+            });
+        };
+
+        Ok(Response {
+            url: url.clone(),
+            status: *status,
+            headers: headers.clone(),
+            body: body.clone(),
+        })
+    }
+}
+
+fn key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+
+    url.to_string()
+}
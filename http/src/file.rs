@@ -0,0 +1,120 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, instrument};
+
+use wbe_core::escape_html;
+
+use crate::{percent_decode, url::percent_encode_path_segment, Host, HttpError, Response, Url};
+
+#[instrument]
+pub fn request_file(url: &Url) -> Result<Response, HttpError> {
+    // the parser already turns localhost into the empty host
+    if !matches!(url.host(), None | Some(Host::Empty)) {
+        return Err(HttpError::InvalidFileUrl(url.to_string()));
+    }
+    let Ok(path) = String::from_utf8(percent_decode(&url.pathname()))
+        else { return Err(HttpError::InvalidFileUrl(url.to_string())) };
+    let error = |x| HttpError::File(path.clone(), x);
+    let metadata = fs::metadata(&path).map_err(error)?;
+    let path = PathBuf::from(&path);
+
+    if metadata.is_dir() {
+        // like most servers, make sure the url ends in a slash, so that
+        // relative urls in the listing resolve to the directory’s entries
+        let url = match url.pathname().ends_with('/') {
+            true => url.clone(),
+            false => Url::parse(&format!("{}/", url.pathname()), Some(url))?,
+        };
+        debug!(%url, "generating directory listing");
+        let body = directory_listing(&path).map_err(error)?;
+
+        return Ok(Response {
+            url,
+            status: 200,
+            headers: [("content-type", "text/html; charset=utf-8")].into(),
+            body: body.into_bytes(),
+        });
+    }
+
+    Ok(Response {
+        url: url.clone(),
+        status: 200,
+        headers: Default::default(),
+        body: fs::read(&path).map_err(error)?,
+    })
+}
+
+fn directory_listing(path: &Path) -> io::Result<String> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        entries.push((name, entry.file_type()?.is_dir()));
+    }
+    entries.sort();
+
+    let title = escape_html(&path.to_string_lossy());
+    let mut result = format!(
+        "<!doctype html><title>index of {0}</title><h1>index of {0}</h1><ul>",
+        title
+    );
+    if path.parent().is_some() {
+        result += "<li><a href=\"../\">../</a>";
+    }
+    for (name, is_dir) in entries {
+        let slash = if is_dir { "/" } else { "" };
+        // the leading ./ keeps a name like `a:b` from looking like a scheme
+        let href = percent_encode_path_segment(&name);
+        let name = escape_html(&name);
+        result += &format!("<li><a href=\"./{}{2}\">{}{2}</a>", href, name, slash);
+    }
+    result += "</ul>";
+
+    Ok(result)
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_request_file() -> Result<(), HttpError> {
+    let directory = std::env::temp_dir().join(format!("wbe-http-file-{}", std::process::id()));
+    fs::create_dir_all(directory.join("sub"))?;
+    for name in ["#notes", "a?b", "50%.txt", "a:b", "x&amp;y"] {
+        fs::write(directory.join(name), name)?;
+    }
+    let base = Url::parse(&format!("file://{}/", directory.to_string_lossy()), None)?;
+
+    // files
+    let response = request_file(&Url::parse("50%25.txt", Some(&base))?)?;
+    assert_eq!((response.status, &*response.body), (200, &b"50%.txt"[..]));
+
+    // directories get a trailing slash, so relative links work
+    let response = request_file(&Url::parse("sub", Some(&base))?)?;
+    assert_eq!(response.url.to_string(), format!("{}sub/", base));
+    assert_eq!(response.headers.get("content-type"), Some("text/html; charset=utf-8"));
+
+    // names are links to themselves, even with special characters
+    let response = request_file(&base)?;
+    let listing = String::from_utf8(response.body).unwrap();
+    assert!(listing.contains(r#"<a href="./%23notes">#notes</a>"#));
+    assert!(listing.contains(r#"<a href="./a%3Fb">a?b</a>"#));
+    assert!(listing.contains(r#"<a href="./50%25.txt">50%.txt</a>"#));
+    assert!(listing.contains(r#"<a href="./a:b">a:b</a>"#));
+    assert!(listing.contains(r#"<a href="./x%26amp;y">x&amp;amp;y</a>"#));
+    assert!(listing.contains(r#"<a href="./sub/">sub/</a>"#));
+    for name in ["#notes", "a?b", "50%.txt", "a:b", "x&amp;y"] {
+        let href = format!("./{}", percent_encode_path_segment(name));
+        let response = request_file(&Url::parse(&href, Some(&base))?)?;
+        assert_eq!(response.body, name.as_bytes());
+    }
+
+    // only local files
+    let url = Url::parse(&format!("file://example.com{}", directory.to_string_lossy()), None)?;
+    assert!(matches!(request_file(&url), Err(HttpError::InvalidFileUrl(_))));
+
+    fs::remove_dir_all(directory).ok();
+
+    Ok(())
+}
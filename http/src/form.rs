@@ -0,0 +1,143 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use encoding_rs::Encoding;
+
+// every encoder takes the encoding of the document with the form, which
+// writes characters it can’t represent as numeric character references.
+// utf-16 encodings become utf-8, like everywhere else that encodes text.
+// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#picking-an-encoding-for-the-form>
+
+/// form data as a query string or request body, with spaces as plus signs.
+/// <https://url.spec.whatwg.org/#concept-urlencoded-serializer>
+pub fn encode_urlencoded(entries: &[(String, String)], encoding: &'static Encoding) -> String {
+    let encode = |input: &str| {
+        let mut result = String::new();
+        for &octet in &*encoding.encode(&normalize_newlines(input)).0 {
+            match octet {
+                b' ' => result.push('+'),
+                b'*' | b'-' | b'.' | b'_' => result.push(octet as char),
+                _ if octet.is_ascii_alphanumeric() => result.push(octet as char),
+                _ => result += &format!("%{:02X}", octet),
+            }
+        }
+
+        result
+    };
+
+    entries
+        .iter()
+        .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// form data as a multipart/form-data body, returning the content-type
+/// (which has the boundary) and the body.
+/// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#multipart-form-data>
+pub fn encode_multipart(
+    entries: &[(String, String)],
+    encoding: &'static Encoding,
+) -> (String, Vec<u8>) {
+    // names are quoted, so escape quotes and line breaks like other browsers
+    let escape = |name: &str| {
+        normalize_newlines(name)
+            .replace('"', "%22")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let parts = entries
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "Content-Disposition: form-data; name=\"{}\"\r\n\r\n{}",
+                escape(name),
+                normalize_newlines(value)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // the boundary must not appear in any part, which is very unlikely
+    // with a random-ish one, but cheap to check
+    let mut boundary = new_boundary();
+    while parts.iter().any(|x| x.contains(&boundary)) {
+        boundary = new_boundary();
+    }
+
+    let mut body = String::new();
+    for part in parts {
+        body += &format!("--{}\r\n{}\r\n", boundary, part);
+    }
+    body += &format!("--{}--\r\n", boundary);
+
+    (
+        format!("multipart/form-data; boundary={}", boundary),
+        encoding.encode(&body).0.into_owned(),
+    )
+}
+
+/// form data as a text/plain body, which is ambiguous, but easy to read.
+/// <https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#text/plain-encoding-algorithm>
+pub fn encode_text_plain(entries: &[(String, String)], encoding: &'static Encoding) -> Vec<u8> {
+    let result = entries
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}={}\r\n",
+                normalize_newlines(name),
+                normalize_newlines(value)
+            )
+        })
+        .collect::<String>();
+
+    encoding.encode(&result).0.into_owned()
+}
+
+/// converts lone cr and lone lf to crlf, as form submission requires.
+fn normalize_newlines(input: &str) -> String {
+    input
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', "\r\n")
+}
+
+fn new_boundary() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_nanos() as u64);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+
+    format!(
+        "----wbeFormBoundary{:016x}",
+        nanos.wrapping_mul(0x9E3779B97F4A7C15) ^ count
+    )
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_form_encoding() {
+    use encoding_rs::{SHIFT_JIS, UTF_16LE, UTF_8, WINDOWS_1252};
+
+    let entries = [("q".to_owned(), "café & crème".to_owned()), ("a b".to_owned(), "1+1=2\n*-._~".to_owned())];
+    assert_eq!(encode_urlencoded(&entries, UTF_8), "q=caf%C3%A9+%26+cr%C3%A8me&a+b=1%2B1%3D2%0D%0A*-._%7E");
+    assert_eq!(encode_urlencoded(&[], UTF_8), "");
+    assert_eq!(encode_text_plain(&entries, UTF_8), "q=café & crème\r\na b=1+1=2\r\n*-._~\r\n".as_bytes());
+
+    // other encodings, with references for what they can’t represent
+    let entries = [("q".to_owned(), "café 日本".to_owned())];
+    assert_eq!(encode_urlencoded(&entries, WINDOWS_1252), "q=caf%E9+%26%2326085%3B%26%2326412%3B");
+    assert_eq!(encode_urlencoded(&entries, SHIFT_JIS), "q=caf%26%23233%3B+%93%FA%96%7B");
+    assert_eq!(encode_urlencoded(&entries, UTF_16LE), encode_urlencoded(&entries, UTF_8));
+    assert_eq!(encode_text_plain(&entries, WINDOWS_1252), b"q=caf\xE9 &#26085;&#26412;\r\n");
+
+    let (content_type, body) = encode_multipart(&[("x\"\ny".to_owned(), "1\r2".to_owned()), ("z".to_owned(), "".to_owned())], UTF_8);
+    let boundary = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+    assert_eq!(String::from_utf8(body).unwrap(), format!(concat!(
+        "--{0}\r\nContent-Disposition: form-data; name=\"x%22%0D%0Ay\"\r\n\r\n1\r\n2\r\n",
+        "--{0}\r\nContent-Disposition: form-data; name=\"z\"\r\n\r\n\r\n",
+        "--{0}--\r\n",
+    ), boundary));
+}
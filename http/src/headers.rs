@@ -0,0 +1,123 @@
+use wbe_core::trim_ascii;
+
+/// header fields in the order they were received. names compare without
+/// case, and fields like set-cookie can be repeated, so this is a multi-map.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the first value of the given field, if any.
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(field))
+            .map(|(_, x)| &**x)
+    }
+
+    /// every value of the given field, in order.
+    pub fn get_all<'s>(&'s self, field: &'s str) -> impl Iterator<Item = &'s str> {
+        self.0
+            .iter()
+            .filter(move |(x, _)| x.eq_ignore_ascii_case(field))
+            .map(|(_, x)| &**x)
+    }
+
+    /// every value of the given field, combined into one list as if they
+    /// were sent in one field. this is wrong for set-cookie, which has its
+    /// own syntax, so use [`Headers::get_all`] for that.
+    /// <https://httpwg.org/specs/rfc9110.html#field.lines>
+    pub fn get_combined(&self, field: &str) -> Option<String> {
+        let values = self.get_all(field).collect::<Vec<_>>();
+        if values.is_empty() {
+            return None;
+        }
+
+        Some(values.join(", "))
+    }
+
+    /// every element of every value of the given comma-separated list field.
+    pub fn get_list<'s>(&'s self, field: &'s str) -> impl Iterator<Item = &'s str> {
+        self.get_all(field)
+            .flat_map(|x| x.split(','))
+            .map(trim_ascii)
+            .filter(|x| !x.is_empty())
+    }
+
+    pub fn contains(&self, field: &str) -> bool {
+        self.get(field).is_some()
+    }
+
+    /// adds a value, keeping any existing values of the field.
+    pub fn append(&mut self, field: impl Into<String>, value: impl Into<String>) {
+        self.0.push((field.into(), value.into()));
+    }
+
+    /// replaces any existing values of the field with the given value.
+    pub fn insert(&mut self, field: impl Into<String>, value: impl Into<String>) {
+        let field = field.into();
+        self.remove(&field);
+        self.append(field, value);
+    }
+
+    pub fn remove(&mut self, field: &str) {
+        self.0.retain(|(x, _)| !x.eq_ignore_ascii_case(field));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(field, value)| (&**field, &**value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Headers {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut result = Self::new();
+        for (field, value) in iter {
+            result.append(field, value);
+        }
+
+        result
+    }
+}
+
+impl<K: Into<String>, V: Into<String>, const N: usize> From<[(K, V); N]> for Headers {
+    fn from(fields: [(K, V); N]) -> Self {
+        fields.into_iter().collect()
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_headers() {
+    let mut headers = Headers::from([("Set-Cookie", "a=1"), ("cache-control", "no-cache"), ("set-cookie", "b=2"), ("Cache-Control", " max-age=60 ,")]);
+    assert_eq!(headers.get("SET-COOKIE"), Some("a=1"));
+    assert_eq!(headers.get_all("set-cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+    assert_eq!(headers.get_combined("cache-control").as_deref(), Some("no-cache,  max-age=60 ,"));
+    assert_eq!(headers.get_list("cache-control").collect::<Vec<_>>(), ["no-cache", "max-age=60"]);
+    assert_eq!(headers.get_combined("expires"), None);
+
+    headers.insert("Cache-Control", "private");
+    assert_eq!(headers.iter().collect::<Vec<_>>(), [("Set-Cookie", "a=1"), ("set-cookie", "b=2"), ("Cache-Control", "private")]);
+    headers.remove("set-cookie");
+    assert_eq!(headers.len(), 1);
+}
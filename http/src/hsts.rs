@@ -0,0 +1,303 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{debug, warn};
+
+use crate::{Host, Url};
+use wbe_core::trim_ascii;
+
+/// policies can’t last longer than this, however long the max-age, like in
+/// chromium. this also keeps the expiry from overflowing.
+const MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// hosts that only want to be reached over https, from their
+/// strict-transport-security fields and from the preload list.
+/// <https://www.rfc-editor.org/rfc/rfc6797>
+#[derive(Debug, Default)]
+pub struct HstsStore(Mutex<OwnedHstsStore>);
+
+#[derive(Debug, Default)]
+struct OwnedHstsStore {
+    /// known hosts, from strict-transport-security fields.
+    entries: BTreeMap<String, HstsPolicy>,
+    /// preloaded hosts, and whether their subdomains are included.
+    preload: BTreeMap<String, bool>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HstsPolicy {
+    pub expiry: SystemTime,
+    pub include_subdomains: bool,
+}
+
+impl HstsStore {
+    /// also keep known hosts in the given file, so they outlive the process.
+    /// any hosts already in the file are added to the store.
+    pub fn set_path(&self, path: Option<PathBuf>) {
+        let mut inner = self.0.lock().unwrap();
+        if let Some(path) = &path {
+            match fs::read_to_string(path) {
+                Ok(contents) => inner.entries.extend(read_entries(&contents)),
+                Err(error) if error.kind() == ErrorKind::NotFound => {}
+                Err(error) => warn!(?path, %error, "failed to read hsts file"),
+            }
+        }
+        inner.path = path;
+    }
+
+    /// adds the hosts in a preload list, with one host per line, each
+    /// followed by `includeSubDomains` if its subdomains are included too.
+    /// empty lines and lines starting with `#` are ignored.
+    pub fn preload(&self, list: &str) {
+        let mut inner = self.0.lock().unwrap();
+        for line in list.lines().map(trim_ascii) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_ascii_whitespace();
+            let Some(host) = words.next() else { continue };
+            let include_subdomains = words.any(|x| x.eq_ignore_ascii_case("includeSubDomains"));
+            inner
+                .preload
+                .insert(host.to_ascii_lowercase(), include_subdomains);
+        }
+    }
+
+    /// updates the policy for the host of `url` from its
+    /// strict-transport-security field.
+    /// <https://www.rfc-editor.org/rfc/rfc6797#section-8.1>
+    pub fn set_header(&self, url: &Url, value: &str) {
+        // only over a secure connection, and never for ip addresses
+        let Some(Host::Domain(host)) = url.host() else { return };
+        if url.scheme() != "https" {
+            return;
+        }
+        let Some((max_age, include_subdomains)) = parse_sts(value) else {
+            debug!(value, "ignoring strict-transport-security");
+            return;
+        };
+
+        let mut inner = self.0.lock().unwrap();
+        if max_age.is_zero() {
+            inner.entries.remove(host);
+        } else {
+            let policy = HstsPolicy {
+                expiry: SystemTime::now() + max_age,
+                include_subdomains,
+            };
+            inner.entries.insert(host.clone(), policy);
+        }
+        inner.save();
+    }
+
+    /// whether requests to the given host must use https.
+    /// <https://www.rfc-editor.org/rfc/rfc6797#section-8.2>
+    pub fn is_known(&self, host: &str) -> bool {
+        let inner = self.0.lock().unwrap();
+        let now = SystemTime::now();
+        let host = host.strip_suffix('.').unwrap_or(host).to_ascii_lowercase();
+
+        // the host itself, then each of its superdomains
+        let mut superdomain = false;
+        let mut domain = &*host;
+        loop {
+            if let Some(policy) = inner.entries.get(domain) {
+                if policy.expiry > now && (!superdomain || policy.include_subdomains) {
+                    return true;
+                }
+            }
+            if let Some(include_subdomains) = inner.preload.get(domain) {
+                if !superdomain || *include_subdomains {
+                    return true;
+                }
+            }
+            let Some((_, rest)) = domain.split_once('.') else { return false };
+            superdomain = true;
+            domain = rest;
+        }
+    }
+
+    /// the https url to request instead of `url`, if its host is known.
+    /// <https://www.rfc-editor.org/rfc/rfc6797#section-8.3>
+    pub fn upgrade(&self, url: &Url) -> Option<Url> {
+        let Some(Host::Domain(host)) = url.host() else { return None };
+        if url.scheme() != "http" || !self.is_known(host) {
+            return None;
+        }
+        let mut result = url.clone();
+        result.set_scheme("https");
+
+        Some(result)
+    }
+
+    /// every known host whose policy hasn’t expired yet.
+    pub fn entries(&self) -> BTreeMap<String, HstsPolicy> {
+        let now = SystemTime::now();
+
+        self.0
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|(_, x)| x.expiry > now)
+            .map(|(k, v)| (k.clone(), *v))
+            .collect()
+    }
+
+    /// forgets every known host, but not the preloaded ones.
+    pub fn clear(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.entries.clear();
+        inner.save();
+    }
+}
+
+impl OwnedHstsStore {
+    fn save(&self) {
+        let Some(path) = &self.path else { return };
+        let now = SystemTime::now();
+        let mut contents = String::new();
+        for (host, policy) in self.entries.iter().filter(|(_, x)| x.expiry > now) {
+            let expiry = policy.expiry.duration_since(UNIX_EPOCH).unwrap_or_default();
+            contents += &format!("{} {}", host, expiry.as_secs());
+            if policy.include_subdomains {
+                contents += " includeSubDomains";
+            }
+            contents += "\n";
+        }
+        if let Err(error) = fs::write(path, contents) {
+            warn!(?path, %error, "failed to write hsts file");
+        }
+    }
+}
+
+/// reads entries written by [`OwnedHstsStore::save`], skipping any that
+/// are malformed or have expired.
+fn read_entries(contents: &str) -> Vec<(String, HstsPolicy)> {
+    let now = SystemTime::now();
+    let mut result = vec![];
+    for line in contents.lines() {
+        let mut words = line.split_ascii_whitespace();
+        let (Some(host), Some(expiry)) = (words.next(), words.next()) else { continue };
+        let Ok(expiry) = expiry.parse::<u64>() else { continue };
+        let Some(expiry) = UNIX_EPOCH.checked_add(Duration::from_secs(expiry)) else { continue };
+        if expiry > now {
+            let include_subdomains = words.next() == Some("includeSubDomains");
+            result.push((
+                host.to_owned(),
+                HstsPolicy {
+                    expiry,
+                    include_subdomains,
+                },
+            ));
+        }
+    }
+
+    result
+}
+
+/// parses a strict-transport-security field into its max-age and whether it
+/// has includeSubDomains, or None if the field is invalid.
+/// <https://www.rfc-editor.org/rfc/rfc6797#section-6.1>
+fn parse_sts(value: &str) -> Option<(Duration, bool)> {
+    let mut max_age = None;
+    let mut include_subdomains = false;
+    let mut seen = vec![];
+    for directive in value.split(';') {
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => {
+                let value = trim_ascii(value);
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|x| x.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name, Some(value))
+            }
+            None => (directive, None),
+        };
+        let name = trim_ascii(name).to_ascii_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+
+        // repeated directives make the whole field invalid
+        if seen.contains(&name) {
+            return None;
+        }
+        match &*name {
+            "max-age" => {
+                let value =
+                    value.filter(|x| !x.is_empty() && x.bytes().all(|x| x.is_ascii_digit()))?;
+                // too big to fit is as good as forever
+                let value = value.parse::<u64>().unwrap_or(u64::MAX);
+                max_age = Some(Duration::from_secs(value).min(MAX_AGE));
+            }
+            "includesubdomains" => include_subdomains = true,
+            _ => {}
+        }
+        seen.push(name);
+    }
+
+    Some((max_age?, include_subdomains))
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_hsts() -> Result<(), Box<dyn std::error::Error>> {
+    let url = |x| Url::parse(x, None);
+
+    assert_eq!(parse_sts("max-age=31536000"), Some((Duration::from_secs(31536000), false)));
+    assert_eq!(parse_sts(" Max-Age = \"60\" ; includeSubDomains ;"), Some((Duration::from_secs(60), true)));
+    assert_eq!(parse_sts("max-age=0; preload"), Some((Duration::ZERO, false)));
+    assert_eq!(parse_sts("max-age=99999999999999999999999"), Some((MAX_AGE, false)));
+    assert_eq!(parse_sts("includeSubDomains"), None);
+    assert_eq!(parse_sts("max-age=-1"), None);
+    assert_eq!(parse_sts("max-age=60; max-age=60"), None);
+
+    let store = HstsStore::default();
+    store.set_header(&url("http://example.org/")?, "max-age=60");
+    store.set_header(&url("https://127.0.0.1/")?, "max-age=60");
+    assert!(!store.is_known("example.org"));
+    assert!(!store.is_known("127.0.0.1"));
+    store.set_header(&url("https://example.org/")?, "max-age=60");
+    store.set_header(&url("https://b.example.com/")?, "max-age=60; includeSubDomains");
+    assert!(store.is_known("example.org"));
+    assert!(store.is_known("EXAMPLE.ORG."));
+    assert!(!store.is_known("a.example.org"));
+    assert!(store.is_known("b.example.com"));
+    assert!(store.is_known("a.b.example.com"));
+    assert!(!store.is_known("example.com"));
+    store.set_header(&url("https://example.org/")?, "max-age=0");
+    assert!(!store.is_known("example.org"));
+
+    // only http urls are upgraded, keeping any port other than the default
+    store.preload("# comment\n\ndev includeSubDomains\nexample.net\n");
+    assert_eq!(store.upgrade(&url("http://a.dev/x?y#z")?), Some(url("https://a.dev/x?y#z")?));
+    assert_eq!(store.upgrade(&url("http://example.net:80/")?), Some(url("https://example.net/")?));
+    assert_eq!(store.upgrade(&url("http://example.net:8080/")?), Some(url("https://example.net:8080/")?));
+    assert_eq!(store.upgrade(&url("http://a.example.net/")?), None);
+    assert_eq!(store.upgrade(&url("https://a.dev/")?), None);
+    assert_eq!(store.upgrade(&url("ws://a.dev/")?), None);
+
+    // known hosts outlive the store that learned them, but preloaded ones don’t
+    let path = std::env::temp_dir().join(format!("wbe-http-hsts-{}", std::process::id()));
+    let store = HstsStore::default();
+    store.set_path(Some(path.clone()));
+    store.preload("example.net");
+    store.set_header(&url("https://b.example.com/")?, "max-age=60; includeSubDomains");
+    let store = HstsStore::default();
+    store.set_path(Some(path.clone()));
+    assert!(store.is_known("a.b.example.com"));
+    assert!(!store.is_known("example.net"));
+    assert_eq!(store.entries().len(), 1);
+    fs::remove_file(path).ok();
+
+    Ok(())
+}
@@ -1,172 +1,690 @@
+pub mod body;
+pub mod cache;
+pub mod chunked;
+pub mod coding;
+pub mod cookie;
+pub mod data;
+pub mod date;
+pub mod error;
+pub mod fetcher;
+pub mod file;
+pub mod form;
+pub mod headers;
+pub mod hsts;
+pub mod mime;
+pub mod pool;
+pub mod proxy;
+pub mod request;
+pub mod timeout;
+pub mod url;
+
+pub use crate::{
+    body::Body,
+    cache::HttpCache,
+    chunked::ChunkedReader,
+    coding::{content_decoder, decode_content},
+    cookie::{Cookie, CookieJar, SameSite},
+    data::request_data,
+    date::parse_http_date,
+    error::HttpError,
+    fetcher::{Fetcher, MemoryFetcher, SocketFetcher},
+    file::request_file,
+    form::{encode_multipart, encode_text_plain, encode_urlencoded},
+    headers::Headers,
+    hsts::HstsStore,
+    mime::MimeType,
+    pool::ConnectionPool,
+    proxy::Proxies,
+    request::{Destination, Request, Settings},
+    timeout::Timeouts,
+    url::{Host, Url},
+};
+
 use std::{
-    collections::BTreeMap,
+    collections::BTreeSet,
     io::BufRead,
-    io::{BufReader, Read, Write},
-    net::TcpStream,
+    io::{self, BufReader, ErrorKind, Read, Write},
+    net::{Ipv6Addr, TcpStream, ToSocketAddrs},
     str,
     str::FromStr,
 };
 
-use eyre::bail;
+use lazy_static::lazy_static;
 use rustls_connector::RustlsConnector;
-use tracing::{debug, instrument, trace};
-
-use wbe_core::{dump, lparse, lparse_chomp, rparse_split, trim_ascii, ReadWriteStream};
-
-#[instrument]
-pub fn request(
-    url: &str,
-    base: Option<&str>,
-) -> eyre::Result<(usize, BTreeMap<String, String>, Vec<u8>)> {
-    let url = if let Some(data) = lparse(url, "data:([^;,]+)((?:;base64)?),(.*)") {
-        assert_eq!(data.get(2).unwrap().as_str(), "");
-        let mut result = vec![];
-        let mut input = data.get(3).unwrap().as_str();
-        while !input.is_empty() {
-            if let Some(percent) = lparse_chomp(&mut input, "%[0-9A-Fa-f]{2}") {
-                let percent = percent.get(0).unwrap().as_str();
-                result.push(u8::from_str_radix(&percent[1..], 16).unwrap());
-            } else {
-                let index = input.char_indices().nth(1).map_or(input.len(), |(i, _)| i);
-                let (next, rest) = input.split_at(index);
-                for octet in next.bytes() {
-                    result.push(octet);
+use tracing::{debug, instrument};
+
+use crate::{
+    body::{Framing, MessageBody},
+    coding::ACCEPT_ENCODING,
+    pool::{Connection, PoolKey},
+    proxy::proxy_authorization,
+    timeout::{LimitedStream, Limits},
+};
+use wbe_core::{dump, lparse_chomp, trim_ascii, ReadWriteStream};
+
+// https://fetch.spec.whatwg.org/#http-redirect-fetch
+const MAX_REDIRECTS: usize = 20;
+
+const REQUEST_BODY_FIELDS: &[&str] = &[
+    "Content-Encoding",
+    "Content-Language",
+    "Content-Location",
+    "Content-Type",
+];
+
+lazy_static! {
+    pub static ref POOL: ConnectionPool = ConnectionPool::default();
+    pub static ref CACHE: HttpCache = HttpCache::default();
+    pub static ref COOKIES: CookieJar = CookieJar::default();
+    pub static ref HSTS: HstsStore = HstsStore::default();
+    static ref TLS_CONNECTOR: Result<RustlsConnector, String> =
+        RustlsConnector::new_with_native_certs().map_err(|x| x.to_string());
+}
+
+#[derive(Debug)]
+pub struct Response {
+    /// final url after following any redirects.
+    pub url: Url,
+    pub status: usize,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+/// a response whose body hasn’t necessarily arrived yet.
+#[derive(Debug)]
+pub struct StreamingResponse {
+    /// final url after following any redirects.
+    pub url: Url,
+    pub status: usize,
+    pub headers: Headers,
+    pub body: Body,
+}
+
+impl Response {
+    pub fn into_streaming(self) -> StreamingResponse {
+        StreamingResponse {
+            url: self.url,
+            status: self.status,
+            headers: self.headers,
+            body: self.body.into(),
+        }
+    }
+}
+
+impl StreamingResponse {
+    /// waits for the rest of the body.
+    pub fn into_response(mut self) -> Result<Response, HttpError> {
+        let mut body = vec![];
+        self.body.read_to_end(&mut body)?;
+        debug!(url = %self.url, body = dump(&body));
+
+        Ok(Response {
+            url: self.url,
+            status: self.status,
+            headers: self.headers,
+            body,
+        })
+    }
+}
+
+/// gets the given url with the default settings, and any proxies in the environment.
+pub fn request(url: &Url) -> Result<Response, HttpError> {
+    let settings = Settings {
+        proxies: Proxies::from_env(),
+        ..Default::default()
+    };
+    Request::get(url.clone()).send(&settings)
+}
+
+/// sends the request, following any redirects. if `stream` is true, this
+/// returns as soon as the final response head arrives, otherwise the body
+/// has already arrived too.
+fn send(
+    request: &Request,
+    settings: &Settings,
+    stream: bool,
+) -> Result<StreamingResponse, HttpError> {
+    match request.url.scheme() {
+        "http" | "https" => {}
+        "file" => return request_file(&request.url).map(Response::into_streaming),
+        "data" => return request_data(&request.url).map(Response::into_streaming),
+        other => return Err(HttpError::UnsupportedScheme(other.to_owned())),
+    }
+
+    // the total timeout covers every redirect, not each one
+    let limits = Limits::new(
+        request.timeouts.unwrap_or(settings.timeouts),
+        request.cancellation.clone(),
+    );
+    let mut url = request.url.clone();
+    let mut method = &*request.method;
+    let mut visited = BTreeSet::default();
+    let site_for_cookies = request.site_for_cookies();
+    loop {
+        // known hosts get https, even when a redirect asks for plain http
+        if let Some(upgraded) = HSTS.upgrade(&url) {
+            debug!(%url, %upgraded, "upgrading to https");
+            url = upgraded;
+        }
+        if !visited.insert((method, url.to_string())) {
+            return Err(HttpError::RedirectLoop(url.to_string()));
+        }
+
+        // the method only changes when a redirect drops the body, and then the
+        // fields describing the body go too
+        // https://fetch.spec.whatwg.org/#request-body-header-name
+        let mut headers = request.header_fields(&url, settings);
+        let body = match method == request.method {
+            true => &*request.body,
+            false => {
+                for field in REQUEST_BODY_FIELDS {
+                    headers.remove(field);
                 }
-                input = rest;
+                &[]
+            }
+        };
+        let proxy = settings.proxies.proxy_for(&url);
+        let safe_navigation =
+            request.destination == Destination::Document && matches!(method, "GET" | "HEAD");
+        let once = |headers: &Headers| {
+            request_once(
+                method,
+                &url,
+                headers,
+                body,
+                site_for_cookies,
+                safe_navigation,
+                proxy,
+                &limits,
+                stream,
+            )
+        };
+        let mut response = match method {
+            "GET" => CACHE.fetch_streaming(&url, |validators| {
+                for (field, value) in validators {
+                    headers.insert(*field, *value);
+                }
+                once(&headers)
+            })?,
+            "HEAD" => once(&headers)?,
+            _ => {
+                let response = once(&headers)?;
+                if (200..400).contains(&response.status) {
+                    CACHE.invalidate(&url);
+                }
+                response
+            }
+        };
+        let status = response.status;
+        let location = match status {
+            301 | 302 | 303 | 307 | 308 => response.headers.get("location"),
+            _ => None,
+        };
+        let Some(location) = location.map(|x| x.to_owned()) else { return Ok(response) };
+
+        // nobody wants the body of a redirect, but the connection can only be
+        // reused once we’ve read it
+        io::copy(&mut response.body, &mut io::sink())?;
+        if visited.len() > MAX_REDIRECTS {
+            return Err(HttpError::TooManyRedirects(url.to_string()));
+        }
+
+        let mut next = Url::parse(&location, Some(&url))?;
+        if !matches!(next.scheme(), "http" | "https") {
+            return Err(HttpError::UnsupportedScheme(next.scheme().to_owned()));
+        }
+
+        // https://httpwg.org/specs/rfc9110.html#field.location
+        if next.fragment().is_none() {
+            next.set_fragment(url.fragment().map(|x| x.to_owned()));
+        }
+
+        // 303 means “see other” with GET, and 301 and 302 are historically
+        // treated the same way for POST, but 307 and 308 keep the method
+        if status == 303 && method != "HEAD" || matches!(status, 301 | 302) && method == "POST" {
+            method = "GET";
+        }
+        debug!(status, %url, %next, method, "following redirect");
+        url = next;
+    }
+}
+
+#[instrument(skip(headers, body, proxy, limits))]
+#[allow(clippy::too_many_arguments)]
+fn request_once(
+    method: &str,
+    url: &Url,
+    headers: &Headers,
+    body: &[u8],
+    site_for_cookies: Option<&Url>,
+    safe_navigation: bool,
+    proxy: Option<&Url>,
+    limits: &Limits,
+    stream: bool,
+) -> Result<StreamingResponse, HttpError> {
+    // cookies go with every request over the network, including redirects and
+    // revalidations, but not when the cache answers for the server
+    let mut request_headers = headers.clone();
+    if let Some(cookie) = COOKIES.cookie_header(url, site_for_cookies, safe_navigation) {
+        request_headers.insert("Cookie", cookie);
+    }
+
+    // plain http through a proxy means asking the proxy for the whole url,
+    // but https goes through a tunnel, so the server sees the usual request
+    // https://httpwg.org/specs/rfc9112.html#absolute-form
+    let target = match proxy {
+        Some(proxy) if url.scheme() == "http" => {
+            if let Some(authorization) = proxy_authorization(proxy) {
+                request_headers.insert("Proxy-Authorization", authorization);
+            }
+            let mut target = url.clone();
+            target.set_fragment(None);
+            target.href()
+        }
+        _ => url.path_and_query(),
+    };
+    let attempt = |connection: &mut Connection| {
+        connection.get_mut().set_limits(Some(limits.clone()));
+        exchange(connection, method, url, &target, &request_headers, body)
+    };
+
+    let Some(port) = url.port_or_known_default()
+        else { return Err(HttpError::UnsupportedScheme(url.scheme().to_owned())) };
+    let key = PoolKey {
+        scheme: url.scheme().to_owned(),
+        hostname: url.hostname(),
+        port,
+        proxy: proxy.map(|x| x.href()),
+    };
+
+    // the server may have closed a pooled connection since we last used it,
+    // so if that fails, retry idempotent requests with a new connection.
+    // but not if we gave up, since a new connection won’t help
+    let (connection, (status, mut headers, framing, keep_alive)) = match POOL.checkout(&key) {
+        Some(mut connection) => match attempt(&mut connection) {
+            Ok(result) => (connection, result),
+            Err(HttpError::Timeout) => return Err(HttpError::Timeout),
+            Err(HttpError::Cancelled) => return Err(HttpError::Cancelled),
+            Err(error) if matches!(method, "GET" | "HEAD") => {
+                debug!(%error, "reused connection failed, retrying with new connection");
+                let mut connection = connect(url, proxy, limits)?;
+                let result = attempt(&mut connection)?;
+                (connection, result)
+            }
+            Err(error) => return Err(error),
+        },
+        None => {
+            let mut connection = connect(url, proxy, limits)?;
+            let result = attempt(&mut connection)?;
+            (connection, result)
+        }
+    };
+    for set_cookie in headers.get_all("set-cookie") {
+        COOKIES.set_cookie(url, set_cookie);
+    }
+
+    // only the first field counts, if there are several
+    // https://www.rfc-editor.org/rfc/rfc6797#section-8.1
+    if let Some(value) = headers.get("strict-transport-security") {
+        HSTS.set_header(url, value);
+    }
+
+    // the connection goes back to the pool once the body has been read,
+    // which might not be until long after we return
+    let mut message = MessageBody::new(connection, framing, keep_alive.then_some(key));
+
+    // content codings are applied before transfer codings, so undo them after.
+    // leave the content-encoding header alone though, since it’s useful for debugging
+    let codings = headers.get_combined("content-encoding");
+    let body = match (stream, codings) {
+        (true, Some(codings)) if !message.is_done() => {
+            content_decoder(Box::new(message), &codings).map_err(HttpError::ContentDecoding)?
+        }
+        (true, _) => Box::new(message),
+        (false, codings) => {
+            let mut body = vec![];
+            message.read_to_end(&mut body)?;
+            for (field, value) in message.into_trailers() {
+                headers.append(field, value);
+            }
+            if let Some(codings) = codings {
+                body = decode_content(body, &codings).map_err(HttpError::ContentDecoding)?;
             }
+            Box::new(io::Cursor::new(body))
         }
-        return Ok((200, Default::default(), result));
+    };
+
+    Ok(StreamingResponse {
+        url: url.clone(),
+        status,
+        headers,
+        body: Body::new(body),
+    })
+}
+
+fn connect(url: &Url, proxy: Option<&Url>, limits: &Limits) -> Result<Connection, HttpError> {
+    // ipv6 addresses are serialised in brackets, which neither connect nor tls want
+    let unbracketed = |url: &Url| match url.host() {
+        Some(Host::Ipv6(pieces)) => Ipv6Addr::from(*pieces).to_string(),
+        _ => url.hostname(),
+    };
+    let hostname = unbracketed(proxy.unwrap_or(url));
+    let Some(port) = proxy.unwrap_or(url).port_or_known_default()
+        else { return Err(HttpError::UnsupportedScheme(url.scheme().to_owned())) };
+
+    // resolve separately from connecting, so we can tell the two kinds of failure apart.
+    // the system resolver has no timeout or cancellation, so check before and after
+    limits.next_wait(limits.timeouts.connect)?;
+    let addresses = (&*hostname, port)
+        .to_socket_addrs()
+        .map_err(|x| HttpError::Dns(hostname.clone(), x))?
+        .collect::<Vec<_>>();
+
+    // try each address in turn, like TcpStream::connect, but with a timeout
+    let mut result = Err(io::Error::new(ErrorKind::NotFound, "no addresses"));
+    for address in addresses {
+        let timeout = limits.next_wait(limits.timeouts.connect)?;
+        result = TcpStream::connect_timeout(&address, timeout);
+        if result.is_ok() {
+            break;
+        }
+    }
+    let stream = match result {
+        Ok(stream) => stream,
+        Err(error) if error.kind() == ErrorKind::TimedOut => return Err(HttpError::Timeout),
+        Err(error) => {
+            let address = proxy.unwrap_or(url).host_and_port();
+            return Err(HttpError::Connect(address, error));
+        }
+    };
+
+    // the tunnel and handshake happen in here, so they need the timeouts too
+    let timeout = limits.next_wait(limits.timeouts.connect)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let socket = stream.try_clone()?;
+    if let (Some(proxy), "https") = (proxy, url.scheme()) {
+        tunnel(&stream, url, proxy)?;
+    }
+    let hostname = unbracketed(url);
+
+    let stream: Box<dyn ReadWriteStream + Send> = match url.scheme() {
+        "http" => Box::new(stream),
+        "https" => {
+            let connector = match &*TLS_CONNECTOR {
+                Ok(connector) => connector,
+                Err(error) => return Err(HttpError::Tls(error.clone())),
+            };
+            let stream = connector
+                .connect(&hostname, stream)
+                .map_err(|x| HttpError::Tls(x.to_string()))?;
+            Box::new(stream)
+        }
+        other => return Err(HttpError::UnsupportedScheme(other.to_owned())),
+    };
+
+    Ok(BufReader::new(LimitedStream::new(stream, socket)))
+}
+
+/// asks the proxy for a tunnel to the server, so we can talk tls through it.
+/// <https://httpwg.org/specs/rfc9110.html#CONNECT>
+#[instrument(skip(stream, proxy), fields(proxy = proxy.host_and_port()))]
+fn tunnel(mut stream: &TcpStream, url: &Url, proxy: &Url) -> Result<(), HttpError> {
+    // unlike the host field, the request target always has a port
+    let port = url.port_or_known_default().unwrap_or(443);
+    let authority = format!("{}:{}", url.hostname(), port);
+    let mut head = format!("CONNECT {} HTTP/1.1\r\n", authority);
+    head += &format!("Host: {}\r\n", authority);
+    if let Some(authorization) = proxy_authorization(proxy) {
+        head += &format!("Proxy-Authorization: {}\r\n", authorization);
+    }
+    head += "\r\n";
+    stream.write_all(head.as_bytes())?;
+    stream.flush()?;
+
+    // the server says nothing until we start the handshake, so anything
+    // after the response head means the proxy is confused
+    let mut reader = BufReader::new(stream);
+    let (_, status, _) = read_response_head(&mut reader)?;
+    if !(200..300).contains(&status) {
+        let reason = format!("CONNECT {} failed with status {}", authority, status);
+        return Err(HttpError::Proxy(reason));
+    }
+    if !reader.buffer().is_empty() {
+        let reason = format!("unexpected data after CONNECT {} response", authority);
+        return Err(HttpError::Proxy(reason));
+    }
+
+    Ok(())
+}
+
+/// sends one request and reads its response head, returning how the body
+/// is framed, and whether the connection can be kept alive for another request.
+#[instrument(skip(connection, request_headers, request_body))]
+fn exchange(
+    connection: &mut Connection,
+    method: &str,
+    url: &Url,
+    target: &str,
+    request_headers: &Headers,
+    request_body: &[u8],
+) -> Result<(usize, Headers, Framing, bool), HttpError> {
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, target);
+    head += &format!("Host: {}\r\n", url.host_and_port());
+    head += &format!("Accept-Encoding: {}\r\n", ACCEPT_ENCODING);
+    for (field, value) in request_headers.iter() {
+        head += &format!("{}: {}\r\n", field, value);
+    }
+    // methods like POST need a length even when the body is empty
+    // https://httpwg.org/specs/rfc9110.html#field.content-length
+    if !request_body.is_empty() || !matches!(method, "GET" | "HEAD") {
+        head += &format!("Content-Length: {}\r\n", request_body.len());
+    }
+    head += "\r\n";
+    connection.get_mut().write_all(head.as_bytes())?;
+    connection.get_mut().write_all(request_body)?;
+    connection.get_mut().flush()?;
+
+    // skip interim responses like 100 Continue and 103 Early Hints
+    let (version, status, headers) = loop {
+        let (version, status, headers) = read_response_head(connection)?;
+        if !(100..200).contains(&status) || status == 101 {
+            break (version, status, headers);
+        }
+        debug!(status, "skipping interim response");
+    };
+
+    // https://httpwg.org/specs/rfc9112.html#persistent.connections
+    let mut keep_alive = match &*version {
+        "HTTP/1.1" => !has_token(&headers, "connection", "close"),
+        _ => has_token(&headers, "connection", "keep-alive"),
+    };
+
+    // https://httpwg.org/specs/rfc9112.html#message.body.length
+    let framing = if method == "HEAD" || matches!(status, 204 | 304) {
+        // no body, even if there are content-length or transfer-encoding headers
+        Framing::Length(0)
+    } else if let Some(codings) = headers.get_combined("transfer-encoding") {
+        // chunked must be the final transfer coding, and we support no others
+        if !codings.eq_ignore_ascii_case("chunked") {
+            let reason = format!("unsupported transfer-encoding: {:?}", codings);
+            return Err(HttpError::MalformedResponse(reason));
+        }
+        Framing::Chunked
+    } else if let Some(length) = headers.get("content-length") {
+        let Ok(length) = u64::from_str(length) else {
+            let reason = format!("invalid content-length: {:?}", length);
+            return Err(HttpError::MalformedResponse(reason));
+        };
+        Framing::Length(length)
     } else {
-        let base = base.map(|x| Url::new(x, None).unwrap());
-        Url::new(url, base.as_ref())?
+        // body ends when the server closes the connection
+        keep_alive = false;
+        Framing::Close
     };
+    debug!(?framing, keep_alive);
 
-    let mut stream: Box<dyn ReadWriteStream> = match url.scheme() {
-        "http:" => Box::new(TcpStream::connect((url.hostname(), url.port()))?),
-        "https:" => {
-            let connector = RustlsConnector::new_with_native_certs()?;
-            let stream = TcpStream::connect((url.hostname(), url.port()))?;
-            Box::new(connector.connect(url.hostname(), stream)?)
+    Ok((status, headers, framing, keep_alive))
+}
+
+fn read_response_head(stream: &mut impl BufRead) -> Result<(String, usize, Headers), HttpError> {
+    let Some(line) = read_line(stream)? else {
+        return Err(HttpError::MalformedResponse(
+            "connection closed before response".to_owned(),
+        ));
+    };
+
+    // the reason phrase is optional, and so is the space before it
+    let mut parts = line.splitn(3, ' ');
+    let (version, status) = match (parts.next(), parts.next()) {
+        (Some(version), Some(status))
+            if version.starts_with("HTTP/")
+                && status.len() == 3
+                && status.bytes().all(|x| x.is_ascii_digit()) =>
+        {
+            (version.to_owned(), usize::from_str(status).unwrap())
+        }
+        _ => {
+            let reason = format!("invalid status line: {:?}", line);
+            return Err(HttpError::MalformedResponse(reason));
         }
-        other => bail!("unknown scheme: {:?}", other),
     };
-    write!(stream, "GET {} HTTP/1.0\r\n", url.path())?;
-    write!(stream, "Host: {}:{}\r\n\r\n", url.hostname(), url.port())?;
 
-    let mut stream = BufReader::new(stream);
-    let mut received = vec![];
-    assert_ne!(stream.read_until(b'\n', &mut received)?, 0);
-
-    let line = received.strip_suffix(b"\r\n").unwrap();
-    let [_version, status, _explanation] = line.splitn(3, |x| *x == b' ').collect::<Vec<_>>()[..]
-        else { panic!("failed to parse response status line") };
-    let Ok(Ok(status)) = str::from_utf8(status).map(usize::from_str)
-        else { panic!("failed to parse response status code") };
-    received.clear();
-
-    let mut headers = BTreeMap::default();
-    while stream.read_until(b'\n', &mut received)? > 0 {
-        // TODO: hard-coding utf-8 is not correct in practice
-        let line = str::from_utf8(&received)?;
-        let line = line.strip_suffix("\r\n").unwrap();
+    let mut headers = Headers::new();
+    loop {
+        let Some(line) = read_line(stream)? else {
+            return Err(HttpError::MalformedResponse(
+                "connection closed in headers".to_owned(),
+            ));
+        };
         if line.is_empty() {
             break;
         }
-        let (field, value) = line
-            .split_once(":")
-            .expect("failed to parse response header");
+        let Some((field, value)) = line.split_once(':') else {
+            let reason = format!("invalid header line: {:?}", line);
+            return Err(HttpError::MalformedResponse(reason));
+        };
         debug!(field = field, value = value);
-        headers.insert(
-            trim_ascii(field).to_ascii_lowercase(),
-            trim_ascii(value).to_owned(),
-        );
-        received.clear();
+        headers.append(trim_ascii(field).to_ascii_lowercase(), trim_ascii(value));
     }
 
-    assert!(!headers.contains_key("transfer-encoding"));
-    assert!(!headers.contains_key("content-encoding"));
+    Ok((version, status, headers))
+}
 
-    let mut body = vec![];
-    stream.read_to_end(&mut body)?;
-    debug!(body = dump(&body));
+/// reads a line of the response head, or None at eof. we accept a bare lf
+/// as well as crlf, and decode as latin-1, since that’s what servers send.
+/// <https://httpwg.org/specs/rfc9112.html#message.parsing>
+fn read_line(stream: &mut impl BufRead) -> Result<Option<String>, HttpError> {
+    let mut received = vec![];
+    if stream.read_until(b'\n', &mut received)? == 0 {
+        return Ok(None);
+    }
+    let Some(line) = received.strip_suffix(b"\n") else {
+        return Err(HttpError::MalformedResponse(
+            "connection closed mid-line".to_owned(),
+        ));
+    };
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
 
-    Ok((status, headers, body))
+    Ok(Some(line.iter().map(|x| *x as char).collect()))
 }
 
-#[derive(Debug)]
-pub struct Url {
-    scheme: String,
-    hostname: String,
-    port: u16,
-    path: String,
-}
-
-impl Url {
-    pub fn new(mut url: &str, base: Option<&Url>) -> eyre::Result<Self> {
-        let Some(scheme) = lparse_chomp(&mut url, "[A-Za-z0-9-]+:")
-            .map(|x| x.get(0).unwrap().as_str().to_owned())
-            .or_else(|| base.map(|x| x.scheme.clone()))
-            else { bail!("no scheme found but no base given") };
-        let (hostname, port) = if lparse_chomp(&mut url, "//").is_some() {
-            let Some(host) = lparse_chomp(&mut url, "[^/]+")
-                .map(|x| x.get(0).unwrap().as_str())
-                else { bail!("failed to chomp host") };
-            let (port, hostname) = rparse_split(host, r":([0-9]+)")
-                .map(|x| x.into_pair())
-                .unwrap_or((
-                    match scheme.as_ref() {
-                        "http:" => ":80",
-                        "https:" => ":443",
-                        _ => unreachable!(),
-                    },
-                    host,
-                ));
-            let port = u16::from_str(&port[1..])?;
-
-            (hostname.to_owned(), port)
-        } else if let Some(base) = base {
-            (base.hostname.clone(), base.port)
+pub fn percent_decode(mut input: &str) -> Vec<u8> {
+    let mut result = vec![];
+    while !input.is_empty() {
+        if let Some(percent) = lparse_chomp(&mut input, "%[0-9A-Fa-f]{2}") {
+            let percent = percent.get(0).unwrap().as_str();
+            result.push(u8::from_str_radix(&percent[1..], 16).unwrap());
         } else {
-            bail!("no host found but no base given")
-        };
-        let path = match url {
-            "" => "/".to_owned(),
-            other => {
-                if other.starts_with("/") {
-                    other.to_owned()
-                } else if let Some(base) = base {
-                    let (_, base) = rparse_split(&base.path, "[^/]*")
-                        .map(|x| x.into_pair())
-                        .unwrap();
-                    base.to_owned() + other
-                } else {
-                    bail!("relative path found but no base given")
+            let index = input.char_indices().nth(1).map_or(input.len(), |(i, _)| i);
+            let (next, rest) = input.split_at(index);
+            for octet in next.bytes() {
+                result.push(octet);
+            }
+            input = rest;
+        }
+    }
+
+    result
+}
+
+fn has_token(headers: &Headers, field: &str, token: &str) -> bool {
+    headers
+        .get_list(field)
+        .any(|x| x.eq_ignore_ascii_case(token))
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_redirects() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{io::{BufRead, BufReader, Write}, net::TcpListener, sync::{Arc, Mutex}, thread};
+
+    // a server that redirects according to the path, and echoes anything else
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let base = Url::parse(&format!("http://{}/", listener.local_addr()?), None)?;
+    let received = Arc::new(Mutex::new(vec![]));
+    let log = received.clone();
+    thread::spawn(move || -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept()?;
+            let mut reader = BufReader::new(stream);
+            let (mut head, mut content_type, mut length) = (String::new(), "-".to_owned(), 0);
+            while !head.ends_with("\r\n\r\n") {
+                let start = head.len();
+                reader.read_line(&mut head)?;
+                let line = head[start..].trim_end().to_owned();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length: ") {
+                    length = value.parse().unwrap();
+                }
+                if let Some(value) = line.strip_prefix("Content-Type: ") {
+                    content_type = value.to_owned();
                 }
             }
-        };
-        trace!(url, scheme, hostname, port, path);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            let (method, path) = head.split_once(' ').unwrap();
+            let path = path.split_once(' ').unwrap().0;
+            let echo = format!("{} {} {}", method, content_type, String::from_utf8_lossy(&body));
+            log.lock().unwrap().push(format!("{} {}", method, path));
 
-        Ok(Self {
-            scheme,
-            hostname,
-            port,
-            path,
-        })
-    }
+            let (status, location) = match path {
+                "/a/relative" => (302, "../b".to_owned()),
+                "/loop/1" => (301, "/loop/2".to_owned()),
+                "/loop/2" => (301, "/loop/1".to_owned()),
+                "/post/301" => (301, "/echo".to_owned()),
+                "/post/302" => (302, "/echo".to_owned()),
+                "/post/307" => (307, "/echo".to_owned()),
+                "/post/308" => (308, "/echo".to_owned()),
+                path => match path.strip_prefix("/hop/") {
+                    Some(n) => (302, format!("/hop/{}", n.parse::<usize>().unwrap() + 1)),
+                    None => (200, String::new()),
+                },
+            };
+            let body = if status == 200 { echo } else { String::new() };
+            write!(reader.get_mut(), "HTTP/1.1 {} x\r\nLocation: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}", status, location, body.len(), body)?;
+        }
+    });
+    let url = |x: &str| Url::parse(x, Some(&base));
+    let post = |x: &str| -> Result<Response, HttpError> {
+        Request::post(url(x)?).body("text/plain", "a=1").send(&Settings::default())
+    };
 
-    pub fn scheme(&self) -> &str {
-        &self.scheme
-    }
+    // relative locations resolve against the current url, and fragments carry over
+    let response = Request::get(url("/a/relative#x")?).send(&Settings::default())?;
+    assert_eq!(response.url, url("/b#x")?);
+    assert_eq!(response.body, b"GET - ");
 
-    pub fn hostname(&self) -> &str {
-        &self.hostname
-    }
+    // loops and long chains are errors
+    assert!(matches!(Request::get(url("/loop/1")?).send(&Settings::default()), Err(HttpError::RedirectLoop(_))));
+    received.lock().unwrap().clear();
+    assert!(matches!(Request::get(url("/hop/0")?).send(&Settings::default()), Err(HttpError::TooManyRedirects(_))));
+    assert_eq!(received.lock().unwrap().len(), MAX_REDIRECTS + 1);
 
-    pub fn port(&self) -> u16 {
-        self.port
-    }
+    // 301 and 302 turn post into get without the body, but 307 and 308 don’t
+    assert_eq!(post("/post/301")?.body, b"GET - ");
+    assert_eq!(post("/post/302")?.body, b"GET - ");
+    assert_eq!(post("/post/307")?.body, b"POST text/plain a=1");
+    assert_eq!(post("/post/308")?.body, b"POST text/plain a=1");
 
-    pub fn path(&self) -> &str {
-        &self.path
-    }
+    Ok(())
 }
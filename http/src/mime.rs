@@ -0,0 +1,132 @@
+use std::fmt::Display;
+
+/// a parsed mime type, with lowercase type, subtype, and parameter names.
+/// <https://mimesniff.spec.whatwg.org/#mime-type-representation>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimeType {
+    pub r#type: String,
+    pub subtype: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+impl MimeType {
+    /// <https://mimesniff.spec.whatwg.org/#parse-a-mime-type>
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim_matches(is_http_whitespace);
+        let (r#type, rest) = input.split_once('/')?;
+        if r#type.is_empty() || !r#type.chars().all(is_http_token_code_point) {
+            return None;
+        }
+        let (subtype, mut rest) = rest.split_once(';').unwrap_or((rest, ""));
+        let subtype = subtype.trim_end_matches(is_http_whitespace);
+        if subtype.is_empty() || !subtype.chars().all(is_http_token_code_point) {
+            return None;
+        }
+
+        let mut parameters: Vec<(String, String)> = vec![];
+        while !rest.is_empty() {
+            rest = rest.trim_start_matches(is_http_whitespace);
+            let end = rest.find([';', '=']).unwrap_or(rest.len());
+            let name = rest[..end].to_ascii_lowercase();
+            rest = &rest[end..];
+            if let Some(next) = rest.strip_prefix(';') {
+                rest = next;
+                continue;
+            }
+            let Some(next) = rest.strip_prefix('=') else { break };
+            rest = next;
+
+            let value = if rest.starts_with('"') {
+                let value = collect_quoted_string(&mut rest);
+                rest = rest.split_once(';').map_or("", |(_, x)| x);
+
+                value
+            } else {
+                let (value, next) = rest.split_once(';').unwrap_or((rest, ""));
+                rest = next;
+                let value = value.trim_end_matches(is_http_whitespace);
+                if value.is_empty() {
+                    continue;
+                }
+
+                value.to_owned()
+            };
+
+            if !name.is_empty()
+                && name.chars().all(is_http_token_code_point)
+                && value.chars().all(is_quoted_string_token_code_point)
+                && !parameters.iter().any(|(x, _)| *x == name)
+            {
+                parameters.push((name, value));
+            }
+        }
+
+        Some(Self {
+            r#type: r#type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters,
+        })
+    }
+
+    /// <https://mimesniff.spec.whatwg.org/#mime-type-essence>
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.r#type, self.subtype)
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, x)| &**x)
+    }
+}
+
+/// <https://mimesniff.spec.whatwg.org/#serialize-a-mime-type>
+impl Display for MimeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.r#type, self.subtype)?;
+        for (name, value) in &self.parameters {
+            write!(f, ";{}=", name)?;
+            if !value.is_empty() && value.chars().all(is_http_token_code_point) {
+                write!(f, "{}", value)?;
+            } else {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                write!(f, "\"{}\"", value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// <https://fetch.spec.whatwg.org/#collect-an-http-quoted-string>
+fn collect_quoted_string(input: &mut &str) -> String {
+    let mut result = String::new();
+    let mut chars = input.chars();
+    chars.next(); // opening quote
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some(c) => result.push(c),
+                None => result.push('\\'),
+            },
+            c => result.push(c),
+        }
+    }
+    *input = chars.as_str();
+
+    result
+}
+
+fn is_http_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | ' ')
+}
+
+fn is_http_token_code_point(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+fn is_quoted_string_token_code_point(c: char) -> bool {
+    matches!(c, '\t' | ' '..='~' | '\u{80}'..='\u{FF}')
+}
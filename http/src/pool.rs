@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    io::BufReader,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, instrument, trace};
+
+use crate::timeout::LimitedStream;
+
+/// close idle connections well before servers typically would, so that we
+/// rarely try to reuse a connection the server has already given up on.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// same as the usual browser limit on connections per origin.
+pub const MAX_IDLE_PER_ORIGIN: usize = 6;
+
+pub type Connection = BufReader<LimitedStream>;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PoolKey {
+    pub scheme: String,
+    pub hostname: String,
+    pub port: u16,
+    /// connections through a proxy are only reusable through that proxy.
+    pub proxy: Option<String>,
+}
+
+/// idle keep-alive connections, keyed by scheme, host, port, and proxy.
+#[derive(Default)]
+pub struct ConnectionPool(Mutex<BTreeMap<PoolKey, Vec<(Instant, Connection)>>>);
+
+impl ConnectionPool {
+    /// takes the most recently used idle connection for the given key, if any.
+    #[instrument(skip(self))]
+    pub fn checkout(&self, key: &PoolKey) -> Option<Connection> {
+        let mut pool = self.0.lock().unwrap();
+        Self::evict_idle(&mut pool);
+        let result = pool.get_mut(key).and_then(|x| x.pop()).map(|(_, x)| x);
+        debug!(reused = result.is_some());
+
+        result
+    }
+
+    /// returns a connection to the pool, once its response has been read in full.
+    #[instrument(skip(self, connection))]
+    pub fn checkin(&self, key: PoolKey, connection: Connection) {
+        let mut pool = self.0.lock().unwrap();
+        Self::evict_idle(&mut pool);
+        let idle = pool.entry(key).or_default();
+        if idle.len() >= MAX_IDLE_PER_ORIGIN {
+            // drop the least recently used connection
+            idle.remove(0);
+        }
+        idle.push((Instant::now(), connection));
+        trace!(idle = idle.len());
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    fn evict_idle(pool: &mut BTreeMap<PoolKey, Vec<(Instant, Connection)>>) {
+        let now = Instant::now();
+        for (key, idle) in pool.iter_mut() {
+            let len = idle.len();
+            idle.retain(|(since, _)| now.duration_since(*since) < IDLE_TIMEOUT);
+            if idle.len() < len {
+                debug!(?key, evicted = len - idle.len());
+            }
+        }
+        pool.retain(|_, idle| !idle.is_empty());
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_connection_pool() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{io::{BufRead, Read, Write}, net::{TcpListener, TcpStream}, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread};
+    use crate::{Request, Settings, Url};
+
+    // a keep-alive server that counts connections, and closes them or sends
+    // a long body depending on the path
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let base = Url::parse(&format!("http://{}/", listener.local_addr()?), None)?;
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let count = accepted.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            count.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || -> std::io::Result<()> {
+                let mut reader = BufReader::new(stream?);
+                loop {
+                    let mut head = String::new();
+                    while !head.ends_with("\r\n\r\n") {
+                        if reader.read_line(&mut head)? == 0 {
+                            return Ok(());
+                        }
+                    }
+                    let path = head.split(' ').nth(1).unwrap().to_owned();
+                    let (close, body) = match &*path {
+                        "/close" => ("Connection: close\r\n", "ok".to_owned()),
+                        "/long" => ("", "x".repeat(1 << 20)),
+                        _ => ("", "ok".to_owned()),
+                    };
+                    write!(reader.get_mut(), "HTTP/1.1 200 OK\r\n{}Cache-Control: no-store\r\nContent-Length: {}\r\n\r\n{}", close, body.len(), body)?;
+                    if !close.is_empty() || path == "/stale" {
+                        return Ok(());
+                    }
+                }
+            });
+        }
+    });
+    let get = |path: &str| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(Request::get(Url::parse(path, Some(&base))?).send(&Settings::default())?.body)
+    };
+
+    // sequential requests share a connection
+    assert_eq!(get("/a")?, b"ok");
+    assert_eq!(get("/b")?, b"ok");
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+    // but not after the server closes it
+    assert_eq!(get("/close")?, b"ok");
+    assert_eq!(get("/a")?, b"ok");
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+    // or if we stop reading the body early
+    let mut response = Request::get(Url::parse("/long", Some(&base))?).send_streaming(&Settings::default())?;
+    response.body.read_exact(&mut [0; 2])?;
+    drop(response);
+    assert_eq!(get("/a")?, b"ok");
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+
+    // a pooled connection the server has since closed gets retried
+    assert_eq!(get("/stale")?, b"ok");
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(get("/a")?, b"ok");
+    assert_eq!(accepted.load(Ordering::SeqCst), 4);
+
+    // idle connections are evicted after a while, and there are only a few
+    // per origin
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let connection = || -> std::io::Result<Connection> {
+        let socket = TcpStream::connect(listener.local_addr()?)?;
+        Ok(BufReader::new(LimitedStream::new(Box::new(socket.try_clone()?), socket)))
+    };
+    let key = |port| PoolKey { scheme: "http".to_owned(), hostname: "example.org".to_owned(), port, proxy: None };
+    let pool = ConnectionPool::default();
+    for _ in 0..MAX_IDLE_PER_ORIGIN + 2 {
+        pool.checkin(key(80), connection()?);
+    }
+    assert_eq!(pool.0.lock().unwrap()[&key(80)].len(), MAX_IDLE_PER_ORIGIN);
+    let stale = Instant::now() - IDLE_TIMEOUT - Duration::from_secs(1);
+    pool.0.lock().unwrap().insert(key(8080), vec![(stale, connection()?)]);
+    assert!(pool.checkout(&key(8080)).is_none());
+    assert!(pool.checkout(&key(80)).is_some());
+    assert!(pool.checkout(&key(81)).is_none());
+
+    Ok(())
+}
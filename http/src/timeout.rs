@@ -0,0 +1,178 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use wbe_core::{CancellationToken, Cancelled, ReadWriteStream};
+
+/// how often a blocked read wakes up to check for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// how long we wait for the network before giving up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// for each connection attempt, including the tls handshake.
+    pub connect: Duration,
+    /// for each read, so a slow but steady response is fine.
+    pub read: Duration,
+    /// for the whole request, including any redirects.
+    pub total: Duration,
+}
+
+/// the timeouts and cancellation for one request, which
+/// [`LimitedStream`] enforces while the request has its connection.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub timeouts: Timeouts,
+    pub deadline: Instant,
+    pub cancellation: CancellationToken,
+}
+
+/// a connection that fails reads with TimedOut when they take too long, or
+/// with a [`Cancelled`] error when its request is cancelled.
+pub struct LimitedStream {
+    inner: Box<dyn ReadWriteStream + Send>,
+    /// handle to the underlying socket, for setting timeouts under tls.
+    socket: TcpStream,
+    limits: Option<Limits>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(10),
+            read: Duration::from_secs(30),
+            total: Duration::from_secs(120),
+        }
+    }
+}
+
+impl Limits {
+    pub fn new(timeouts: Timeouts, cancellation: CancellationToken) -> Self {
+        Self {
+            timeouts,
+            deadline: Instant::now() + timeouts.total,
+            cancellation,
+        }
+    }
+
+    /// how long the next blocking operation can take, given its own
+    /// timeout, or an error if we’re cancelled or out of time.
+    pub fn next_wait(&self, timeout: Duration) -> io::Result<Duration> {
+        if self.cancellation.is_cancelled() {
+            return Err(io::Error::other(Cancelled));
+        }
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || timeout.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "timed out"));
+        }
+
+        Ok(timeout.min(remaining))
+    }
+}
+
+impl LimitedStream {
+    pub fn new(inner: Box<dyn ReadWriteStream + Send>, socket: TcpStream) -> Self {
+        Self {
+            inner,
+            socket,
+            limits: None,
+        }
+    }
+
+    /// sets the limits for the request that now has the connection, or
+    /// None while it sits idle in the pool.
+    pub fn set_limits(&mut self, limits: Option<Limits>) {
+        self.limits = limits;
+    }
+}
+
+impl Read for LimitedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(limits) = &self.limits else { return self.inner.read(buf) };
+
+        // wake up every so often to check for cancellation. retrying is safe
+        // even under tls, because rustls only consumes whole records
+        let start = Instant::now();
+        loop {
+            let timeout = limits.timeouts.read.saturating_sub(start.elapsed());
+            let wait = limits.next_wait(timeout)?.min(POLL_INTERVAL);
+            self.socket.set_read_timeout(Some(wait))?;
+            match self.inner.read(buf) {
+                Err(error)
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    continue
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Write for LimitedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // no polling here, since a failed tls write may have buffered some of
+        // the data, so we can’t retry it. requests are small anyway
+        if let Some(limits) = &self.limits {
+            let wait = limits.next_wait(limits.timeouts.read)?;
+            self.socket.set_write_timeout(Some(wait))?;
+        }
+
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_limited_stream() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{net::TcpListener, thread};
+
+    use crate::{HttpError, Request, Settings, Url};
+
+    // a server that accepts connections but never responds
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = Url::parse(&format!("http://{}/", listener.local_addr()?), None)?;
+    thread::spawn(move || {
+        let _connections = listener.incoming().collect::<Vec<_>>();
+    });
+
+    let timeouts = Timeouts {
+        read: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let result = Request::get(url.clone())
+        .timeouts(timeouts)
+        .send(&Settings::default());
+    assert!(matches!(result, Err(HttpError::Timeout)), "{:?}", result);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let timeouts = Timeouts {
+        total: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let result = Request::get(url.clone())
+        .timeouts(timeouts)
+        .send(&Settings::default());
+    assert!(matches!(result, Err(HttpError::Timeout)), "{:?}", result);
+
+    let cancellation = CancellationToken::new();
+    let canceller = cancellation.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        canceller.cancel();
+    });
+    let start = Instant::now();
+    let result = Request::get(url)
+        .cancellation(cancellation)
+        .send(&Settings::default());
+    assert!(matches!(result, Err(HttpError::Cancelled)), "{:?}", result);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    Ok(())
+}
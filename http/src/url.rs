@@ -0,0 +1,1240 @@
+use std::{
+    error::Error,
+    fmt::{Debug, Display, Write},
+    str,
+};
+
+use tracing::{instrument, trace};
+
+use crate::percent_decode;
+
+/// the special schemes, and their default ports.
+/// <https://url.spec.whatwg.org/#special-scheme>
+pub const SPECIAL_SCHEMES: &[(&str, Option<u16>)] = &[
+    ("ftp", Some(21)),
+    ("file", None),
+    ("http", Some(80)),
+    ("https", Some(443)),
+    ("ws", Some(80)),
+    ("wss", Some(443)),
+];
+
+/// a parsed url, per the whatwg url standard.
+/// <https://url.spec.whatwg.org/#concept-url>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    scheme: String,
+    username: String,
+    password: String,
+    host: Option<Host>,
+    port: Option<u16>,
+    path: UrlPath,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+/// <https://url.spec.whatwg.org/#concept-host>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Domain(String),
+    Ipv4(u32),
+    Ipv6([u16; 8]),
+    Opaque(String),
+    Empty,
+}
+
+/// <https://url.spec.whatwg.org/#concept-url-path>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlPath {
+    Opaque(String),
+    Segments(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub input: String,
+    pub reason: &'static str,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid url ({}): {:?}", self.reason, self.input)
+    }
+}
+
+impl Error for ParseError {}
+
+impl Url {
+    /// <https://url.spec.whatwg.org/#concept-basic-url-parser>
+    #[instrument]
+    pub fn parse(input: &str, base: Option<&Url>) -> Result<Self, ParseError> {
+        let result = Parser::new(input, base).run().map_err(|reason| ParseError {
+            input: input.to_owned(),
+            reason,
+        });
+        trace!(?result);
+
+        result
+    }
+
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    pub fn is_special(&self) -> bool {
+        is_special(&self.scheme)
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    pub fn host(&self) -> Option<&Host> {
+        self.host.as_ref()
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    pub fn port_or_known_default(&self) -> Option<u16> {
+        self.port.or_else(|| default_port(&self.scheme))
+    }
+
+    pub fn path(&self) -> &UrlPath {
+        &self.path
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// replaces the scheme of a special url with another special scheme,
+    /// dropping the port if it’s the default for the new scheme.
+    /// <https://url.spec.whatwg.org/#scheme-state>
+    pub fn set_scheme(&mut self, scheme: &str) {
+        assert!(default_port(&self.scheme).is_some() && default_port(scheme).is_some());
+        self.scheme = scheme.to_owned();
+        if self.port == default_port(scheme) {
+            self.port = None;
+        }
+    }
+
+    pub fn set_fragment(&mut self, fragment: Option<String>) {
+        self.fragment = fragment;
+    }
+
+    /// replaces the query, which must already be percent-encoded.
+    pub fn set_query(&mut self, query: Option<String>) {
+        self.query = query;
+    }
+
+    /// <https://url.spec.whatwg.org/#dom-url-href>
+    pub fn href(&self) -> String {
+        self.to_string()
+    }
+
+    /// <https://url.spec.whatwg.org/#concept-url-origin>
+    pub fn origin(&self) -> String {
+        match &*self.scheme {
+            "blob" => match Url::parse(&self.pathname(), None) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => url.origin(),
+                _ => "null".to_owned(),
+            },
+            "ftp" | "http" | "https" | "ws" | "wss" => {
+                format!("{}://{}", self.scheme, self.host_and_port())
+            }
+            _ => "null".to_owned(),
+        }
+    }
+
+    /// <https://url.spec.whatwg.org/#dom-url-protocol>
+    pub fn protocol(&self) -> String {
+        format!("{}:", self.scheme)
+    }
+
+    /// <https://url.spec.whatwg.org/#dom-url-host>
+    pub fn host_and_port(&self) -> String {
+        match self.port {
+            Some(port) => format!("{}:{}", self.hostname(), port),
+            None => self.hostname(),
+        }
+    }
+
+    /// <https://url.spec.whatwg.org/#dom-url-hostname>
+    pub fn hostname(&self) -> String {
+        self.host.as_ref().map_or("".to_owned(), |x| x.to_string())
+    }
+
+    /// <https://url.spec.whatwg.org/#url-path-serializer>
+    pub fn pathname(&self) -> String {
+        match &self.path {
+            UrlPath::Opaque(path) => path.clone(),
+            UrlPath::Segments(segments) => {
+                let mut result = String::new();
+                for segment in segments {
+                    result += "/";
+                    result += segment;
+                }
+
+                result
+            }
+        }
+    }
+
+    /// <https://url.spec.whatwg.org/#dom-url-search>
+    pub fn search(&self) -> String {
+        match self.query.as_deref() {
+            None | Some("") => "".to_owned(),
+            Some(query) => format!("?{}", query),
+        }
+    }
+
+    /// <https://url.spec.whatwg.org/#dom-url-hash>
+    pub fn hash(&self) -> String {
+        match self.fragment.as_deref() {
+            None | Some("") => "".to_owned(),
+            Some(fragment) => format!("#{}", fragment),
+        }
+    }
+
+    /// path and query, for the request target of an http request.
+    pub fn path_and_query(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.pathname(), query),
+            None => self.pathname(),
+        }
+    }
+}
+
+/// <https://url.spec.whatwg.org/#concept-url-serializer>
+impl Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.scheme)?;
+        if let Some(host) = &self.host {
+            write!(f, "//")?;
+            if !self.username.is_empty() || !self.password.is_empty() {
+                write!(f, "{}", self.username)?;
+                if !self.password.is_empty() {
+                    write!(f, ":{}", self.password)?;
+                }
+                write!(f, "@")?;
+            }
+            write!(f, "{}", host)?;
+            if let Some(port) = self.port {
+                write!(f, ":{}", port)?;
+            }
+        } else if let UrlPath::Segments(segments) = &self.path {
+            // keep a path like //x from being mistaken for a host
+            if segments.len() > 1 && segments[0].is_empty() {
+                write!(f, "/.")?;
+            }
+        }
+        write!(f, "{}", self.pathname())?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// <https://url.spec.whatwg.org/#host-serializing>
+impl Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Host::Domain(x) | Host::Opaque(x) => write!(f, "{}", x),
+            Host::Ipv4(x) => {
+                let [a, b, c, d] = x.to_be_bytes();
+                write!(f, "{}.{}.{}.{}", a, b, c, d)
+            }
+            Host::Ipv6(pieces) => {
+                // compress the first longest run of two or more zero pieces
+                let mut compress = None;
+                let mut longest = 1;
+                let mut i = 0;
+                while i < 8 {
+                    let len = pieces[i..].iter().take_while(|x| **x == 0).count();
+                    if len > longest {
+                        compress = Some(i);
+                        longest = len;
+                    }
+                    i += len.max(1);
+                }
+
+                write!(f, "[")?;
+                let mut i = 0;
+                while i < 8 {
+                    if compress == Some(i) {
+                        write!(f, "{}", if i == 0 { "::" } else { ":" })?;
+                        i += longest;
+                        continue;
+                    }
+                    write!(f, "{:x}", pieces[i])?;
+                    if i != 7 {
+                        write!(f, ":")?;
+                    }
+                    i += 1;
+                }
+                write!(f, "]")
+            }
+            Host::Empty => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    SchemeStart,
+    Scheme,
+    NoScheme,
+    SpecialRelativeOrAuthority,
+    PathOrAuthority,
+    Relative,
+    RelativeSlash,
+    SpecialAuthoritySlashes,
+    SpecialAuthorityIgnoreSlashes,
+    Authority,
+    Host,
+    Port,
+    File,
+    FileSlash,
+    FileHost,
+    PathStart,
+    Path,
+    OpaquePath,
+    Query,
+    Fragment,
+}
+
+struct Parser<'b> {
+    input: Vec<char>,
+    base: Option<&'b Url>,
+    url: Url,
+    state: State,
+    pointer: isize,
+    buffer: String,
+    at_sign_seen: bool,
+    inside_brackets: bool,
+    password_token_seen: bool,
+}
+
+impl<'b> Parser<'b> {
+    fn new(input: &str, base: Option<&'b Url>) -> Self {
+        // strip leading and trailing c0 controls and spaces, then remove all tabs and newlines
+        let input = input.trim_matches(|c| c <= ' ');
+        let input = input
+            .chars()
+            .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+            .collect();
+
+        Self {
+            input,
+            base,
+            url: Url {
+                scheme: "".to_owned(),
+                username: "".to_owned(),
+                password: "".to_owned(),
+                host: None,
+                port: None,
+                path: UrlPath::Segments(vec![]),
+                query: None,
+                fragment: None,
+            },
+            state: State::SchemeStart,
+            pointer: 0,
+            buffer: "".to_owned(),
+            at_sign_seen: false,
+            inside_brackets: false,
+            password_token_seen: false,
+        }
+    }
+
+    fn c(&self) -> Option<char> {
+        usize::try_from(self.pointer)
+            .ok()
+            .and_then(|x| self.input.get(x))
+            .copied()
+    }
+
+    fn remaining(&self) -> &[char] {
+        let start = usize::try_from(self.pointer + 1).unwrap_or(0);
+
+        self.input.get(start..).unwrap_or(&[])
+    }
+
+    fn remaining_starts_with(&self, c: char) -> bool {
+        self.remaining().first() == Some(&c)
+    }
+
+    fn segments(&mut self) -> &mut Vec<String> {
+        match &mut self.url.path {
+            UrlPath::Segments(segments) => segments,
+            UrlPath::Opaque(_) => unreachable!("url has opaque path"),
+        }
+    }
+
+    /// <https://url.spec.whatwg.org/#shorten-a-urls-path>
+    fn shorten_path(&mut self) {
+        let is_file = self.url.scheme == "file";
+        let segments = self.segments();
+        if is_file && segments.len() == 1 && is_normalized_windows_drive_letter(&segments[0]) {
+            return;
+        }
+        segments.pop();
+    }
+
+    fn run(mut self) -> Result<Url, &'static str> {
+        loop {
+            let c = self.c();
+            match self.state {
+                State::SchemeStart => match c {
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        self.buffer.push(c.to_ascii_lowercase());
+                        self.state = State::Scheme;
+                    }
+                    _ => {
+                        self.state = State::NoScheme;
+                        self.pointer -= 1;
+                    }
+                },
+                State::Scheme => match c {
+                    Some(c) if c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.') => {
+                        self.buffer.push(c.to_ascii_lowercase());
+                    }
+                    Some(':') => {
+                        self.url.scheme = std::mem::take(&mut self.buffer);
+                        if self.url.scheme == "file" {
+                            self.state = State::File;
+                        } else if self.url.is_special()
+                            && self.base.map(|x| &*x.scheme) == Some(&*self.url.scheme)
+                        {
+                            self.state = State::SpecialRelativeOrAuthority;
+                        } else if self.url.is_special() {
+                            self.state = State::SpecialAuthoritySlashes;
+                        } else if self.remaining_starts_with('/') {
+                            self.state = State::PathOrAuthority;
+                            self.pointer += 1;
+                        } else {
+                            self.url.path = UrlPath::Opaque("".to_owned());
+                            self.state = State::OpaquePath;
+                        }
+                    }
+                    _ => {
+                        // start over from the first code point
+                        self.buffer.clear();
+                        self.state = State::NoScheme;
+                        self.pointer = -1;
+                    }
+                },
+                State::NoScheme => {
+                    let Some(base) = self.base else { return Err("missing scheme and no base") };
+                    match (&base.path, c) {
+                        (UrlPath::Opaque(_), Some('#')) => {
+                            self.url.scheme = base.scheme.clone();
+                            self.url.path = base.path.clone();
+                            self.url.query = base.query.clone();
+                            self.url.fragment = Some("".to_owned());
+                            self.state = State::Fragment;
+                        }
+                        (UrlPath::Opaque(_), _) => return Err("relative url with opaque base"),
+                        _ if base.scheme != "file" => {
+                            self.state = State::Relative;
+                            self.pointer -= 1;
+                        }
+                        _ => {
+                            self.state = State::File;
+                            self.pointer -= 1;
+                        }
+                    }
+                }
+                State::SpecialRelativeOrAuthority => {
+                    if c == Some('/') && self.remaining_starts_with('/') {
+                        self.state = State::SpecialAuthorityIgnoreSlashes;
+                        self.pointer += 1;
+                    } else {
+                        self.state = State::Relative;
+                        self.pointer -= 1;
+                    }
+                }
+                State::PathOrAuthority => {
+                    if c == Some('/') {
+                        self.state = State::Authority;
+                    } else {
+                        self.state = State::Path;
+                        self.pointer -= 1;
+                    }
+                }
+                State::Relative => {
+                    let base = self.base.expect("relative state with no base");
+                    self.url.scheme = base.scheme.clone();
+                    if c == Some('/') || self.url.is_special() && c == Some('\\') {
+                        self.state = State::RelativeSlash;
+                    } else {
+                        self.url.username = base.username.clone();
+                        self.url.password = base.password.clone();
+                        self.url.host = base.host.clone();
+                        self.url.port = base.port;
+                        self.url.path = base.path.clone();
+                        self.url.query = base.query.clone();
+                        match c {
+                            Some('?') => {
+                                self.url.query = Some("".to_owned());
+                                self.state = State::Query;
+                            }
+                            Some('#') => {
+                                self.url.fragment = Some("".to_owned());
+                                self.state = State::Fragment;
+                            }
+                            Some(_) => {
+                                self.url.query = None;
+                                self.shorten_path();
+                                self.state = State::Path;
+                                self.pointer -= 1;
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                State::RelativeSlash => {
+                    if self.url.is_special() && matches!(c, Some('/' | '\\')) {
+                        self.state = State::SpecialAuthorityIgnoreSlashes;
+                    } else if c == Some('/') {
+                        self.state = State::Authority;
+                    } else {
+                        let base = self.base.expect("relative slash state with no base");
+                        self.url.username = base.username.clone();
+                        self.url.password = base.password.clone();
+                        self.url.host = base.host.clone();
+                        self.url.port = base.port;
+                        self.state = State::Path;
+                        self.pointer -= 1;
+                    }
+                }
+                State::SpecialAuthoritySlashes => {
+                    if c == Some('/') && self.remaining_starts_with('/') {
+                        self.pointer += 1;
+                    } else {
+                        self.pointer -= 1;
+                    }
+                    self.state = State::SpecialAuthorityIgnoreSlashes;
+                }
+                State::SpecialAuthorityIgnoreSlashes => {
+                    if !matches!(c, Some('/' | '\\')) {
+                        self.state = State::Authority;
+                        self.pointer -= 1;
+                    }
+                }
+                State::Authority => match c {
+                    Some('@') => {
+                        if self.at_sign_seen {
+                            self.buffer.insert_str(0, "%40");
+                        }
+                        self.at_sign_seen = true;
+                        for c in std::mem::take(&mut self.buffer).chars() {
+                            if c == ':' && !self.password_token_seen {
+                                self.password_token_seen = true;
+                                continue;
+                            }
+                            if self.password_token_seen {
+                                percent_encode(&mut self.url.password, c, userinfo_set);
+                            } else {
+                                percent_encode(&mut self.url.username, c, userinfo_set);
+                            }
+                        }
+                    }
+                    None | Some('/' | '?' | '#') => {
+                        if self.at_sign_seen && self.buffer.is_empty() {
+                            return Err("credentials with no host");
+                        }
+                        self.pointer -= self.buffer.chars().count() as isize + 1;
+                        self.buffer.clear();
+                        self.state = State::Host;
+                    }
+                    Some('\\') if self.url.is_special() => {
+                        if self.at_sign_seen && self.buffer.is_empty() {
+                            return Err("credentials with no host");
+                        }
+                        self.pointer -= self.buffer.chars().count() as isize + 1;
+                        self.buffer.clear();
+                        self.state = State::Host;
+                    }
+                    Some(c) => self.buffer.push(c),
+                },
+                State::Host => match c {
+                    Some(':') if !self.inside_brackets => {
+                        if self.buffer.is_empty() {
+                            return Err("port with no host");
+                        }
+                        let host = parse_host(&self.buffer, !self.url.is_special())?;
+                        self.url.host = Some(host);
+                        self.buffer.clear();
+                        self.state = State::Port;
+                    }
+                    None | Some('/' | '?' | '#') => {
+                        self.end_host()?;
+                    }
+                    Some('\\') if self.url.is_special() => {
+                        self.end_host()?;
+                    }
+                    Some(c) => {
+                        if c == '[' {
+                            self.inside_brackets = true;
+                        }
+                        if c == ']' {
+                            self.inside_brackets = false;
+                        }
+                        self.buffer.push(c);
+                    }
+                },
+                State::Port => match c {
+                    Some(c) if c.is_ascii_digit() => self.buffer.push(c),
+                    None | Some('/' | '?' | '#') => self.end_port()?,
+                    Some('\\') if self.url.is_special() => self.end_port()?,
+                    Some(_) => return Err("invalid port"),
+                },
+                State::File => {
+                    self.url.scheme = "file".to_owned();
+                    self.url.host = Some(Host::Empty);
+                    if matches!(c, Some('/' | '\\')) {
+                        self.state = State::FileSlash;
+                    } else if let Some(base) = self.base.filter(|x| x.scheme == "file") {
+                        self.url.host = base.host.clone();
+                        self.url.path = base.path.clone();
+                        self.url.query = base.query.clone();
+                        match c {
+                            Some('?') => {
+                                self.url.query = Some("".to_owned());
+                                self.state = State::Query;
+                            }
+                            Some('#') => {
+                                self.url.fragment = Some("".to_owned());
+                                self.state = State::Fragment;
+                            }
+                            Some(_) => {
+                                self.url.query = None;
+                                let rest = &self.input[self.pointer as usize..];
+                                if !starts_with_windows_drive_letter(rest) {
+                                    self.shorten_path();
+                                } else {
+                                    self.url.path = UrlPath::Segments(vec![]);
+                                }
+                                self.state = State::Path;
+                                self.pointer -= 1;
+                            }
+                            None => {}
+                        }
+                    } else {
+                        self.state = State::Path;
+                        self.pointer -= 1;
+                    }
+                }
+                State::FileSlash => {
+                    if matches!(c, Some('/' | '\\')) {
+                        self.state = State::FileHost;
+                    } else {
+                        if let Some(base) = self.base.filter(|x| x.scheme == "file") {
+                            self.url.host = base.host.clone();
+                            let rest = self.input.get(self.pointer as usize..).unwrap_or(&[]);
+                            if let UrlPath::Segments(base_segments) = &base.path {
+                                if !starts_with_windows_drive_letter(rest)
+                                    && base_segments
+                                        .first()
+                                        .is_some_and(|x| is_normalized_windows_drive_letter(x))
+                                {
+                                    let first = base_segments[0].clone();
+                                    self.segments().push(first);
+                                }
+                            }
+                        }
+                        self.state = State::Path;
+                        self.pointer -= 1;
+                    }
+                }
+                State::FileHost => match c {
+                    None | Some('/' | '\\' | '?' | '#') => {
+                        self.pointer -= 1;
+                        if is_windows_drive_letter(&self.buffer) {
+                            // buffer is deliberately not reset, but used in the path state
+                            self.state = State::Path;
+                        } else if self.buffer.is_empty() {
+                            self.url.host = Some(Host::Empty);
+                            self.state = State::PathStart;
+                        } else {
+                            let mut host = parse_host(&self.buffer, false)?;
+                            if host == Host::Domain("localhost".to_owned()) {
+                                host = Host::Empty;
+                            }
+                            self.url.host = Some(host);
+                            self.buffer.clear();
+                            self.state = State::PathStart;
+                        }
+                    }
+                    Some(c) => self.buffer.push(c),
+                },
+                State::PathStart => {
+                    if self.url.is_special() {
+                        self.state = State::Path;
+                        if !matches!(c, Some('/' | '\\')) {
+                            self.pointer -= 1;
+                        }
+                    } else if c == Some('?') {
+                        self.url.query = Some("".to_owned());
+                        self.state = State::Query;
+                    } else if c == Some('#') {
+                        self.url.fragment = Some("".to_owned());
+                        self.state = State::Fragment;
+                    } else if c.is_some() {
+                        self.state = State::Path;
+                        if c != Some('/') {
+                            self.pointer -= 1;
+                        }
+                    }
+                }
+                State::Path => {
+                    let slash = c == Some('/') || self.url.is_special() && c == Some('\\');
+                    if c.is_none() || slash || matches!(c, Some('?' | '#')) {
+                        let buffer = std::mem::take(&mut self.buffer);
+                        if is_double_dot_segment(&buffer) {
+                            self.shorten_path();
+                            if !slash {
+                                self.segments().push("".to_owned());
+                            }
+                        } else if is_single_dot_segment(&buffer) {
+                            if !slash {
+                                self.segments().push("".to_owned());
+                            }
+                        } else {
+                            let mut buffer = buffer;
+                            if self.url.scheme == "file"
+                                && self.segments().is_empty()
+                                && is_windows_drive_letter(&buffer)
+                            {
+                                buffer.replace_range(1..2, ":");
+                            }
+                            self.segments().push(buffer);
+                        }
+                        match c {
+                            Some('?') => {
+                                self.url.query = Some("".to_owned());
+                                self.state = State::Query;
+                            }
+                            Some('#') => {
+                                self.url.fragment = Some("".to_owned());
+                                self.state = State::Fragment;
+                            }
+                            _ => {}
+                        }
+                    } else if let Some(c) = c {
+                        percent_encode(&mut self.buffer, c, path_set);
+                    }
+                }
+                State::OpaquePath => match c {
+                    Some('?') => {
+                        self.url.query = Some("".to_owned());
+                        self.state = State::Query;
+                    }
+                    Some('#') => {
+                        self.url.fragment = Some("".to_owned());
+                        self.state = State::Fragment;
+                    }
+                    Some(c) => {
+                        if let UrlPath::Opaque(path) = &mut self.url.path {
+                            percent_encode(path, c, c0_control_set);
+                        }
+                    }
+                    None => {}
+                },
+                State::Query => match c {
+                    None | Some('#') => {
+                        let set = match self.url.is_special() {
+                            true => special_query_set,
+                            false => query_set,
+                        };
+                        let query = self.url.query.get_or_insert_with(String::new);
+                        for c in std::mem::take(&mut self.buffer).chars() {
+                            percent_encode(query, c, set);
+                        }
+                        if c == Some('#') {
+                            self.url.fragment = Some("".to_owned());
+                            self.state = State::Fragment;
+                        }
+                    }
+                    Some(c) => self.buffer.push(c),
+                },
+                State::Fragment => {
+                    if let Some(c) = c {
+                        let fragment = self.url.fragment.get_or_insert_with(String::new);
+                        percent_encode(fragment, c, fragment_set);
+                    }
+                }
+            }
+
+            if self.pointer >= self.input.len() as isize {
+                break;
+            }
+            self.pointer += 1;
+        }
+
+        Ok(self.url)
+    }
+
+    fn end_host(&mut self) -> Result<(), &'static str> {
+        self.pointer -= 1;
+        if self.url.is_special() && self.buffer.is_empty() {
+            return Err("special url with empty host");
+        }
+        let host = parse_host(&self.buffer, !self.url.is_special())?;
+        self.url.host = Some(host);
+        self.buffer.clear();
+        self.state = State::PathStart;
+
+        Ok(())
+    }
+
+    fn end_port(&mut self) -> Result<(), &'static str> {
+        if !self.buffer.is_empty() {
+            let port = self
+                .buffer
+                .parse::<u16>()
+                .map_err(|_| "port out of range")?;
+            self.url.port = Some(port).filter(|x| Some(*x) != default_port(&self.url.scheme));
+            self.buffer.clear();
+        }
+        self.state = State::PathStart;
+        self.pointer -= 1;
+
+        Ok(())
+    }
+}
+
+pub fn is_special(scheme: &str) -> bool {
+    SPECIAL_SCHEMES.iter().any(|(x, _)| *x == scheme)
+}
+
+pub fn default_port(scheme: &str) -> Option<u16> {
+    SPECIAL_SCHEMES
+        .iter()
+        .find(|(x, _)| *x == scheme)
+        .and_then(|(_, port)| *port)
+}
+
+/// <https://url.spec.whatwg.org/#windows-drive-letter>
+fn is_windows_drive_letter(input: &str) -> bool {
+    let mut chars = input.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(a), Some(':' | '|'), None) => a.is_ascii_alphabetic(),
+        _ => false,
+    }
+}
+
+fn is_normalized_windows_drive_letter(input: &str) -> bool {
+    is_windows_drive_letter(input) && input.ends_with(':')
+}
+
+/// <https://url.spec.whatwg.org/#start-with-a-windows-drive-letter>
+fn starts_with_windows_drive_letter(input: &[char]) -> bool {
+    match input {
+        [a, ':' | '|'] | [a, ':' | '|', '/' | '\\' | '?' | '#', ..] => a.is_ascii_alphabetic(),
+        _ => false,
+    }
+}
+
+fn is_single_dot_segment(input: &str) -> bool {
+    input == "." || input.eq_ignore_ascii_case("%2e")
+}
+
+fn is_double_dot_segment(input: &str) -> bool {
+    matches!(
+        &*input.to_ascii_lowercase(),
+        ".." | ".%2e" | "%2e." | "%2e%2e"
+    )
+}
+
+/// <https://url.spec.whatwg.org/#c0-control-percent-encode-set>
+fn c0_control_set(c: char) -> bool {
+    c <= '\x1F' || c > '~'
+}
+
+fn fragment_set(c: char) -> bool {
+    c0_control_set(c) || matches!(c, ' ' | '"' | '<' | '>' | '`')
+}
+
+fn query_set(c: char) -> bool {
+    c0_control_set(c) || matches!(c, ' ' | '"' | '#' | '<' | '>')
+}
+
+fn special_query_set(c: char) -> bool {
+    query_set(c) || c == '\''
+}
+
+fn path_set(c: char) -> bool {
+    query_set(c) || matches!(c, '?' | '`' | '{' | '}')
+}
+
+fn userinfo_set(c: char) -> bool {
+    path_set(c)
+        || matches!(
+            c,
+            '/' | ':' | ';' | '=' | '@' | '[' | '\\' | ']' | '^' | '|'
+        )
+}
+
+/// percent-encodes a literal path segment, like a file name, so that any
+/// `%`, `#`, `?`, or slash stays part of the segment.
+pub(crate) fn percent_encode_path_segment(input: &str) -> String {
+    let mut result = String::new();
+    for c in input.chars() {
+        percent_encode(&mut result, c, |c| {
+            path_set(c) || matches!(c, '%' | '/' | '\\' | '&')
+        });
+    }
+
+    result
+}
+
+/// <https://url.spec.whatwg.org/#utf-8-percent-encode>
+fn percent_encode(output: &mut String, c: char, set: fn(char) -> bool) {
+    if !set(c) {
+        output.push(c);
+        return;
+    }
+    for octet in c.encode_utf8(&mut [0; 4]).bytes() {
+        write!(output, "%{:02X}", octet).unwrap();
+    }
+}
+
+/// <https://url.spec.whatwg.org/#concept-host-parser>
+fn parse_host(input: &str, is_not_special: bool) -> Result<Host, &'static str> {
+    if let Some(input) = input.strip_prefix('[') {
+        let Some(input) = input.strip_suffix(']') else { return Err("unterminated ipv6 address") };
+        return parse_ipv6(input).map(Host::Ipv6);
+    }
+
+    if is_not_special {
+        // https://url.spec.whatwg.org/#concept-opaque-host-parser
+        if input.contains(is_forbidden_host_code_point) {
+            return Err("forbidden host code point");
+        }
+        let mut result = String::new();
+        for c in input.chars() {
+            percent_encode(&mut result, c, c0_control_set);
+        }
+
+        return Ok(Host::Opaque(result));
+    }
+
+    let domain = String::from_utf8_lossy(&percent_decode(input)).into_owned();
+    let Ok(ascii_domain) = idna::domain_to_ascii(&domain) else { return Err("invalid domain") };
+    if ascii_domain.is_empty() {
+        return Err("empty host");
+    }
+    if ascii_domain.contains(is_forbidden_domain_code_point) {
+        return Err("forbidden domain code point");
+    }
+    if ends_in_a_number(&ascii_domain) {
+        return parse_ipv4(&ascii_domain).map(Host::Ipv4);
+    }
+
+    Ok(Host::Domain(ascii_domain))
+}
+
+/// <https://url.spec.whatwg.org/#forbidden-host-code-point>
+fn is_forbidden_host_code_point(c: char) -> bool {
+    matches!(
+        c,
+        '\0' | '\t'
+            | '\n'
+            | '\r'
+            | ' '
+            | '#'
+            | '/'
+            | ':'
+            | '<'
+            | '>'
+            | '?'
+            | '@'
+            | '['
+            | '\\'
+            | ']'
+            | '^'
+            | '|'
+    )
+}
+
+fn is_forbidden_domain_code_point(c: char) -> bool {
+    is_forbidden_host_code_point(c) || c <= '\x1F' || c == '%' || c == '\x7F'
+}
+
+/// <https://url.spec.whatwg.org/#ends-in-a-number-checker>
+fn ends_in_a_number(input: &str) -> bool {
+    let mut parts = input.split('.').collect::<Vec<_>>();
+    if parts.last() == Some(&"") {
+        if parts.len() == 1 {
+            return false;
+        }
+        parts.pop();
+    }
+    let last = parts.last().unwrap();
+    if !last.is_empty() && last.bytes().all(|x| x.is_ascii_digit()) {
+        return true;
+    }
+
+    parse_ipv4_number(last).is_ok()
+}
+
+/// <https://url.spec.whatwg.org/#concept-ipv4-parser>
+fn parse_ipv4(input: &str) -> Result<u32, &'static str> {
+    let mut parts = input.split('.').collect::<Vec<_>>();
+    if parts.last() == Some(&"") && parts.len() > 1 {
+        parts.pop();
+    }
+    if parts.len() > 4 {
+        return Err("too many ipv4 parts");
+    }
+    let numbers = parts
+        .into_iter()
+        .map(parse_ipv4_number)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (last, rest) = numbers.split_last().unwrap();
+    if rest.iter().any(|x| *x > 255) {
+        return Err("ipv4 part out of range");
+    }
+    if *last >= 256u64.pow(5 - numbers.len() as u32) {
+        return Err("ipv4 address out of range");
+    }
+    let mut result = *last;
+    for (i, n) in rest.iter().enumerate() {
+        result += n * 256u64.pow(3 - i as u32);
+    }
+
+    Ok(result as u32)
+}
+
+/// <https://url.spec.whatwg.org/#ipv4-number-parser>
+fn parse_ipv4_number(mut input: &str) -> Result<u64, &'static str> {
+    if input.is_empty() {
+        return Err("empty ipv4 part");
+    }
+    let mut radix = 10;
+    if let Some(rest) = input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+    {
+        input = rest;
+        radix = 16;
+    } else if input.len() >= 2 && input.starts_with('0') {
+        input = &input[1..];
+        radix = 8;
+    }
+    if input.is_empty() {
+        return Ok(0);
+    }
+    if !input.chars().all(|x| x.is_digit(radix)) {
+        return Err("invalid ipv4 part");
+    }
+
+    // saturate, since anything that big will be out of range anyway
+    Ok(u64::from_str_radix(input, radix).unwrap_or(u64::MAX))
+}
+
+/// <https://url.spec.whatwg.org/#concept-ipv6-parser>
+fn parse_ipv6(input: &str) -> Result<[u16; 8], &'static str> {
+    let input = input.chars().collect::<Vec<_>>();
+    let mut address = [0u16; 8];
+    let mut piece_index = 0;
+    let mut compress = None;
+    let mut pointer = 0;
+    let c = |pointer: usize| input.get(pointer).copied();
+
+    if c(pointer) == Some(':') {
+        if c(pointer + 1) != Some(':') {
+            return Err("ipv6 address starts with single colon");
+        }
+        pointer += 2;
+        piece_index += 1;
+        compress = Some(piece_index);
+    }
+    while c(pointer).is_some() {
+        if piece_index == 8 {
+            return Err("too many ipv6 pieces");
+        }
+        if c(pointer) == Some(':') {
+            if compress.is_some() {
+                return Err("multiple ipv6 compressions");
+            }
+            pointer += 1;
+            piece_index += 1;
+            compress = Some(piece_index);
+            continue;
+        }
+        let mut value = 0u16;
+        let mut length = 0;
+        while length < 4 {
+            let Some(digit) = c(pointer).and_then(|x| x.to_digit(16)) else { break };
+            value = value * 0x10 + digit as u16;
+            pointer += 1;
+            length += 1;
+        }
+        match c(pointer) {
+            Some('.') => {
+                if length == 0 {
+                    return Err("ipv4 in ipv6 with empty part");
+                }
+                pointer -= length;
+                if piece_index > 6 {
+                    return Err("ipv4 in ipv6 with too many pieces");
+                }
+                let mut numbers_seen = 0;
+                while c(pointer).is_some() {
+                    let mut ipv4_piece: Option<u16> = None;
+                    if numbers_seen > 0 {
+                        if c(pointer) == Some('.') && numbers_seen < 4 {
+                            pointer += 1;
+                        } else {
+                            return Err("invalid ipv4 in ipv6");
+                        }
+                    }
+                    if !c(pointer).is_some_and(|x| x.is_ascii_digit()) {
+                        return Err("invalid ipv4 in ipv6");
+                    }
+                    while let Some(number) = c(pointer).and_then(|x| x.to_digit(10)) {
+                        ipv4_piece = match ipv4_piece {
+                            None => Some(number as u16),
+                            Some(0) => return Err("leading zero in ipv4 in ipv6"),
+                            Some(piece) => Some(piece * 10 + number as u16),
+                        };
+                        if ipv4_piece > Some(255) {
+                            return Err("ipv4 in ipv6 part out of range");
+                        }
+                        pointer += 1;
+                    }
+                    address[piece_index] = address[piece_index] * 0x100 + ipv4_piece.unwrap();
+                    numbers_seen += 1;
+                    if numbers_seen == 2 || numbers_seen == 4 {
+                        piece_index += 1;
+                    }
+                }
+                if numbers_seen != 4 {
+                    return Err("ipv4 in ipv6 with too few parts");
+                }
+                break;
+            }
+            Some(':') => {
+                pointer += 1;
+                if c(pointer).is_none() {
+                    return Err("ipv6 address ends with single colon");
+                }
+            }
+            Some(_) => return Err("invalid ipv6 code point"),
+            None => {}
+        }
+        address[piece_index] = value;
+        piece_index += 1;
+    }
+    if let Some(compress) = compress {
+        let mut swaps = piece_index - compress;
+        piece_index = 7;
+        while piece_index != 0 && swaps > 0 {
+            address.swap(piece_index, compress + swaps - 1);
+            piece_index -= 1;
+            swaps -= 1;
+        }
+    } else if piece_index != 8 {
+        return Err("too few ipv6 pieces");
+    }
+
+    Ok(address)
+}
+
+#[test]
+fn test_urltestdata() {
+    use serde_json::Value;
+
+    // domain to ascii is delegated to the idna crate, which disagrees with uts #46 here
+    const IDNA_SKIP_INPUTS: &[&str] = &[
+        "http://www.foo。bar.com",
+        "http://Ｇｏ.com",
+        "http://你好你好",
+        "https://faß.ExAmPlE/",
+        "http://０Ｘｃ０．０２５０．０１",
+        "ftp://%e2%98%83",
+        "https://%e2%98%83",
+        "file://a\u{ad}b/p",
+        "file://a%C2%ADb/p",
+        "http://GOO\u{200b}\u{2060}\u{feff}goo.com",
+    ];
+
+    // https://github.com/web-platform-tests/wpt/blob/master/url/resources/urltestdata.json
+    let tests: Vec<Value> =
+        serde_json::from_str(include_str!("../tests/urltestdata.json")).unwrap();
+    let mut failures = vec![];
+    for test in tests.iter().filter(|x| x.is_object()) {
+        let input = test["input"].as_str().unwrap();
+        if IDNA_SKIP_INPUTS.contains(&input) {
+            continue;
+        }
+        let base = match test["base"].as_str().map(|x| Url::parse(x, None)) {
+            Some(Ok(base)) => Some(base),
+            Some(Err(error)) => {
+                failures.push(format!("{:?}: failed to parse base: {}", input, error));
+                continue;
+            }
+            None => None,
+        };
+        let expected_failure = test.get("failure").is_some();
+        let url = match (Url::parse(input, base.as_ref()), expected_failure) {
+            (Ok(url), false) => url,
+            (Err(_), true) => continue,
+            (Ok(url), true) => {
+                failures.push(format!("{:?}: expected failure, got {}", input, url));
+                continue;
+            }
+            (Err(error), false) => {
+                failures.push(format!("{:?}: unexpected {}", input, error));
+                continue;
+            }
+        };
+        let port = url.port().map_or("".to_owned(), |x| x.to_string());
+        for (key, actual) in [
+            ("href", url.href()),
+            ("protocol", url.protocol()),
+            ("username", url.username().to_owned()),
+            ("password", url.password().to_owned()),
+            ("host", url.host_and_port()),
+            ("hostname", url.hostname()),
+            ("port", port),
+            ("pathname", url.pathname()),
+            ("search", url.search()),
+            ("hash", url.hash()),
+            ("origin", url.origin()),
+        ] {
+            if let Some(expected) = test.get(key).and_then(|x| x.as_str()) {
+                if actual != expected {
+                    failures.push(format!(
+                        "{:?}: {} was {:?}, expected {:?}",
+                        input, key, actual, expected
+                    ));
+                }
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
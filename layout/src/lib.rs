@@ -21,13 +21,17 @@ use owning_ref::{RwLockReadGuardRef, RwLockWriteGuardRefMut};
 use tracing::{debug, instrument, trace, warn};
 use unicode_segmentation::UnicodeSegmentation;
 
-use wbe_core::{dump_backtrace, FONTS};
+use wbe_core::{dump_backtrace, CancellationToken, FONTS};
 use wbe_dom::{
+    form::{self, ControlType},
     style::{CssDisplay, CssFontStyle, CssFontWeight, CssQuad, CssTextAlign},
     Node, NodeType, Style,
 };
 use wbe_html_lexer::{html_word, HtmlWord};
 
+/// space between a form control’s border and its contents.
+const CONTROL_PADDING: f32 = 4.0;
+
 pub type LayoutRead<'n, T> = RwLockReadGuardRef<'n, OwnedLayout, T>;
 pub type LayoutWrite<'n, T> = RwLockWriteGuardRefMut<'n, OwnedLayout, T>;
 
@@ -47,9 +51,10 @@ pub struct OwnedLayout {
     text_align: CssTextAlign,
 }
 
-struct DocumentContext<'v, 'p> {
+struct DocumentContext<'v, 'p, 'c> {
     viewport: &'v ViewportInfo,
     display_list: &'p mut Vec<Paint>,
+    cancellation: &'c CancellationToken,
 }
 
 #[derive(Debug)]
@@ -203,8 +208,12 @@ impl Layout {
         self.read().map(|x| &*x.display_list)
     }
 
-    #[instrument(skip(viewport))]
-    pub fn layout(&self, viewport: &ViewportInfo) -> eyre::Result<()> {
+    #[instrument(skip(viewport, cancellation))]
+    pub fn layout(
+        &self,
+        viewport: &ViewportInfo,
+        cancellation: &CancellationToken,
+    ) -> eyre::Result<()> {
         assert_eq!(self.inlines().len(), 0);
         assert_eq!(self.node().unwrap().r#type(), NodeType::Document);
 
@@ -212,6 +221,7 @@ impl Layout {
         let mut dc = DocumentContext {
             viewport,
             display_list: &mut display_list,
+            cancellation,
         };
 
         self.write().rect =
@@ -223,6 +233,7 @@ impl Layout {
     }
 
     fn f(&self, dc: &mut DocumentContext) -> eyre::Result<()> {
+        dc.cancellation.check()?;
         // trace!(mode = ?self.mode(), node = %*self.node().data());
 
         // save where we started, for background paint
@@ -445,6 +456,9 @@ impl Layout {
         match node.r#type() {
             NodeType::Document => unreachable!(),
             NodeType::Element => {
+                if let Some(r#type) = ControlType::of(&node) {
+                    return self.control(node, r#type, dc, ic);
+                }
                 if &*node.name() == "br" {
                     self.flush(dc, ic)?;
                 }
@@ -470,22 +484,7 @@ impl Layout {
         assert_eq!(node.r#type(), NodeType::Text);
         let style = node.data().style();
         let font_size = style.font_size();
-        let font = FontInfo::new(
-            FontFamily::Name(match (style.font_weight(), style.font_style()) {
-                (CssFontWeight::Normal, CssFontStyle::Normal) => FONTS[0].0.into(),
-                (CssFontWeight::Bold, CssFontStyle::Normal) => FONTS[1].0.into(),
-                (CssFontWeight::Normal, CssFontStyle::Italic) => FONTS[2].0.into(),
-                (CssFontWeight::Bold, CssFontStyle::Italic) => FONTS[3].0.into(),
-            }),
-            match (style.font_weight(), style.font_style()) {
-                (CssFontWeight::Normal, CssFontStyle::Normal) => FONTS[0].1,
-                (CssFontWeight::Bold, CssFontStyle::Normal) => FONTS[1].1,
-                (CssFontWeight::Normal, CssFontStyle::Italic) => FONTS[2].1,
-                (CssFontWeight::Bold, CssFontStyle::Italic) => FONTS[3].1,
-            },
-            font_size,
-            dc.viewport.scale,
-        )?;
+        let font = Self::font(&style, dc)?;
         let rect = self.read().rect;
 
         let mut input = &*node.value().unwrap();
@@ -500,11 +499,7 @@ impl Layout {
                 HtmlWord::Other(x) => x,
             };
             for word in text.split_word_bounds() {
-                let advance = word
-                    .chars()
-                    .map(|c| font.ab.h_advance(font.ab.glyph_id(c)))
-                    .sum::<f32>()
-                    / dc.viewport.scale;
+                let advance = Self::advance(&font, word, dc);
                 let ascent = font.ab.ascent() / dc.viewport.scale;
                 let height = font.ab.height() / dc.viewport.scale;
                 let line_height = style.line_height().resolve(font_size);
@@ -531,6 +526,85 @@ impl Layout {
         Ok(())
     }
 
+    /// lays out a form control as one unbreakable box, sized for its
+    /// contents, like an inline-block.
+    fn control(
+        &self,
+        node: Node,
+        r#type: ControlType,
+        dc: &mut DocumentContext,
+        ic: &mut InlineContext,
+    ) -> eyre::Result<()> {
+        let style = node.data().style();
+        let font = Self::font(&style, dc)?;
+        let line_height = style.line_height().resolve(style.font_size());
+        let char_width = Self::advance(&font, "0", dc);
+        let attr = |name: &str, default: usize| {
+            node.attr(name)
+                .and_then(|x| x.parse::<usize>().ok())
+                .filter(|x| *x > 0)
+                .unwrap_or(default)
+        };
+        let (width, height) = match r#type {
+            ControlType::Hidden => return Ok(()),
+            ControlType::Checkbox | ControlType::Radio => (line_height, line_height),
+            ControlType::Text | ControlType::Password => {
+                (attr("size", 20) as f32 * char_width, line_height)
+            }
+            ControlType::TextArea => (
+                attr("cols", 20) as f32 * char_width,
+                attr("rows", 2) as f32 * line_height,
+            ),
+            ControlType::Select => {
+                // wide enough for any option, plus the arrow
+                let widest = form::options(&node)
+                    .iter()
+                    .map(|x| Self::advance(&font, &form::option_label(x), dc))
+                    .fold(0.0, f32::max);
+                (widest + line_height, line_height)
+            }
+            _ => (Self::advance(&font, &form::label(&node), dc), line_height),
+        };
+        let size = vec2(width, height) + vec2(CONTROL_PADDING, CONTROL_PADDING) * 2.0;
+
+        if ic.cursor.x + size.x > self.read().rect.max.x {
+            self.flush(dc, ic)?;
+        }
+        ic.max_height = ic.max_height.max(size.y);
+        ic.line_display_list
+            .push(Paint::Control(Rect::from_min_size(ic.cursor, size), node));
+        ic.cursor.x += size.x;
+
+        Ok(())
+    }
+
+    fn font(style: &Style, dc: &DocumentContext) -> eyre::Result<FontInfo> {
+        FontInfo::new(
+            FontFamily::Name(match (style.font_weight(), style.font_style()) {
+                (CssFontWeight::Normal, CssFontStyle::Normal) => FONTS[0].0.into(),
+                (CssFontWeight::Bold, CssFontStyle::Normal) => FONTS[1].0.into(),
+                (CssFontWeight::Normal, CssFontStyle::Italic) => FONTS[2].0.into(),
+                (CssFontWeight::Bold, CssFontStyle::Italic) => FONTS[3].0.into(),
+            }),
+            match (style.font_weight(), style.font_style()) {
+                (CssFontWeight::Normal, CssFontStyle::Normal) => FONTS[0].1,
+                (CssFontWeight::Bold, CssFontStyle::Normal) => FONTS[1].1,
+                (CssFontWeight::Normal, CssFontStyle::Italic) => FONTS[2].1,
+                (CssFontWeight::Bold, CssFontStyle::Italic) => FONTS[3].1,
+            },
+            style.font_size(),
+            dc.viewport.scale,
+        )
+    }
+
+    /// how far the given text advances the cursor, in points.
+    fn advance(font: &FontInfo, text: &str, dc: &DocumentContext) -> f32 {
+        text.chars()
+            .map(|c| font.ab.h_advance(font.ab.glyph_id(c)))
+            .sum::<f32>()
+            / dc.viewport.scale
+    }
+
     fn flush(&self, dc: &mut DocumentContext, ic: &mut InlineContext) -> eyre::Result<()> {
         // move text paints for ‘vertical-align’
         for text in &mut ic.line_display_list[..] {
@@ -541,6 +615,8 @@ impl Layout {
                         ic.max_ascent - font.ab.ascent() / dc.viewport.scale,
                     ));
                 }
+                // controls stay at the top of the line
+                Paint::Control(..) => {}
                 _ => unreachable!(),
             }
         }
@@ -559,7 +635,7 @@ impl Layout {
         };
         for text in &mut ic.line_display_list[..] {
             match text {
-                Paint::Text(rect, _, _, _) => {
+                Paint::Text(rect, _, _, _) | Paint::Control(rect, _) => {
                     *rect = rect.translate(vec2(offset, 0.0));
                 }
                 _ => unreachable!(),
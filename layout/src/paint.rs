@@ -8,6 +8,8 @@ use crate::*;
 pub enum Paint {
     Text(Rect, Color32, font::FontInfo, String),
     Fill(Rect, Color32),
+    /// a form control, which the browser draws with a widget.
+    Control(Rect, Node),
 }
 
 impl Paint {
@@ -15,6 +17,7 @@ impl Paint {
         match self {
             Paint::Text(rect, _, _, _) => rect,
             Paint::Fill(rect, _) => rect,
+            Paint::Control(rect, _) => rect,
         }
     }
 }
@@ -1,26 +1,56 @@
-use std::env::args;
+use std::env::{self, args};
+use std::fs;
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use egui::{
     vec2, Align, Color32, Context, FontData, FontDefinitions, FontFamily, Frame, Rect, TextEdit,
 };
-use tracing::{error, instrument, trace, warn};
+use tracing::{debug, error, instrument, trace, warn};
 
 use wbe_browser::{Browser, Document, OwnedBrowser, OwnedDocument, RenderStatus};
-use wbe_core::FONTS;
+use wbe_core::{CancellationToken, Cancelled, FONTS};
+use wbe_http::{Proxies, Request, Settings, SocketFetcher};
 use wbe_layout::ViewportInfo;
 
+/// how often we show the document so far while it’s streaming in.
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(250);
+
 fn main() -> eyre::Result<()> {
     // log to stdout (level configurable by RUST_LOG=debug)
     tracing_subscriber::fmt::init();
 
+    if let Ok(path) = env::var("WBE_CACHE_PATH") {
+        wbe_http::CACHE.set_directory(Some(path.into()));
+    }
+    if let Ok(path) = env::var("WBE_HSTS_PATH") {
+        wbe_http::HSTS.set_path(Some(path.into()));
+    }
+    if let Ok(path) = env::var("WBE_HSTS_PRELOAD_PATH") {
+        match fs::read_to_string(&path) {
+            Ok(list) => wbe_http::HSTS.preload(&list),
+            Err(error) => warn!(path, %error, "failed to read hsts preload list"),
+        }
+    }
+
     let location = args()
         .nth(1)
         .unwrap_or("http://example.org/index.html".to_owned());
 
+    let mut settings = Settings::default();
+    if let Ok(user_agent) = env::var("WBE_USER_AGENT") {
+        settings.user_agent = user_agent;
+    }
+    if let Ok(accept_language) = env::var("WBE_ACCEPT_LANGUAGE") {
+        settings.accept_language = accept_language;
+    }
+    settings.proxies = Proxies::from_env();
+
     let browser = Browser::wrap(OwnedBrowser {
         location,
+        fetcher: Arc::new(SocketFetcher::new(settings)),
         ..Default::default()
     });
 
@@ -40,6 +70,11 @@ fn main() -> eyre::Result<()> {
             continue;
         }
 
+        // a newer navigation has already replaced this one
+        if request.cancellation.is_cancelled() {
+            continue;
+        }
+
         let mut next_document = browser.read().next_document.write().take();
         if matches!(next_document, OwnedDocument::None) {
             warn!("renderer received render request, but there was no next_document");
@@ -49,15 +84,58 @@ fn main() -> eyre::Result<()> {
         browser.set_status(RenderStatus::Load);
         request.egui_ctx.request_repaint();
 
+        let fetcher = browser.read().fetcher.clone();
+        let mut last_preview = Instant::now();
+        // whether the location bar has the post-redirect url yet
+        let mut location_shown = false;
         loop {
             next_document = match next_document {
                 OwnedDocument::None => break,
-                result @ OwnedDocument::Navigated { .. } => {
+                result @ (OwnedDocument::Navigated { .. } | OwnedDocument::Submitted { .. }) => {
                     browser.set_status(RenderStatus::Load);
                     request.egui_ctx.request_repaint();
                     result
                 }
+                OwnedDocument::Streaming {
+                    ref location,
+                    ref stream,
+                    ..
+                } => {
+                    // reflect any redirects in the location bar, but only
+                    // once, not every chunk, or we would keep overwriting
+                    // whatever the user is typing there
+                    if !location_shown {
+                        *browser.location_mut() = location.clone();
+                        location_shown = true;
+                    }
+
+                    // show the document so far, though not too often,
+                    // since each preview styles and lays out everything
+                    if last_preview.elapsed() >= PREVIEW_INTERVAL {
+                        match next_document.preview(
+                            request.viewport.clone(),
+                            &*fetcher,
+                            &request.cancellation,
+                        ) {
+                            Ok(preview) => browser.write().document = Document::wrap(preview),
+                            Err(e) if e.is::<Cancelled>() => {
+                                debug!("render request cancelled");
+                                break;
+                            }
+                            Err(e) => error!("preview error: {}", e.to_string()),
+                        }
+                        last_preview = Instant::now();
+                    }
+                    browser.set_status(RenderStatus::Stream(stream.received()));
+                    request.egui_ctx.request_repaint();
+                    next_document
+                }
                 result @ OwnedDocument::Loaded { .. } => {
+                    // reflect any redirects in the location bar
+                    if let Some(location) = result.location().filter(|_| !location_shown) {
+                        *browser.location_mut() = location.to_owned();
+                        location_shown = true;
+                    }
                     browser.set_status(RenderStatus::Parse);
                     request.egui_ctx.request_repaint();
                     result
@@ -80,8 +158,16 @@ fn main() -> eyre::Result<()> {
                     break;
                 }
             };
-            next_document = match next_document.tick(request.viewport.clone()) {
+            next_document = match next_document.tick(
+                request.viewport.clone(),
+                &*fetcher,
+                &request.cancellation,
+            ) {
                 Ok(result) => result,
+                Err(e) if e.is::<Cancelled>() => {
+                    debug!("render request cancelled");
+                    break;
+                }
                 Err(e) => {
                     error!("error: {}", e.to_string());
                     break;
@@ -125,11 +211,14 @@ fn main() -> eyre::Result<()> {
 pub struct App {
     browser: Browser,
     render_request_tx: Sender<RenderRequest>,
+    /// for the current navigation, which the next one cancels.
+    cancellation: CancellationToken,
 }
 
 pub struct RenderRequest {
     viewport: ViewportInfo,
     egui_ctx: Context,
+    cancellation: CancellationToken,
 }
 
 impl App {
@@ -140,6 +229,7 @@ impl App {
             Self {
                 browser,
                 render_request_tx,
+                cancellation: CancellationToken::new(),
             },
             render_request_rx,
         )
@@ -148,12 +238,28 @@ impl App {
     #[instrument(skip(self))]
     fn go(&mut self, egui_ctx: Context) {
         let location = self.browser.read().location.clone();
+        self.navigate(OwnedDocument::Navigated { location }, egui_ctx);
+    }
+
+    #[instrument(skip(self))]
+    fn submit(&mut self, request: Request, egui_ctx: Context) {
+        let location = request.url.href();
+        *self.browser.location_mut() = location.clone();
+        self.navigate(OwnedDocument::Submitted { location, request }, egui_ctx);
+    }
+
+    fn navigate(&mut self, document: OwnedDocument, egui_ctx: Context) {
+        // stop any load, parse, or layout still in progress for the old location
+        self.cancellation.cancel();
+        self.cancellation = CancellationToken::new();
+
         self.browser.set_status(RenderStatus::Load);
-        *self.browser.write().next_document.write() = OwnedDocument::Navigated { location };
+        *self.browser.write().next_document.write() = document;
         self.render_request_tx
             .send(RenderRequest {
                 viewport: self.browser.read().viewport.clone(),
                 egui_ctx,
+                cancellation: self.cancellation.clone(),
             })
             .unwrap();
     }
@@ -173,10 +279,13 @@ impl eframe::App for App {
                     if status != RenderStatus::Done {
                         ui.spinner();
                         ui.label(match status {
-                            RenderStatus::Load => "load",
-                            RenderStatus::Parse => "parse",
-                            RenderStatus::Style => "style",
-                            RenderStatus::Layout => "layout",
+                            RenderStatus::Load => "load".to_owned(),
+                            RenderStatus::Stream(received) => {
+                                format!("load+parse ({} KiB)", received / 1024)
+                            }
+                            RenderStatus::Parse => "parse".to_owned(),
+                            RenderStatus::Style => "style".to_owned(),
+                            RenderStatus::Layout => "layout".to_owned(),
                             RenderStatus::Done => unreachable!(),
                         });
                     }
@@ -188,6 +297,7 @@ impl eframe::App for App {
             );
         });
 
+        let mut submission = None;
         egui::CentralPanel::default()
             .frame(Frame::none().fill(Color32::WHITE))
             .show(ctx, |ui| {
@@ -230,7 +340,11 @@ impl eframe::App for App {
                             .update(viewport_rect, ctx.pixels_per_point())
                             .clone();
                         if let OwnedDocument::LaidOut {
-                            layout, viewport, ..
+                            location,
+                            encoding,
+                            layout,
+                            viewport,
+                            ..
                         } = &*document
                         {
                             // expand scroll_rect where needed to fit page contents
@@ -239,7 +353,14 @@ impl eframe::App for App {
 
                             // paint the layout tree translated by -self.scroll (since we do the
                             // translate ourselves and not ScrollArea, it’s not cheating)
-                            OwnedDocument::paint(ui, layout, viewport, browser.scroll);
+                            submission = OwnedDocument::paint(
+                                ui,
+                                location,
+                                encoding,
+                                layout,
+                                viewport,
+                                browser.scroll,
+                            );
 
                             if *viewport != new_viewport {
                                 let has_next_document =
@@ -256,6 +377,7 @@ impl eframe::App for App {
                                     .send(RenderRequest {
                                         viewport: browser.viewport.clone(),
                                         egui_ctx: ctx.clone(),
+                                        cancellation: self.cancellation.clone(),
                                     })
                                     .unwrap();
                             }
@@ -276,6 +398,10 @@ impl eframe::App for App {
                     });
             });
 
+        if let Some(request) = submission {
+            self.submit(request, ctx.clone());
+        }
+
         // now that we have a valid viewport, go if needed
         assert!(self.browser.read().viewport.is_valid());
         let first_update = self.browser.read().first_update;
@@ -1,5 +1,6 @@
 #![feature(stmt_expr_attributes)]
 
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
 use eyre::eyre;
 use paste::paste;
 use tracing::{debug, instrument, trace, warn};
@@ -27,6 +28,44 @@ pub fn parse_css_file(text: &str) -> eyre::Result<RuleList> {
     }
 }
 
+/// decodes a stylesheet, given the charset from its content-type (if any), and the
+/// encoding of the document that linked to it.
+/// <https://drafts.csswg.org/css-syntax/#input-byte-stream>
+#[instrument(skip(body))]
+pub fn decode_css_file(
+    body: &[u8],
+    protocol_charset: Option<&str>,
+    environment_encoding: &'static Encoding,
+) -> String {
+    let encoding = Encoding::for_bom(body)
+        .map(|(x, _)| x)
+        .or_else(|| protocol_charset.and_then(|x| Encoding::for_label(x.as_bytes())))
+        .or_else(|| {
+            // @charset only counts if it’s exactly the first thing in the file
+            let rest = body.strip_prefix(b"@charset \"")?;
+            let end = rest[..rest.len().min(1024)]
+                .iter()
+                .position(|x| matches!(x, b'"' | b';'))?;
+            if rest[end..].starts_with(b"\";") {
+                Encoding::for_label(&rest[..end]).map(|x| match x {
+                    x if x == UTF_16BE || x == UTF_16LE => UTF_8,
+                    x => x,
+                })
+            } else {
+                None
+            }
+        })
+        .unwrap_or(environment_encoding);
+    debug!(encoding = encoding.name());
+
+    let (text, had_errors) = encoding.decode_with_bom_removal(body);
+    if had_errors {
+        warn!(encoding = encoding.name(), "malformed input replaced");
+    }
+
+    text.into_owned()
+}
+
 pub fn parse_style_attr(text: &str) -> eyre::Result<DeclarationList> {
     match css_declaration_list(text) {
         Ok(("", result)) => Ok(result),
@@ -399,8 +438,8 @@ fn test() -> eyre::Result<()> {
     use wbe_css_parser::CompoundSelector;
     use wbe_html_parser::parse_html;
 
-    let dom = parse_html("<html><body><p><b></b><i></i><a id=b class='c d'>x</a>")?;
-    let a = dom.children()[0].children()[0].children()[0].children()[2].clone();
+    let dom = parse_html("<html><body><p><b></b><i></i><a id=b class='c d'>x</a>", &Default::default())?;
+    let a = dom.children()[0].children()[1].children()[0].children()[2].clone();
     assert!(match_compound(&a, &compound([])));
     assert!(match_compound(&a, &compound(["*"])));
     assert!(match_compound(&a, &compound(["a"])));
@@ -430,3 +469,15 @@ fn test() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+#[rustfmt::skip]
+fn test_decode_css_file() {
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    assert_eq!(decode_css_file(b"\xEF\xBB\xBFp{}", Some("shift_jis"), WINDOWS_1252), "p{}");
+    assert_eq!(decode_css_file(b"p::after{content:'\xE9'}", Some("latin1"), UTF_8), "p::after{content:'é'}");
+    assert_eq!(decode_css_file(b"@charset \"shift_jis\";p::after{content:'\x82\xA0'}", None, WINDOWS_1252), "@charset \"shift_jis\";p::after{content:'あ'}");
+    assert_eq!(decode_css_file(b"@charset 'shift_jis';p::after{content:'\x82\xA0'}", None, WINDOWS_1252), "@charset 'shift_jis';p::after{content:'‚\u{a0}'}");
+    assert_eq!(decode_css_file(b"p::after{content:'\x82\xA0'}", None, SHIFT_JIS), "p::after{content:'あ'}");
+}